@@ -0,0 +1,208 @@
+use rodio::Source;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A region of the current track decoded for `Player::set_ab_loop`, ready to repeat
+#[derive(Clone)]
+pub(crate) struct Region {
+    pub(crate) start_ms: u64,
+    pub(crate) end_ms: u64,
+    /// Where each repeat picks up, just past `start_ms`; the crossfade covers the rest
+    pub(crate) resume_ms: u64,
+    /// One repeat, from `resume_ms` to `end_ms` with the crossfade into the start mixed into
+    /// its tail, interleaved
+    body: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Region {
+    /// Build the loop from the decoded `samples` of `start_ms..end_ms`, blending the seam
+    /// over up to `crossfade`. `None` when there isn't a whole frame to repeat.
+    pub(crate) fn new(
+        start_ms: u64,
+        end_ms: u64,
+        samples: &[f32],
+        channels: u16,
+        sample_rate: u32,
+        crossfade: Duration,
+    ) -> Option<Self> {
+        let width = channels.max(1) as usize;
+        let frames = samples.len() / width;
+        if frames == 0 {
+            return None;
+        }
+        let fade = ((crossfade.as_secs_f64() * sample_rate as f64) as usize).min(frames / 2);
+        let body: Vec<f32> = (fade..frames)
+            .flat_map(|frame| (0..width).map(move |ch| (frame, ch)))
+            .map(|(frame, ch)| {
+                let sample = samples[frame * width + ch];
+                // The last `fade` frames fade out under the first `fade` fading in
+                let Some(k) = frame.checked_sub(frames - fade) else {
+                    return sample;
+                };
+                let t = (k as f32 + 0.5) / fade as f32;
+                sample * (1.0 - t) + samples[k * width + ch] * t
+            })
+            .collect();
+        Some(Self {
+            start_ms,
+            end_ms,
+            resume_ms: start_ms + fade as u64 * 1000 / sample_rate.max(1) as u64,
+            body: body.into(),
+            channels,
+            sample_rate,
+        })
+    }
+
+    /// Play `source`, which starts `at` into the track, into the loop and around it forever
+    pub(crate) fn wrap<S>(&self, source: S, at: Duration) -> AbLoop<S>
+    where
+        S: Source<Item = f32>,
+    {
+        let width = self.channels.max(1) as usize;
+        let frames_to = |ms: u64| (ms as f64 * self.sample_rate as f64 / 1000.0) as usize;
+        let at_ms = at.as_millis() as u64;
+        // The track itself plays up to where the repeat picks up
+        let (lead_in, pos) = match self.resume_ms.checked_sub(at_ms) {
+            Some(ahead) => (frames_to(ahead) * width, 0),
+            None => (
+                0,
+                (frames_to(at_ms - self.resume_ms) * width) % self.body.len(),
+            ),
+        };
+        AbLoop {
+            lead_in: source,
+            lead_in_left: lead_in,
+            body: self.body.clone(),
+            pos,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+        }
+    }
+}
+
+/// `position_ms` on a track that has been playing around a loop from `resume_ms` back at
+/// `end_ms` since before reaching `end_ms`
+pub(crate) fn wrap_position(position_ms: u64, resume_ms: u64, end_ms: u64) -> u64 {
+    match position_ms.checked_sub(end_ms) {
+        Some(past) => resume_ms + past % end_ms.saturating_sub(resume_ms).max(1),
+        None => position_ms,
+    }
+}
+
+/// Plays the start of a track up to an A-B loop, then the loop over and over
+pub(crate) struct AbLoop<S> {
+    lead_in: S,
+    /// Samples of `lead_in` still to play
+    lead_in_left: usize,
+    body: Arc<[f32]>,
+    /// Next sample of `body`
+    pos: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl<S> Iterator for AbLoop<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.lead_in_left > 0 {
+            self.lead_in_left -= 1;
+            match self.lead_in.next() {
+                Some(sample) => return Some(sample),
+                // Ended early; the loop takes over at once
+                None => self.lead_in_left = 0,
+            }
+        }
+        let sample = self.body[self.pos];
+        self.pos = (self.pos + 1) % self.body.len();
+        Some(sample)
+    }
+}
+
+impl<S> Source for AbLoop<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// A mono ramp of 1 ms steps at 1 kHz, so each sample's value is its time in ms
+    fn ramp(from_ms: u64, to_ms: u64) -> Vec<f32> {
+        (from_ms..to_ms).map(|ms| ms as f32).collect()
+    }
+
+    #[test]
+    fn plays_into_the_loop_and_around_it() {
+        let region = Region::new(
+            100,
+            200,
+            &ramp(100, 200),
+            1,
+            1000,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        assert_eq!(region.resume_ms, 110);
+        let track = SamplesBuffer::new(1, 1000, ramp(50, 300));
+        let played: Vec<f32> = region
+            .wrap(track, Duration::from_millis(50))
+            .take(400)
+            .collect();
+
+        // Straight through the track up to where the repeat picks up
+        assert_eq!(&played[..60], &ramp(50, 110)[..]);
+        // Then the region on, with its last 10 ms blending into its first
+        assert_eq!(&played[60..140], &ramp(110, 190)[..]);
+        for k in 0..10 {
+            let t = (k as f32 + 0.5) / 10.0;
+            let expected = (190 + k) as f32 * (1.0 - t) + (100 + k) as f32 * t;
+            assert!((played[140 + k] - expected).abs() < 1e-3);
+        }
+        // And around again
+        assert_eq!(&played[150..230], &ramp(110, 190)[..]);
+    }
+
+    #[test]
+    fn joins_the_loop_part_way_round() {
+        let region = Region::new(100, 200, &ramp(100, 200), 1, 1000, Duration::ZERO).unwrap();
+        let track = SamplesBuffer::new(1, 1000, ramp(150, 300));
+        let played: Vec<f32> = region
+            .wrap(track, Duration::from_millis(150))
+            .take(60)
+            .collect();
+        assert_eq!(&played[..50], &ramp(150, 200)[..]);
+        assert_eq!(&played[50..], &ramp(100, 110)[..]);
+    }
+
+    #[test]
+    fn wraps_the_position_back_past_the_end() {
+        assert_eq!(wrap_position(150, 110, 200), 150);
+        assert_eq!(wrap_position(200, 110, 200), 110);
+        assert_eq!(wrap_position(295, 110, 200), 115);
+        assert_eq!(wrap_position(300, 110, 200), 120);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::retry::IoRetry;
+use crate::spectrum;
+use crate::stream::{SymphoniaDecoder, SymphoniaSource};
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use rodio::{Decoder, Source};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Frames per min/max pair kept while decoding for `waveform`, before merging into buckets
+const WAVEFORM_CHUNK: usize = 256;
+/// Length of the windows the signal level is measured over
+const LEVEL_WINDOW: Duration = Duration::from_millis(50);
+/// Samples per spectral window for applause detection
+const FLATNESS_WINDOW: usize = 2048;
+/// Spectral flatness above which a window sounds like broadband noise rather than music
+const APPLAUSE_FLATNESS: f32 = 0.3;
+/// Windows quieter than this (dBFS) are silence and may sit inside a run of applause
+const APPLAUSE_SILENCE_DB: f32 = -50.0;
+/// Shortest run of applause worth trimming
+const MIN_APPLAUSE: Duration = Duration::from_secs(1);
+/// Applause is only looked for this close to the start and end of a track
+const APPLAUSE_EDGE: Duration = Duration::from_secs(60);
+
+/// Tuning for `detect_track_boundaries_with`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryDetection {
+    /// Windows quieter than this (RMS, dBFS) count as silence
+    pub silence_threshold_db: f32,
+    /// Shortest run of silence that is treated as a gap between tracks
+    pub min_gap: Duration,
+}
+
+impl Default for BoundaryDetection {
+    fn default() -> Self {
+        Self {
+            silence_threshold_db: -50.0,
+            min_gap: Duration::from_millis(700),
+        }
+    }
+}
+
+/// Find likely track splits in a long single-file recording such as a DJ mix.
+///
+/// Uses the default `BoundaryDetection` settings; see `detect_track_boundaries_with`.
+pub fn detect_track_boundaries<P: AsRef<Path>>(path: P) -> Result<Vec<Duration>> {
+    detect_track_boundaries_with(path, BoundaryDetection::default())
+}
+
+/// Find likely track splits by looking for gaps of near-silence.
+///
+/// Returns the middle of every silent gap of at least `min_gap`, in order. Silence at the very
+/// start or end of the file is lead-in/run-out rather than a split and is ignored. This
+/// decodes the whole file, so call it off the UI thread.
+pub fn detect_track_boundaries_with<P: AsRef<Path>>(
+    path: P,
+    detection: BoundaryDetection,
+) -> Result<Vec<Duration>> {
+    let levels = window_levels_db(path.as_ref())?;
+    let window_secs = LEVEL_WINDOW.as_secs_f64();
+    let min_windows = (detection.min_gap.as_secs_f64() / window_secs).ceil() as usize;
+
+    let mut boundaries = Vec::new();
+    let mut gap_start = None;
+    for (i, &level) in levels.iter().enumerate() {
+        let silent = level < detection.silence_threshold_db;
+        match (silent, gap_start) {
+            (true, None) => gap_start = Some(i),
+            (false, Some(start)) => {
+                if start > 0 && i - start >= min_windows.max(1) {
+                    let middle = (start + i) as f64 / 2.0 * window_secs;
+                    boundaries.push(Duration::from_secs_f64(middle));
+                }
+                gap_start = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(boundaries)
+}
+
+/// Min/max pairs of the file downmixed to mono, in `buckets` equal slices of its length.
+///
+/// Decodes the whole file with Symphonia, keeping only a pair per `WAVEFORM_CHUNK` frames
+/// along the way, so memory stays small for long files.
+pub(crate) fn waveform(path: &Path, buckets: usize) -> Result<Vec<(f32, f32)>> {
+    let decoder = SymphoniaDecoder::open(path, IoRetry::default())?;
+    // Default settings: decoded to float
+    let src = SymphoniaSource::new(Arc::new(Mutex::new(decoder)), Arc::default());
+    let channels = src.channels().max(1) as usize;
+
+    let mut chunks = Vec::new();
+    let mut chunk = (f32::INFINITY, f32::NEG_INFINITY);
+    let mut frames = 0;
+    let mut frame_sum = 0.0f32;
+    for (i, sample) in src.enumerate() {
+        frame_sum += sample;
+        if (i + 1) % channels != 0 {
+            continue;
+        }
+        let mono = (frame_sum / channels as f32).clamp(-1.0, 1.0);
+        frame_sum = 0.0;
+        chunk = (chunk.0.min(mono), chunk.1.max(mono));
+        frames += 1;
+        if frames == WAVEFORM_CHUNK {
+            chunks.push(chunk);
+            chunk = (f32::INFINITY, f32::NEG_INFINITY);
+            frames = 0;
+        }
+    }
+    if frames > 0 {
+        chunks.push(chunk);
+    }
+    if chunks.is_empty() {
+        return Ok(vec![(0.0, 0.0); buckets]);
+    }
+
+    // With more buckets than chunks, neighbouring buckets share a chunk
+    let peaks = (0..buckets)
+        .map(|i| {
+            let start = i * chunks.len() / buckets;
+            let end = ((i + 1) * chunks.len() / buckets).max(start + 1);
+            chunks[start..end].iter().fold(
+                (f32::INFINITY, f32::NEG_INFINITY),
+                |(lo, hi), &(min, max)| (lo.min(min), hi.max(max)),
+            )
+        })
+        .collect();
+    Ok(peaks)
+}
+
+/// Where playback should start and stop to skip applause at the edges of a track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ApplauseTrim {
+    pub start: Duration,
+    /// `None` when the end of the track is kept
+    pub end: Option<Duration>,
+}
+
+/// Look for applause (broadband noise, possibly with silence) at the start and end of a track.
+///
+/// Best-effort: detection is a spectral flatness heuristic, so noisy music can be mistaken for
+/// applause and quiet applause can be missed. Decodes the whole file.
+pub(crate) fn detect_applause(path: &Path) -> Result<ApplauseTrim> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let src = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Unsupported/invalid audio: {:?}", path))?
+        .convert_samples::<f32>();
+    let channels = src.channels().max(1) as usize;
+    let window_secs = FLATNESS_WINDOW as f64 / src.sample_rate().max(1) as f64;
+
+    // Per window: Some(true) for applause, Some(false) for music, None for silence
+    let mut windows = Vec::new();
+    let mut mono = Vec::with_capacity(FLATNESS_WINDOW);
+    let mut frame_sum = 0.0f32;
+    for (i, sample) in src.enumerate() {
+        frame_sum += sample;
+        if (i + 1) % channels != 0 {
+            continue;
+        }
+        mono.push(frame_sum / channels as f32);
+        frame_sum = 0.0;
+        if mono.len() == FLATNESS_WINDOW {
+            windows.push(classify_window(&mono));
+            mono.clear();
+        }
+    }
+
+    let min_windows = (MIN_APPLAUSE.as_secs_f64() / window_secs).ceil() as usize;
+    let edge_windows = (APPLAUSE_EDGE.as_secs_f64() / window_secs) as usize;
+    let leading = applause_run(windows.iter().take(edge_windows), min_windows);
+    let trailing = applause_run(windows.iter().rev().take(edge_windows), min_windows);
+    if leading + trailing >= windows.len() {
+        // Nothing but applause; better to play it than nothing
+        return Ok(ApplauseTrim {
+            start: Duration::ZERO,
+            end: None,
+        });
+    }
+    Ok(ApplauseTrim {
+        start: Duration::from_secs_f64(leading as f64 * window_secs),
+        end: (trailing > 0)
+            .then(|| Duration::from_secs_f64((windows.len() - trailing) as f64 * window_secs)),
+    })
+}
+
+fn classify_window(samples: &[f32]) -> Option<bool> {
+    let sum_sq: f64 = samples.iter().map(|&s| (s * s) as f64).sum();
+    if rms_db(sum_sq, samples.len()) < APPLAUSE_SILENCE_DB {
+        return None;
+    }
+    // Geometric over arithmetic mean of the power spectrum: near 0 for tones, higher for noise
+    let power: Vec<f64> = spectrum::magnitudes(samples)
+        .iter()
+        .skip(1)
+        .map(|&m| (m * m) as f64 + 1e-20)
+        .collect();
+    let mean = power.iter().sum::<f64>() / power.len() as f64;
+    let geometric = (power.iter().map(|p| p.ln()).sum::<f64>() / power.len() as f64).exp();
+    Some((geometric / mean) as f32 > APPLAUSE_FLATNESS)
+}
+
+/// Windows to trim from the edge the iterator starts at: the run of applause and silence
+/// before the first music, if it holds at least `min_windows` of applause
+fn applause_run<'a>(windows: impl Iterator<Item = &'a Option<bool>>, min_windows: usize) -> usize {
+    let (mut run, mut applause) = (0, 0);
+    for window in windows {
+        match window {
+            Some(false) => break,
+            Some(true) => applause += 1,
+            None => {}
+        }
+        run += 1;
+    }
+    if applause >= min_windows.max(1) {
+        run
+    } else {
+        0
+    }
+}
+
+/// RMS level of each `LEVEL_WINDOW` of the file, downmixed to mono, in dBFS
+fn window_levels_db(path: &Path) -> Result<Vec<f32>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let src = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Unsupported/invalid audio: {:?}", path))?
+        .convert_samples::<f32>();
+
+    let channels = src.channels().max(1) as usize;
+    let window_frames = ((src.sample_rate() as f64 * LEVEL_WINDOW.as_secs_f64()) as usize).max(1);
+
+    let mut levels = Vec::new();
+    let mut sum_sq = 0.0f64;
+    let mut frames = 0;
+    let mut frame_sum = 0.0f32;
+    for (i, sample) in src.enumerate() {
+        frame_sum += sample;
+        if (i + 1) % channels != 0 {
+            continue;
+        }
+        let mono = frame_sum / channels as f32;
+        frame_sum = 0.0;
+        sum_sq += (mono * mono) as f64;
+        frames += 1;
+        if frames == window_frames {
+            levels.push(rms_db(sum_sq, frames));
+            sum_sq = 0.0;
+            frames = 0;
+        }
+    }
+    if frames > 0 {
+        levels.push(rms_db(sum_sq, frames));
+    }
+    Ok(levels)
+}
+
+fn rms_db(sum_sq: f64, frames: usize) -> f32 {
+    let rms = (sum_sq / frames as f64).sqrt();
+    (20.0 * rms.max(1e-10).log10()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+
+    const RATE: u32 = 44_100;
+
+    /// Two two-second tones with a second of silence between them, after `lead_in` of silence
+    fn two_tones(lead_in: Duration) -> TempFile {
+        let silence = |d: Duration| vec![0.0; (d.as_secs_f32() * RATE as f32) as usize];
+        let samples = [
+            silence(lead_in),
+            sine(440.0, 2 * RATE as usize, 1, RATE),
+            silence(Duration::from_secs(1)),
+            sine(660.0, 2 * RATE as usize, 1, RATE),
+        ]
+        .concat();
+        TempFile::wav(&samples, 1, RATE)
+    }
+
+    /// `frames` of white noise at up to `level`, repeatable from run to run
+    fn noise(frames: usize, level: f32) -> Vec<f32> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..frames)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                level * ((state >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trims_an_applause_tail_and_keeps_the_tone() {
+        let samples = [
+            sine(440.0, 3 * RATE as usize, 1, RATE),
+            noise(3 * RATE as usize, 0.3),
+        ]
+        .concat();
+        let file = TempFile::wav(&samples, 1, RATE);
+        let trim = detect_applause(file.path()).unwrap();
+        assert_eq!(trim.start, Duration::ZERO);
+        let end = trim.end.expect("the noise tail is trimmed").as_secs_f32();
+        assert!((end - 3.0).abs() < 0.1, "{}", end);
+    }
+
+    #[test]
+    fn keeps_a_tone_without_applause() {
+        let file = TempFile::wav(&sine(440.0, 3 * RATE as usize, 1, RATE), 1, RATE);
+        let trim = detect_applause(file.path()).unwrap();
+        assert_eq!(
+            trim,
+            ApplauseTrim {
+                start: Duration::ZERO,
+                end: None
+            }
+        );
+    }
+
+    #[test]
+    fn finds_the_gap_between_two_tones() {
+        let file = two_tones(Duration::ZERO);
+        let boundaries = detect_track_boundaries(file.path()).unwrap();
+        assert_eq!(boundaries.len(), 1, "{:?}", boundaries);
+        let middle = boundaries[0].as_secs_f32();
+        assert!((middle - 2.5).abs() < 0.1, "{}", middle);
+    }
+
+    #[test]
+    fn ignores_lead_in_silence() {
+        let file = two_tones(Duration::from_secs(2));
+        let boundaries = detect_track_boundaries(file.path()).unwrap();
+        assert_eq!(boundaries.len(), 1, "{:?}", boundaries);
+        assert!((boundaries[0].as_secs_f32() - 4.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn respects_the_minimum_gap_and_threshold() {
+        let file = two_tones(Duration::ZERO);
+        let longer_gap = BoundaryDetection {
+            min_gap: Duration::from_millis(1_500),
+            ..Default::default()
+        };
+        assert!(detect_track_boundaries_with(file.path(), longer_gap)
+            .unwrap()
+            .is_empty());
+        // With the threshold above the tones' level, nothing but silence is left
+        let loud_threshold = BoundaryDetection {
+            silence_threshold_db: 0.0,
+            ..Default::default()
+        };
+        assert!(detect_track_boundaries_with(file.path(), loud_threshold)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn draws_the_requested_number_of_buckets() {
+        for secs in [1, 3] {
+            let file = TempFile::wav(&sine(440.0, secs * RATE as usize, 2, RATE), 2, RATE);
+            for buckets in [1, 50, 800] {
+                let peaks = waveform(file.path(), buckets).unwrap();
+                assert_eq!(peaks.len(), buckets);
+                for &(min, max) in &peaks {
+                    assert!((-1.0..=1.0).contains(&min) && (-1.0..=1.0).contains(&max));
+                    assert!(min <= max);
+                }
+                // A half-scale tone peaks near half scale in every bucket long enough to
+                // hold a cycle
+                if buckets <= 50 {
+                    assert!(peaks
+                        .iter()
+                        .all(|&(min, max)| (max - 0.5).abs() < 0.02 && (min + 0.5).abs() < 0.02));
+                }
+            }
+        }
+    }
+}
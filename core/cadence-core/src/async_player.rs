@@ -0,0 +1,247 @@
+use crate::{PlaybackState, Player, TrackInfo};
+use anyhow::{Context as _, Result};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+type Command = Box<dyn FnOnce(&mut Player) + Send>;
+
+/// How often the player's thread makes the `poll_*` calls a host would otherwise make
+const TICK: Duration = Duration::from_millis(50);
+
+/// A `Player` on a thread of its own, driven through futures so an async frontend never
+/// blocks on decoding or the output device.
+///
+/// Each method sends a command to the player's thread and returns a `Reply` that resolves
+/// once the command has been applied there, in the order the commands were sent. Methods not
+/// mirrored here are reached through `run`.
+///
+/// Between commands the thread calls `poll_queue`, `poll_skip_markers` and
+/// `release_idle_output` every 50 ms, so the player keeps up with the queue, markers are
+/// skipped and an idle device is released without the host polling. Their errors, such as
+/// a queued track that fails to load, are dropped; subscribe with `on_track_end` through `run`
+/// to follow track changes.
+///
+/// Dropping the `AsyncPlayer` lets the thread finish the commands already sent, then drops
+/// the player.
+pub struct AsyncPlayer {
+    commands: Sender<Command>,
+}
+
+impl AsyncPlayer {
+    /// Start a player thread on the system's default output device, like `Player::new`
+    pub fn new() -> Result<Self> {
+        Self::spawn(Player::new)
+    }
+
+    /// Start a player thread on the output device named `name`, like
+    /// `Player::new_with_device`
+    pub fn new_with_device(name: &str) -> Result<Self> {
+        let name = name.to_string();
+        Self::spawn(move || Player::new_with_device(&name))
+    }
+
+    /// Start a player thread without an output device, like `Player::new_headless`
+    pub fn new_headless() -> Result<Self> {
+        Self::spawn(Player::new_headless)
+    }
+
+    /// The output stream can't move between threads, so the player is created on the thread
+    /// that owns it
+    fn spawn(open: impl FnOnce() -> Result<Player> + Send + 'static) -> Result<Self> {
+        let (opened_tx, opened_rx) = mpsc::sync_channel(1);
+        let (commands, commands_rx) = mpsc::channel::<Command>();
+        thread::Builder::new()
+            .name("cadence-player".to_string())
+            .spawn(move || {
+                let mut player = match open() {
+                    Ok(player) => {
+                        let _ = opened_tx.send(Ok(()));
+                        player
+                    }
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let mut next_tick = Instant::now() + TICK;
+                loop {
+                    let wait = next_tick.saturating_duration_since(Instant::now());
+                    match commands_rx.recv_timeout(wait) {
+                        Ok(command) => command(&mut player),
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                    if Instant::now() >= next_tick {
+                        tick(&mut player);
+                        next_tick = Instant::now() + TICK;
+                    }
+                }
+            })
+            .context("Failed to start the player thread")?;
+        opened_rx
+            .recv()
+            .context("The player thread stopped while starting")??;
+        Ok(Self { commands })
+    }
+
+    /// Run `f` with the player on its thread, resolving to what it returns
+    pub fn run<T, F>(&self, f: F) -> Reply<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Player) -> T + Send + 'static,
+    {
+        self.try_run(move |player| Ok(f(player)))
+    }
+
+    /// Like `run` for the player methods that can fail, flattening their error into the reply
+    fn try_run<T, F>(&self, f: F) -> Reply<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Player) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        // A command that never runs drops its sender, and the reply resolves to an error
+        let _ = self.commands.send(Box::new(move |player| {
+            let _ = tx.send(f(player));
+        }));
+        Reply { rx }
+    }
+
+    /// Load `path` and play it, like `Player::load_and_play`; resolves once it's playing
+    pub fn play(&self, path: PathBuf) -> Reply<TrackInfo> {
+        self.try_run(move |player| player.load_and_play(path))
+    }
+
+    /// See `Player::load_and_play_symphonia`
+    pub fn play_symphonia(&self, path: PathBuf) -> Reply<TrackInfo> {
+        self.try_run(move |player| player.load_and_play_symphonia(path))
+    }
+
+    pub fn pause(&self) -> Reply<()> {
+        self.run(Player::pause)
+    }
+
+    pub fn resume(&self) -> Reply<()> {
+        self.run(Player::resume)
+    }
+
+    pub fn stop(&self) -> Reply<()> {
+        self.run(Player::stop)
+    }
+
+    /// See `Player::seek`
+    pub fn seek(&self, to_ms: u64) -> Reply<()> {
+        self.try_run(move |player| player.seek(to_ms))
+    }
+
+    /// See `Player::set_volume`
+    pub fn set_volume(&self, volume: f32) -> Reply<()> {
+        self.run(move |player| player.set_volume(volume))
+    }
+
+    pub fn position_ms(&self) -> Reply<Option<u64>> {
+        self.run(|player| player.position_ms())
+    }
+
+    pub fn state(&self) -> Reply<PlaybackState> {
+        self.run(|player| player.state())
+    }
+}
+
+/// The polling a host does for a `Player` it owns itself
+fn tick(player: &mut Player) {
+    let _ = player.poll_queue();
+    let _ = player.poll_skip_markers();
+    player.release_idle_output();
+}
+
+/// Resolves to the result of a command sent to an `AsyncPlayer`, or to an error if the
+/// player's thread stopped before running it
+#[must_use = "the command runs either way, but its result is lost"]
+pub struct Reply<T> {
+    rx: oneshot::Receiver<Result<T>>,
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        Pin::new(&mut self.rx).poll(cx).map(|result| {
+            result.unwrap_or_else(|_| Err(anyhow::anyhow!("The player thread has stopped")))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+
+    /// A player that needs no output device, so the tests run on any machine
+    fn player() -> AsyncPlayer {
+        AsyncPlayer::new_headless().unwrap()
+    }
+
+    #[tokio::test]
+    async fn plays_pauses_and_stops() {
+        let player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 10, 2, 44_100), 2, 44_100);
+
+        let info = player.play(wav.path().to_path_buf()).await.unwrap();
+        assert_eq!(info.path, wav.path());
+        assert_eq!(info.duration_ms, Some(10_000));
+        assert_eq!(player.state().await.unwrap(), PlaybackState::Playing);
+
+        player.pause().await.unwrap();
+        assert_eq!(player.state().await.unwrap(), PlaybackState::Paused);
+        let paused_at = player.position_ms().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(player.position_ms().await.unwrap(), paused_at);
+
+        player.resume().await.unwrap();
+        assert_eq!(player.state().await.unwrap(), PlaybackState::Playing);
+        player.stop().await.unwrap();
+        assert_eq!(player.state().await.unwrap(), PlaybackState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn resolves_errors() {
+        let player = player();
+        let missing = TempFile::new("wav");
+        assert!(player.play(missing.path().to_path_buf()).await.is_err());
+        assert_eq!(player.state().await.unwrap(), PlaybackState::Empty);
+    }
+
+    #[tokio::test]
+    async fn advances_the_queue_without_polling() {
+        let player = player();
+        let first = TempFile::wav(&sine(440.0, 4_410, 1, 44_100), 1, 44_100);
+        let second = TempFile::wav(&sine(880.0, 44_100 * 10, 1, 44_100), 1, 44_100);
+        let paths = [first.path().to_path_buf(), second.path().to_path_buf()];
+        player
+            .run(move |player| -> Result<TrackInfo> {
+                for path in paths {
+                    player.enqueue(path)?;
+                }
+                player.play_queue()
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let current = || player.run(|player| player.current_track().map(|t| t.info.path.clone()));
+        for _ in 0..100 {
+            if current().await.unwrap().as_deref() == Some(second.path()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the queue didn't advance to the second track");
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use rodio::Source;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Decoded size above which `Auto` caches to a temp file instead of memory
+const AUTO_MEMORY_LIMIT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Counter to keep temp file names unique within the process
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// How a track is decoded before it reaches the sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeCachePolicy {
+    /// Decode while playing; seeking decodes again from the start of the file
+    #[default]
+    Stream,
+    /// Decode the whole track into memory on load; seeks jump straight to the position
+    Memory,
+    /// Decode the whole track into a temporary PCM file on load; seeks jump straight to the
+    /// position. The file is deleted once the track is replaced or the player is dropped
+    TempFile,
+    /// `Memory` for tracks whose decoded size is modest, `TempFile` for larger or unknown ones
+    Auto,
+}
+
+impl DecodeCachePolicy {
+    /// Pick a concrete policy for a source, resolving `Auto`
+    pub(crate) fn resolve<S>(self, src: &S) -> Self
+    where
+        S: Source,
+        S::Item: rodio::Sample,
+    {
+        if self != DecodeCachePolicy::Auto {
+            return self;
+        }
+        let decoded_bytes = src
+            .total_duration()
+            .map(|d| d.as_secs_f64() * src.sample_rate() as f64 * src.channels() as f64 * 4.0);
+        match decoded_bytes {
+            Some(bytes) if bytes <= AUTO_MEMORY_LIMIT_BYTES as f64 => DecodeCachePolicy::Memory,
+            _ => DecodeCachePolicy::TempFile,
+        }
+    }
+}
+
+/// A fully decoded track that can be played from any position without decoding again
+pub(crate) struct PcmCache {
+    channels: u16,
+    sample_rate: u32,
+    /// Total number of samples across all channels
+    len: usize,
+    storage: Storage,
+}
+
+#[derive(Clone)]
+enum Storage {
+    Memory(Arc<Vec<f32>>),
+    TempFile(Arc<TempPcm>),
+}
+
+/// Raw little-endian f32 samples on disk, removed when the last reference goes away
+struct TempPcm {
+    path: PathBuf,
+}
+
+impl Drop for TempPcm {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl PcmCache {
+    /// Decode `src` to the end using a resolved `Memory` or `TempFile` policy
+    pub(crate) fn decode<S>(src: S, policy: DecodeCachePolicy) -> Result<Self>
+    where
+        S: Source<Item = f32>,
+    {
+        let channels = src.channels();
+        let sample_rate = src.sample_rate();
+
+        let (len, storage) = match policy {
+            DecodeCachePolicy::TempFile => {
+                let path = std::env::temp_dir().join(format!(
+                    "cadence-{}-{}.pcm",
+                    process::id(),
+                    TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+                ));
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create decode cache {:?}", path))?;
+                // Owned from here on so the file is cleaned up if writing fails
+                let temp = TempPcm { path };
+                let mut writer = BufWriter::new(file);
+                let mut len = 0;
+                for sample in src {
+                    writer.write_all(&sample.to_le_bytes())?;
+                    len += 1;
+                }
+                writer.flush().context("Failed to write decode cache")?;
+                (len, Storage::TempFile(Arc::new(temp)))
+            }
+            _ => {
+                let samples: Vec<f32> = src.collect();
+                (samples.len(), Storage::Memory(Arc::new(samples)))
+            }
+        };
+
+        Ok(Self {
+            channels,
+            sample_rate,
+            len,
+            storage,
+        })
+    }
+
+    /// Whether the samples are held in memory rather than in a temp file
+    pub(crate) fn in_memory(&self) -> bool {
+        matches!(self.storage, Storage::Memory(_))
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        samples_duration(self.len, self.channels, self.sample_rate)
+    }
+
+    /// A source playing the cached track from `start`
+    pub(crate) fn source_at(&self, start: Duration) -> Result<CachedSource> {
+        let frame = (start.as_secs_f64() * self.sample_rate as f64) as usize;
+        let pos = (frame * self.channels as usize).min(self.len);
+
+        let reader = match &self.storage {
+            Storage::Memory(_) => None,
+            Storage::TempFile(temp) => {
+                let mut file = File::open(&temp.path).context("Failed to open decode cache")?;
+                file.seek(SeekFrom::Start(pos as u64 * 4))?;
+                Some(BufReader::new(file))
+            }
+        };
+
+        Ok(CachedSource {
+            storage: self.storage.clone(),
+            reader,
+            pos,
+            len: self.len,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+        })
+    }
+}
+
+/// Plays samples out of a `PcmCache`
+pub(crate) struct CachedSource {
+    /// Keeps the cached data (and the temp file) alive while playing
+    storage: Storage,
+    reader: Option<BufReader<File>>,
+    pos: usize,
+    len: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Iterator for CachedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.len {
+            return None;
+        }
+        let sample = match (&self.storage, &mut self.reader) {
+            (Storage::Memory(samples), _) => samples[self.pos],
+            (Storage::TempFile(_), Some(reader)) => {
+                let mut bytes = [0u8; 4];
+                reader.read_exact(&mut bytes).ok()?;
+                f32::from_le_bytes(bytes)
+            }
+            (Storage::TempFile(_), None) => return None,
+        };
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for CachedSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.len - self.pos)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(samples_duration(self.len, self.channels, self.sample_rate))
+    }
+}
+
+fn samples_duration(len: usize, channels: u16, sample_rate: u32) -> Duration {
+    let frames = len / channels.max(1) as usize;
+    Duration::from_secs_f64(frames as f64 / sample_rate.max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::sine;
+    use rodio::buffer::SamplesBuffer;
+
+    fn decode(samples: &[f32], policy: DecodeCachePolicy) -> PcmCache {
+        PcmCache::decode(SamplesBuffer::new(2, 44_100, samples.to_vec()), policy).unwrap()
+    }
+
+    fn temp_path(cache: &PcmCache) -> PathBuf {
+        match &cache.storage {
+            Storage::TempFile(temp) => temp.path.clone(),
+            Storage::Memory(_) => panic!("cached in memory"),
+        }
+    }
+
+    #[test]
+    fn removes_the_temp_file_with_the_last_source() {
+        let samples = sine(440.0, 88_200, 2, 44_100);
+        let cache = decode(&samples, DecodeCachePolicy::TempFile);
+        assert!(!cache.in_memory());
+        assert_eq!(cache.duration(), Duration::from_secs(2));
+        let path = temp_path(&cache);
+        assert!(path.exists());
+
+        // Seeking reads from the position directly
+        let source = cache.source_at(Duration::from_secs(1)).unwrap();
+        drop(cache);
+        assert!(path.exists(), "removed while a source still plays it");
+        assert_eq!(source.collect::<Vec<_>>(), samples[88_200..]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn auto_keeps_short_tracks_in_memory() {
+        let source = SamplesBuffer::new(2, 44_100, vec![0.0f32; 88_200]);
+        let policy = DecodeCachePolicy::Auto.resolve(&source);
+        assert_eq!(policy, DecodeCachePolicy::Memory);
+        let cache = PcmCache::decode(source, policy).unwrap();
+        assert!(cache.in_memory());
+        assert_eq!(cache.source_at(Duration::ZERO).unwrap().count(), 88_200);
+    }
+}
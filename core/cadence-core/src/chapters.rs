@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+/// A Podcasting 2.0 chapters file
+#[derive(Deserialize)]
+struct ChaptersFile {
+    chapters: Vec<Chapter>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Chapter {
+    /// Seconds from the start of the episode
+    start_time: f64,
+    end_time: Option<f64>,
+    title: Option<String>,
+}
+
+/// Read the ad segments from a Podcasting 2.0 chapters file, ready for
+/// `Player::set_skip_markers`.
+///
+/// The format has no dedicated ad flag, so a chapter counts as an ad when its title starts
+/// with a word like "Ad", "Advertisement", "Sponsor" or "Promo". A chapter without an
+/// `endTime` runs until the next one starts, or to the end of the episode for the last.
+pub fn read_chapter_ad_markers(path: &Path) -> Result<Vec<(Duration, Duration)>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut file: ChaptersFile = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Invalid chapters file {:?}", path))?;
+    file.chapters
+        .retain(|c| c.start_time.is_finite() && c.start_time >= 0.0);
+    file.chapters
+        .sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+
+    let starts: Vec<f64> = file.chapters.iter().map(|c| c.start_time).collect();
+    let markers = file
+        .chapters
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.title.as_deref().is_some_and(is_ad))
+        .map(|(i, c)| {
+            let end = c
+                .end_time
+                .filter(|end| end.is_finite() && *end > c.start_time)
+                .or_else(|| starts.get(i + 1).copied())
+                .map_or(Duration::MAX, Duration::from_secs_f64);
+            (Duration::from_secs_f64(c.start_time), end)
+        })
+        .collect();
+    Ok(markers)
+}
+
+fn is_ad(title: &str) -> bool {
+    let first = title
+        .split(|c: char| !c.is_alphanumeric())
+        .find(|word| !word.is_empty())
+        .unwrap_or_default()
+        .to_lowercase();
+    matches!(
+        first.as_str(),
+        "ad" | "ads" | "advert" | "advertisement" | "sponsor" | "sponsored" | "promo"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempFile;
+
+    fn markers(json: &str) -> Result<Vec<(Duration, Duration)>> {
+        let file = TempFile::new("json");
+        std::fs::write(file.path(), json).unwrap();
+        read_chapter_ad_markers(file.path())
+    }
+
+    #[test]
+    fn finds_ad_chapters() {
+        let json = r#"{
+            "version": "1.2.0",
+            "chapters": [
+                {"startTime": 0, "title": "Intro"},
+                {"startTime": 5, "endTime": 10, "title": "Ad: a mattress"},
+                {"startTime": 10, "title": "Adventures in audio"},
+                {"startTime": 60, "title": "Sponsored segment"},
+                {"startTime": 90, "title": "Outro"},
+                {"startTime": 120, "title": "Promo"}
+            ]
+        }"#;
+        let secs = Duration::from_secs;
+        assert_eq!(
+            markers(json).unwrap(),
+            [
+                (secs(5), secs(10)),
+                // Runs until the next chapter
+                (secs(60), secs(90)),
+                // The last one runs to the end of the episode
+                (secs(120), Duration::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_chapters_and_drops_bad_times() {
+        let json = r#"{"chapters": [
+            {"startTime": 30, "title": "Ad"},
+            {"startTime": -4, "title": "Ad"},
+            {"startTime": 10, "endTime": 2, "title": "Ad break"},
+            {"startTime": 20, "title": "Interview"}
+        ]}"#;
+        let secs = Duration::from_secs;
+        assert_eq!(
+            markers(json).unwrap(),
+            [(secs(10), secs(20)), (secs(30), Duration::MAX)]
+        );
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_chapters() {
+        assert!(markers("[1, 2, 3]").is_err());
+        assert!(read_chapter_ad_markers(TempFile::new("json").path()).is_err());
+    }
+}
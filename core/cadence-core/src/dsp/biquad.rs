@@ -0,0 +1,99 @@
+use std::f64::consts::PI;
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    /// Second-order high-pass (RBJ cookbook)
+    pub(crate) fn high_pass(sample_rate: u32, freq_hz: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prewarp(sample_rate, freq_hz, q);
+        Self::normalize(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// High shelf boosting/cutting above `freq_hz` by `gain_db` (RBJ cookbook)
+    pub(crate) fn high_shelf(sample_rate: u32, freq_hz: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::prewarp(sample_rate, freq_hz, q);
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+        Self::normalize(
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+            (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+        )
+    }
+
+    /// Peaking filter boosting/cutting around `freq_hz` by `gain_db` (RBJ cookbook)
+    pub(crate) fn peaking(sample_rate: u32, freq_hz: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::prewarp(sample_rate, freq_hz, q);
+        Self::normalize(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    /// cos(w0) and alpha for a filter at `freq_hz`, clamped below Nyquist
+    fn prewarp(sample_rate: u32, freq_hz: f64, q: f64) -> (f64, f64) {
+        let nyquist = sample_rate as f64 / 2.0;
+        let w0 = 2.0 * PI * freq_hz.clamp(1.0, nyquist * 0.99) / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q.max(1e-3)))
+    }
+
+    fn normalize(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// One biquad section with its own state (transposed direct form II)
+#[derive(Debug, Clone)]
+pub(crate) struct Biquad {
+    coeffs: Coefficients,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub(crate) fn new(coeffs: Coefficients) -> Self {
+        Self {
+            coeffs,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        let c = &self.coeffs;
+        let x = x as f64;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y as f32
+    }
+}
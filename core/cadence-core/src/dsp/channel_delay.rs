@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+/// Longest delay a single channel can be given, in milliseconds
+pub(crate) const MAX_DELAY_MS: f32 = 50.0;
+
+/// Independent fixed delay per channel, for time-aligning speakers at different distances
+pub(crate) struct ChannelDelay {
+    /// Delay line per channel, holding exactly that channel's delay in samples
+    lines: Vec<VecDeque<f32>>,
+}
+
+impl ChannelDelay {
+    pub(crate) fn new(delays_ms: &[f32], sample_rate: u32) -> Self {
+        let lines = delays_ms
+            .iter()
+            .map(|&ms| {
+                let len = (ms.clamp(0.0, MAX_DELAY_MS) / 1000.0 * sample_rate as f32).round();
+                VecDeque::from(vec![0.0; len as usize])
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        for (sample, line) in frame.iter_mut().zip(self.lines.iter_mut()) {
+            if line.is_empty() {
+                continue;
+            }
+            line.push_back(*sample);
+            *sample = line.pop_front().unwrap_or(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The frame each channel's impulse comes out on, given an impulse in frame 0
+    fn impulse_frames(delay: &mut ChannelDelay, channels: usize) -> Vec<Option<usize>> {
+        let mut arrivals = vec![None; channels];
+        for i in 0..4_096 {
+            let mut frame = vec![if i == 0 { 1.0 } else { 0.0 }; channels];
+            delay.process(&mut frame);
+            for (channel, &sample) in frame.iter().enumerate() {
+                if sample == 1.0 {
+                    arrivals[channel] = Some(i);
+                }
+            }
+        }
+        arrivals
+    }
+
+    #[test]
+    fn delays_each_channel_by_its_own_amount() {
+        let mut delay = ChannelDelay::new(&[0.0, 10.0], 48_000);
+        assert_eq!(impulse_frames(&mut delay, 2), [Some(0), Some(480)]);
+    }
+
+    #[test]
+    fn clamps_delays_to_the_maximum() {
+        let mut delay = ChannelDelay::new(&[-5.0, 80.0], 44_100);
+        assert_eq!(impulse_frames(&mut delay, 2), [Some(0), Some(2_205)]);
+    }
+}
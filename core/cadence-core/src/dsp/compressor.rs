@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+/// Settings for the dynamic range compressor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    /// Peak level in dBFS above which gain is reduced
+    pub threshold_db: f32,
+    /// How many dB over the threshold it takes to come out 1 dB over it
+    pub ratio: f32,
+    /// How quickly gain comes down once a peak crosses the threshold
+    pub attack: Duration,
+    /// How quickly gain recovers after the level drops back
+    pub release: Duration,
+    /// Gain added after compression to bring the level back up, in dB
+    pub makeup_db: f32,
+}
+
+impl Compression {
+    /// Heavy compression with enough makeup gain that quiet dialogue stays audible at a
+    /// volume where explosions don't wake the neighbours
+    pub fn night() -> Self {
+        Self {
+            threshold_db: -30.0,
+            ratio: 4.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(250),
+            makeup_db: 12.0,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 3.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(200),
+            makeup_db: 0.0,
+        }
+    }
+}
+
+/// Feed-forward peak compressor, linked across channels so the stereo image holds still
+pub(crate) struct Compressor {
+    settings: Compression,
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Current gain reduction in dB, positive
+    reduction_db: f32,
+}
+
+impl Compressor {
+    pub(crate) fn new(settings: Compression, sample_rate: u32) -> Self {
+        let coeff =
+            |d: Duration| (-1.0 / (d.as_secs_f32().max(1e-4) * sample_rate.max(1) as f32)).exp();
+        Self {
+            settings,
+            attack_coeff: coeff(settings.attack),
+            release_coeff: coeff(settings.release),
+            reduction_db: 0.0,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> Compression {
+        self.settings
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let level_db = 20.0 * peak.max(1e-10).log10();
+        let over = level_db - self.settings.threshold_db;
+        let wanted = if over > 0.0 {
+            over * (1.0 - 1.0 / self.settings.ratio.max(1.0))
+        } else {
+            0.0
+        };
+        let coeff = if wanted > self.reduction_db {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.reduction_db = coeff * self.reduction_db + (1.0 - coeff) * wanted;
+
+        let gain = 10f32.powf((self.settings.makeup_db - self.reduction_db) / 20.0);
+        frame.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms_db(samples: &[f32]) -> f32 {
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        10.0 * mean_square.log10()
+    }
+
+    /// One second of a 100 Hz square wave at `level`, compressed, keeping the last half
+    fn settled(compressor: &mut Compressor, level: f32) -> Vec<f32> {
+        let out: Vec<f32> = (0..44_100)
+            .map(|i| {
+                let mut frame = [if (i / 220) % 2 == 0 { level } else { -level }];
+                compressor.process(&mut frame);
+                frame[0]
+            })
+            .collect();
+        out[22_050..].to_vec()
+    }
+
+    #[test]
+    fn reduces_the_dynamic_range_by_the_ratio() {
+        let settings = Compression {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            release: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut compressor = Compressor::new(settings, 44_100);
+        // 0 dBFS and -40 dBFS sections, 40 dB apart
+        let loud = rms_db(&settled(&mut compressor, 1.0));
+        let quiet = rms_db(&settled(&mut compressor, 0.01));
+        // 20 dB over the threshold comes out 5 dB over; the quiet part is untouched
+        assert!((loud - -15.0).abs() < 0.1, "{}", loud);
+        assert!((quiet - -40.0).abs() < 0.1, "{}", quiet);
+    }
+
+    #[test]
+    fn adds_makeup_gain() {
+        let settings = Compression {
+            threshold_db: -20.0,
+            makeup_db: 6.0,
+            ..Default::default()
+        };
+        let mut compressor = Compressor::new(settings, 44_100);
+        let quiet = rms_db(&settled(&mut compressor, 0.01));
+        assert!((quiet - -34.0).abs() < 0.1, "{}", quiet);
+    }
+}
@@ -0,0 +1,76 @@
+use std::f32::consts::PI;
+
+/// Corner frequency of the blocker; well below anything audible
+const CUTOFF_HZ: f32 = 5.0;
+
+/// One-pole high-pass that removes DC offset, per channel
+pub(crate) struct DcBlocker {
+    /// Pole radius; closer to 1.0 means a lower corner frequency
+    r: f32,
+    last_in: Vec<f32>,
+    last_out: Vec<f32>,
+}
+
+impl DcBlocker {
+    pub(crate) fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            r: 1.0 - 2.0 * PI * CUTOFF_HZ / sample_rate.max(1) as f32,
+            last_in: vec![0.0; channels as usize],
+            last_out: vec![0.0; channels as usize],
+        }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        for ((sample, x1), y1) in frame
+            .iter_mut()
+            .zip(self.last_in.iter_mut())
+            .zip(self.last_out.iter_mut())
+        {
+            let y = *sample - *x1 + self.r * *y1;
+            *x1 = *sample;
+            *y1 = y;
+            *sample = y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_a_dc_offset() {
+        let rate = 44_100;
+        let mut blocker = DcBlocker::new(2, rate);
+        let mut means = Vec::new();
+        // A 440 Hz tone riding on +0.1 DC, for two seconds
+        for second in 0..2 {
+            let mut sum = 0.0;
+            for i in 0..rate {
+                let t = (second * rate + i) as f32 / rate as f32;
+                let sample = 0.1 + 0.5 * (2.0 * PI * 440.0 * t).sin();
+                let mut frame = [sample, sample];
+                blocker.process(&mut frame);
+                sum += frame[0];
+            }
+            means.push(sum / rate as f32);
+        }
+        // Settled after the first second; the offset is gone
+        assert!(means[1].abs() < 0.001, "{:?}", means);
+    }
+
+    #[test]
+    fn passes_audio_through() {
+        let rate = 44_100;
+        let mut blocker = DcBlocker::new(1, rate);
+        let mut peak: f32 = 0.0;
+        for i in 0..rate {
+            let mut frame = [(2.0 * PI * 100.0 * i as f32 / rate as f32).sin()];
+            blocker.process(&mut frame);
+            if i > rate / 2 {
+                peak = peak.max(frame[0].abs());
+            }
+        }
+        assert!((peak - 1.0).abs() < 0.01, "{}", peak);
+    }
+}
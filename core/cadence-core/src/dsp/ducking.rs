@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Side-chain settings for ducking the music under an external signal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Ducking {
+    /// Side-chain level (dBFS) above which the music ducks
+    pub threshold_db: f32,
+    /// Linear gain applied to the music while ducked
+    pub duck_level: f32,
+    /// Time to duck once the side-chain crosses the threshold
+    pub attack: Duration,
+    /// Time to come back up after it drops below
+    pub release: Duration,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            threshold_db: -30.0,
+            duck_level: 0.3,
+            attack: Duration::from_millis(50),
+            release: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Feeds side-chain levels (e.g. from a microphone monitor) to the player from any thread
+#[derive(Clone, Default)]
+pub struct DuckingInput {
+    /// Latest linear level, stored as f32 bits
+    level: Arc<AtomicU32>,
+}
+
+impl DuckingInput {
+    /// Report the current side-chain level as a linear amplitude (1.0 = full scale)
+    pub fn push(&self, level: f32) {
+        self.level.store(level.abs().to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+}
+
+/// Applies ducking gain, ramping with the configured attack/release
+pub(crate) struct Ducker {
+    settings: Ducking,
+    attack_coeff: f32,
+    release_coeff: f32,
+    gain: f32,
+}
+
+impl Ducker {
+    pub(crate) fn new(settings: Ducking, sample_rate: u32) -> Self {
+        let coeff =
+            |d: Duration| (-1.0 / (d.as_secs_f32().max(1e-4) * sample_rate.max(1) as f32)).exp();
+        Self {
+            settings,
+            attack_coeff: coeff(settings.attack),
+            release_coeff: coeff(settings.release),
+            gain: 1.0,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> Ducking {
+        self.settings
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32], side_chain: f32) {
+        let level_db = 20.0 * side_chain.max(1e-10).log10();
+        let (target, coeff) = if level_db > self.settings.threshold_db {
+            (self.settings.duck_level, self.attack_coeff)
+        } else {
+            (1.0, self.release_coeff)
+        };
+        self.gain = coeff * self.gain + (1.0 - coeff) * target;
+        frame.iter_mut().for_each(|s| *s *= self.gain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 10_000;
+
+    /// Gain applied to a full-scale frame after `duration` with the side chain at `input`
+    fn run(ducker: &mut Ducker, input: &DuckingInput, duration: Duration) -> f32 {
+        let mut frame = [1.0];
+        for _ in 0..(duration.as_secs_f32() * RATE as f32) as usize {
+            frame = [1.0];
+            ducker.process(&mut frame, input.level());
+        }
+        frame[0]
+    }
+
+    #[test]
+    fn ducks_and_restores_with_the_attack_and_release() {
+        let settings = Ducking::default();
+        let mut ducker = Ducker::new(settings, RATE);
+        let input = DuckingInput::default();
+        let duck = settings.duck_level;
+        // One time constant covers 1 - 1/e of the way
+        let partway = |from: f32, to: f32| to + (from - to) / std::f32::consts::E;
+
+        // A voice comes in at -20 dBFS, over the -30 dB threshold
+        input.push(0.1);
+        let gain = run(&mut ducker, &input, settings.attack);
+        assert!((gain - partway(1.0, duck)).abs() < 0.01, "{}", gain);
+        let gain = run(&mut ducker, &input, settings.attack * 10);
+        assert!((gain - duck).abs() < 0.01, "{}", gain);
+
+        // And goes quiet
+        input.push(0.001);
+        let gain = run(&mut ducker, &input, settings.release);
+        assert!((gain - partway(duck, 1.0)).abs() < 0.01, "{}", gain);
+        let gain = run(&mut ducker, &input, settings.release * 10);
+        assert!((gain - 1.0).abs() < 0.01, "{}", gain);
+    }
+
+    #[test]
+    fn stays_put_below_the_threshold() {
+        let mut ducker = Ducker::new(Ducking::default(), RATE);
+        let input = DuckingInput::default();
+        input.push(-0.02);
+        assert_eq!(run(&mut ducker, &input, Duration::from_secs(1)), 1.0);
+    }
+}
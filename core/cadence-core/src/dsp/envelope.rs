@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Piecewise-linear gain automation over track position
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GainEnvelope {
+    /// Breakpoints as (seconds, linear gain), sorted by time
+    points: Vec<(f64, f32)>,
+}
+
+impl GainEnvelope {
+    /// Build an envelope from breakpoints in any order; None if there are none
+    pub(crate) fn new(points: Vec<(Duration, f32)>) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+        let mut points: Vec<(f64, f32)> = points
+            .into_iter()
+            .map(|(at, gain)| (at.as_secs_f64(), gain.max(0.0)))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Self { points })
+    }
+
+    /// Gain at `secs` into the track; held flat before the first and after the last point
+    pub(crate) fn gain_at(&self, secs: f64) -> f32 {
+        let next = self.points.partition_point(|&(at, _)| at <= secs);
+        if next == 0 {
+            return self.points[0].1;
+        }
+        if next == self.points.len() {
+            return self.points[next - 1].1;
+        }
+        let (t0, g0) = self.points[next - 1];
+        let (t1, g1) = self.points[next];
+        let t = ((secs - t0) / (t1 - t0)) as f32;
+        g0 + (g1 - g0) * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_points_and_holds_outside_them() {
+        let envelope =
+            GainEnvelope::new(vec![(Duration::from_secs(1), 1.0), (Duration::ZERO, 0.0)]).unwrap();
+        assert!((envelope.gain_at(0.5) - 0.5).abs() < 1e-6);
+        assert_eq!(envelope.gain_at(-1.0), 0.0);
+        assert_eq!(envelope.gain_at(3.0), 1.0);
+    }
+
+    #[test]
+    fn needs_a_point() {
+        assert_eq!(GainEnvelope::new(Vec::new()), None);
+    }
+}
@@ -0,0 +1,258 @@
+use super::biquad::{Biquad, Coefficients};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Bands set for one queue entry with `Player::set_track_eq`, in place of the global ones;
+/// shared with the pipeline playing the entry so a change reaches it mid-track
+pub(crate) type TrackEq = Arc<Mutex<Option<Vec<EqBand>>>>;
+
+/// What a track can be equalized with in place of the global bands
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrackBands {
+    /// Its queue entry's `TrackEq`
+    pub(crate) own: Option<TrackEq>,
+    /// Preset for its genre, used under `Player::set_genre_eq`
+    pub(crate) genre: Option<Vec<EqBand>>,
+}
+
+impl TrackBands {
+    /// For a track with the queue entry equalizer `own`, tagged with `genre`
+    pub(crate) fn new(own: Option<TrackEq>, genre: Option<&str>) -> Self {
+        Self {
+            own,
+            genre: genre.and_then(genre_preset),
+        }
+    }
+
+    /// The bands to use given the global `eq`: the track's own, then its genre's preset
+    /// when `genre_eq` is on
+    pub(crate) fn resolve(&self, eq: &[EqBand], genre_eq: bool) -> Vec<EqBand> {
+        self.own
+            .as_ref()
+            .and_then(|own| own.lock().clone())
+            .or_else(|| self.genre.clone().filter(|_| genre_eq))
+            .unwrap_or_else(|| eq.to_vec())
+    }
+}
+
+/// Centres of the bands the built-in presets set, an octave apart
+const PRESET_FREQS_HZ: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1_000.0, 2_000.0, 4_000.0, 8_000.0, 16_000.0,
+];
+
+/// Width of each preset band, about an octave so neighbours blend smoothly
+const PRESET_Q: f32 = 1.41;
+
+/// Presets that come with the player, by name, with their gain in dB for each of
+/// `PRESET_FREQS_HZ`
+const BUILT_IN_PRESETS: [(&str, [f32; 10]); 5] = [
+    ("Flat", [0.0; 10]),
+    ("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+    ("Jazz", [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+    (
+        "Vocal Boost",
+        [-2.0, -2.0, -1.0, 0.0, 2.0, 4.0, 4.0, 3.0, 1.0, 0.0],
+    ),
+    (
+        "Bass Boost",
+        [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+];
+
+/// Built-in preset for each genre, by a word its tag contains; the first match wins
+const GENRE_PRESETS: [(&str, &str); 20] = [
+    ("rock", "Rock"),
+    ("metal", "Rock"),
+    ("punk", "Rock"),
+    ("grunge", "Rock"),
+    ("jazz", "Jazz"),
+    ("blues", "Jazz"),
+    ("swing", "Jazz"),
+    ("soul", "Jazz"),
+    ("classical", "Flat"),
+    ("opera", "Flat"),
+    ("orchestral", "Flat"),
+    ("soundtrack", "Flat"),
+    ("hip-hop", "Bass Boost"),
+    ("hip hop", "Bass Boost"),
+    ("rap", "Bass Boost"),
+    ("electronic", "Bass Boost"),
+    ("dance", "Bass Boost"),
+    ("reggae", "Bass Boost"),
+    ("speech", "Vocal Boost"),
+    ("podcast", "Vocal Boost"),
+];
+
+/// Bands of the built-in preset suiting `genre`, if it's one known here
+pub(crate) fn genre_preset(genre: &str) -> Option<Vec<EqBand>> {
+    let genre = genre.to_lowercase();
+    let (_, preset) = GENRE_PRESETS
+        .iter()
+        .find(|(word, _)| genre.contains(word))?;
+    built_in_preset(preset)
+}
+
+/// Names of the built-in presets
+pub(crate) fn built_in_presets() -> impl Iterator<Item = &'static str> {
+    BUILT_IN_PRESETS.iter().map(|(name, _)| *name)
+}
+
+/// Bands of the built-in preset called `name`, leaving out those it doesn't raise or lower
+pub(crate) fn built_in_preset(name: &str) -> Option<Vec<EqBand>> {
+    let (_, gains) = BUILT_IN_PRESETS
+        .iter()
+        .find(|(preset, _)| *preset == name)?;
+    Some(
+        PRESET_FREQS_HZ
+            .iter()
+            .zip(gains)
+            .map(|(&freq_hz, &gain_db)| EqBand {
+                freq_hz,
+                gain_db,
+                q: PRESET_Q,
+            })
+            .filter(|band| band.gain_db != 0.0)
+            .collect(),
+    )
+}
+
+/// One peaking band of the parametric equalizer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    /// Centre frequency
+    pub freq_hz: f32,
+    /// Boost (positive) or cut (negative) at the centre frequency
+    pub gain_db: f32,
+    /// Sharpness; higher values affect a narrower range around the centre
+    pub q: f32,
+}
+
+/// Cascade of peaking biquads, one chain per channel
+pub(crate) struct Equalizer {
+    bands: Vec<EqBand>,
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl Equalizer {
+    pub(crate) fn new(bands: Vec<EqBand>, channels: u16, sample_rate: u32) -> Self {
+        let chain: Vec<Biquad> = bands
+            .iter()
+            .map(|band| {
+                Biquad::new(Coefficients::peaking(
+                    sample_rate,
+                    band.freq_hz as f64,
+                    band.q as f64,
+                    band.gain_db as f64,
+                ))
+            })
+            .collect();
+        Self {
+            filters: vec![chain; channels as usize],
+            bands,
+        }
+    }
+
+    pub(crate) fn bands(&self) -> &[EqBand] {
+        &self.bands
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        for (sample, chain) in frame.iter_mut().zip(self.filters.iter_mut()) {
+            *sample = chain
+                .iter_mut()
+                .fold(*sample, |sample, filter| filter.process(sample));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 44_100;
+    const SWEEP_SECS: f32 = 4.0;
+
+    /// Exponential sine sweep from 20 Hz to 20 kHz, and the frequency at each sample
+    fn sweep() -> (Vec<f32>, Vec<f32>) {
+        let (f0, f1) = (20.0f32, 20_000.0f32);
+        let k = (f1 / f0).ln();
+        (0..(SWEEP_SECS * RATE as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE as f32 / SWEEP_SECS;
+                let phase =
+                    2.0 * std::f32::consts::PI * f0 * SWEEP_SECS / k * ((k * t).exp() - 1.0);
+                (0.5 * phase.sin(), f0 * (k * t).exp())
+            })
+            .unzip()
+    }
+
+    /// Gain in dB the equalizer applied to the part of the sweep around `freq_hz`, per channel
+    fn gain_around(bands: Vec<EqBand>, freq_hz: f32) -> [f32; 2] {
+        let (input, freqs) = sweep();
+        let mut eq = Equalizer::new(bands, 2, RATE);
+        let output: Vec<[f32; 2]> = input
+            .iter()
+            .map(|&s| {
+                let mut frame = [s, s];
+                eq.process(&mut frame);
+                frame
+            })
+            .collect();
+        let centre = freqs.iter().position(|&f| f >= freq_hz).unwrap();
+        let window = centre - 2_048..centre + 2_048;
+        let energy = |samples: &mut dyn Iterator<Item = f32>| samples.map(|s| s * s).sum::<f32>();
+        let before = energy(&mut input[window.clone()].iter().copied());
+        [0, 1].map(|ch| {
+            let after = energy(&mut output[window.clone()].iter().map(|f| f[ch]));
+            10.0 * (after / before).log10()
+        })
+    }
+
+    #[test]
+    fn boosts_energy_around_the_band() {
+        let band = EqBand {
+            freq_hz: 1_000.0,
+            gain_db: 12.0,
+            q: 1.0,
+        };
+        for gain in gain_around(vec![band], 1_000.0) {
+            assert!((gain - 12.0).abs() < 1.0, "{}", gain);
+        }
+        // Well away from the band nothing changes
+        for freq in [100.0, 10_000.0] {
+            for gain in gain_around(vec![band], freq) {
+                assert!(gain.abs() < 1.0, "{} Hz: {}", freq, gain);
+            }
+        }
+    }
+
+    #[test]
+    fn filters_channels_independently() {
+        let band = EqBand {
+            freq_hz: 1_000.0,
+            gain_db: -12.0,
+            q: 2.0,
+        };
+        let mut eq = Equalizer::new(vec![band], 2, RATE);
+        let mut left_only = Vec::new();
+        for i in 0..RATE {
+            let s = (2.0 * std::f32::consts::PI * 1_000.0 * i as f32 / RATE as f32).sin();
+            let mut frame = [s, 0.0];
+            eq.process(&mut frame);
+            left_only.push(frame);
+        }
+        assert!(left_only.iter().all(|frame| frame[1] == 0.0));
+    }
+
+    #[test]
+    fn passes_audio_unchanged_without_bands() {
+        let (input, _) = sweep();
+        let mut eq = Equalizer::new(Vec::new(), 1, RATE);
+        for &s in &input {
+            let mut frame = [s];
+            eq.process(&mut frame);
+            assert_eq!(frame[0], s);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+/// Only this much of the start of a track is eligible for a boost
+const INTRO_WINDOW: Duration = Duration::from_secs(20);
+/// Level (RMS, dBFS) considered "full"; intros quieter than this get lifted towards it
+const REFERENCE_DB: f32 = -20.0;
+/// Never boost by more than this
+const MAX_BOOST_DB: f32 = 12.0;
+/// Quieter than this is silence, which isn't worth boosting
+const SILENCE_DB: f32 = -60.0;
+/// Averaging time of the level measurement
+const LEVEL_TIME: Duration = Duration::from_millis(300);
+/// Smoothing time of gain changes during the intro
+const GAIN_TIME: Duration = Duration::from_millis(800);
+/// Time to drop the boost once the track has arrived, short so the body isn't overshot
+const RELEASE_TIME: Duration = Duration::from_millis(150);
+
+/// Temporarily lifts a quiet intro, fading the boost out once the track reaches full level.
+///
+/// The boost only ever shrinks over a track and is released for good once the track gets
+/// loud or the intro window passes, so it can't pump in the body of the track.
+pub(crate) struct IntroAssist {
+    /// Frames left in the intro window
+    remaining: u64,
+    /// Frames left before the level measurement has settled enough to act on
+    settling: u64,
+    mean_square: f32,
+    level_coeff: f32,
+    gain_coeff: f32,
+    release_coeff: f32,
+    boost_db: f32,
+    /// Highest boost still allowed; only ever comes down
+    ceiling_db: f32,
+    /// The intro is over; ramp the boost back to zero
+    released: bool,
+}
+
+impl IntroAssist {
+    /// `start` is where in the track playback begins; later starts get a shorter window
+    pub(crate) fn new(sample_rate: u32, start: Duration) -> Self {
+        let coeff = |d: Duration| (-1.0 / (d.as_secs_f32() * sample_rate.max(1) as f32)).exp();
+        let window = INTRO_WINDOW.saturating_sub(start);
+        Self {
+            remaining: (window.as_secs_f64() * sample_rate as f64) as u64,
+            settling: (LEVEL_TIME.as_secs_f64() * sample_rate as f64) as u64,
+            mean_square: 0.0,
+            level_coeff: coeff(LEVEL_TIME),
+            gain_coeff: coeff(GAIN_TIME),
+            release_coeff: coeff(RELEASE_TIME),
+            boost_db: 0.0,
+            ceiling_db: MAX_BOOST_DB,
+            released: start >= INTRO_WINDOW,
+        }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        if self.released && self.boost_db.abs() < 0.01 {
+            return;
+        }
+
+        let power = frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32;
+        self.mean_square = self.level_coeff * self.mean_square + (1.0 - self.level_coeff) * power;
+        let level_db = 10.0 * self.mean_square.max(1e-12).log10();
+
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.settling > 0 {
+            self.settling -= 1;
+            return;
+        }
+        if self.remaining == 0 || level_db >= REFERENCE_DB {
+            self.released = true;
+        }
+
+        let target = if self.released {
+            0.0
+        } else if level_db < SILENCE_DB {
+            // Hold through silence rather than chasing it
+            self.boost_db
+        } else {
+            self.ceiling_db = (REFERENCE_DB - level_db).clamp(0.0, self.ceiling_db);
+            self.ceiling_db
+        };
+        let coeff = if self.released {
+            self.release_coeff
+        } else {
+            self.gain_coeff
+        };
+        self.boost_db = coeff * self.boost_db + (1.0 - coeff) * target;
+
+        let gain = 10f32.powf(self.boost_db / 20.0);
+        frame.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain `assist` applies to each of `frames` frames of a square wave at `level`
+    fn gains(assist: &mut IntroAssist, level: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let sample = if (i / 50) % 2 == 0 { level } else { -level };
+                let mut frame = [sample];
+                assist.process(&mut frame);
+                frame[0] / sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boosts_a_quiet_intro_then_releases_at_full_level() {
+        let mut assist = IntroAssist::new(44_100, Duration::ZERO);
+        // Two seconds at -40 dBFS, well under the reference
+        let intro = gains(&mut assist, 0.01, 88_200);
+        assert!(intro[88_199] > 2.0, "intro gain {}", intro[88_199]);
+        // The body at -6 dBFS drops the boost and leaves the body as it is
+        let body = gains(&mut assist, 0.5, 88_200);
+        assert!(body[0] > 1.0);
+        assert!(
+            (body[88_199] - 1.0).abs() < 1e-3,
+            "body gain {}",
+            body[88_199]
+        );
+        // Released for good: a quiet passage later on isn't lifted again
+        let later = gains(&mut assist, 0.01, 44_100);
+        assert!(
+            (later[44_099] - 1.0).abs() < 1e-3,
+            "later gain {}",
+            later[44_099]
+        );
+    }
+
+    #[test]
+    fn leaves_a_track_started_past_the_intro_alone() {
+        let mut assist = IntroAssist::new(44_100, INTRO_WINDOW);
+        let gains = gains(&mut assist, 0.01, 44_100);
+        assert!(gains.iter().all(|&g| g == 1.0));
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+/// Peak limiter that never lets a frame exceed its ceiling.
+///
+/// Gain drops instantly on a peak and recovers over `release`; there is no look-ahead, so it
+/// is meant as a safety net rather than a loudness tool.
+pub(crate) struct Limiter {
+    /// Highest linear peak let through
+    ceiling: f32,
+    release_coeff: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    pub(crate) fn new(ceiling_db: f32, release: Duration, sample_rate: u32) -> Self {
+        Self {
+            ceiling: 10f32.powf(ceiling_db / 20.0),
+            release_coeff: (-1.0 / (release.as_secs_f32().max(1e-4) * sample_rate.max(1) as f32))
+                .exp(),
+            gain: 1.0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let target = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+        self.gain = if target < self.gain {
+            target
+        } else {
+            self.release_coeff * self.gain + (1.0 - self.release_coeff) * target
+        };
+        frame.iter_mut().for_each(|s| *s *= self.gain);
+    }
+}
+
+/// Scale the side of a stereo frame by `width`: 1.0 leaves it alone, 0.0 sums it to mono.
+/// Frames with any other channel count pass through.
+pub(crate) fn narrow(frame: &mut [f32], width: f32) {
+    if let [left, right] = frame {
+        let mid = (*left + *right) * 0.5;
+        let side = (*left - *right) * 0.5 * width;
+        (*left, *right) = (mid + side, mid - side);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::sine;
+
+    #[test]
+    fn never_exceeds_the_ceiling() {
+        let mut limiter = Limiter::new(-1.0, Duration::from_millis(200), 44_100);
+        let ceiling = 10f32.powf(-1.0 / 20.0);
+        // A sine at +6 dBFS, past full scale
+        for sample in sine(440.0, 44_100, 1, 44_100) {
+            let mut frame = [sample * 4.0];
+            limiter.process(&mut frame);
+            assert!(
+                frame[0].abs() <= ceiling + 1e-6,
+                "{} over the ceiling",
+                frame[0]
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_quiet_audio_alone() {
+        let mut limiter = Limiter::new(-1.0, Duration::from_millis(200), 44_100);
+        for sample in sine(440.0, 4_410, 1, 44_100) {
+            let mut frame = [sample];
+            limiter.process(&mut frame);
+            assert_eq!(frame[0], sample);
+        }
+    }
+
+    #[test]
+    fn collapses_to_mono_at_zero_width() {
+        let mut frame = [1.0, -0.5];
+        narrow(&mut frame, 0.0);
+        assert_eq!(frame, [0.25, 0.25]);
+
+        let mut frame = [1.0, -0.5];
+        narrow(&mut frame, 1.0);
+        assert_eq!(frame, [1.0, -0.5]);
+    }
+}
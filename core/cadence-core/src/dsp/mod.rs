@@ -0,0 +1,207 @@
+//! Sample processing stages run by the `Pipeline` on every frame
+
+pub(crate) mod biquad;
+pub(crate) mod channel_delay;
+pub(crate) mod compressor;
+pub(crate) mod dc_blocker;
+pub(crate) mod ducking;
+pub(crate) mod envelope;
+pub(crate) mod eq;
+pub(crate) mod intro_assist;
+pub(crate) mod leveler;
+pub(crate) mod limiter;
+
+use crate::layout::ChannelLayout;
+use crate::stream::InternalFormat;
+use compressor::Compression;
+use ducking::Ducking;
+use envelope::GainEnvelope;
+use eq::EqBand;
+use leveler::LoudnessLeveling;
+use std::time::Duration;
+
+/// Processing settings chosen through the `Player`, copied into each playing pipeline
+#[derive(Debug, Clone)]
+pub(crate) struct DspSettings {
+    /// Remove DC offset before anything else
+    pub dc_blocker: bool,
+    /// Gain automation keyed on track position
+    pub gain_envelope: Option<GainEnvelope>,
+    /// Fade-out over the end of the last queue track, keyed on its position; only the
+    /// track playing on, not one already stopping under a crossfade, follows it
+    pub end_fade: Option<GainEnvelope>,
+    /// Parametric equalizer bands; empty for none
+    pub eq: Vec<EqBand>,
+    /// Equalize tracks tagged with a known genre with its preset instead of `eq`
+    pub genre_eq: bool,
+    /// Real-time loudness leveling
+    pub loudness_leveling: Option<LoudnessLeveling>,
+    /// Lift quiet intros
+    pub intro_assist: bool,
+    /// Duck under the side-chain fed through `Controls::ducking_input`
+    pub ducking: Option<Ducking>,
+    /// Dynamic range compression
+    pub compressor: Option<Compression>,
+    /// Run the peak limiter even outside Bluetooth-safe mode
+    pub limiter: bool,
+    /// Narrow the stereo image and limit peaks for Bluetooth codecs; also forces the DC
+    /// blocker on
+    pub bluetooth_safe: bool,
+    /// Per output channel delay in milliseconds for speaker alignment; empty for none
+    pub channel_delays: Vec<f32>,
+    /// Per output channel polarity inversion; empty for none
+    pub invert_polarity: Vec<bool>,
+    /// Fade to silence over this long on `stop` instead of cutting off; zero for none.
+    /// Defaults to 20 ms.
+    pub stop_ramp: Duration,
+    /// Sample format Symphonia decodes into when no processing stage is running
+    pub sample_format: InternalFormat,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        Self {
+            dc_blocker: true,
+            gain_envelope: None,
+            end_fade: None,
+            eq: Vec::new(),
+            genre_eq: false,
+            loudness_leveling: None,
+            intro_assist: false,
+            ducking: None,
+            compressor: None,
+            limiter: false,
+            bluetooth_safe: false,
+            channel_delays: Vec::new(),
+            invert_polarity: Vec::new(),
+            stop_ramp: crate::DEFAULT_FADE,
+            sample_format: InternalFormat::F32,
+        }
+    }
+}
+
+impl DspSettings {
+    /// `sample_format`, or `F32` while any stage would do float maths on the samples anyway
+    pub(crate) fn decode_format(&self) -> InternalFormat {
+        if self.stage_names().is_empty() {
+            self.sample_format
+        } else {
+            InternalFormat::F32
+        }
+    }
+
+    /// The active processing stages in the order the pipeline runs them, for `format_report`
+    pub(crate) fn stage_names(&self) -> Vec<String> {
+        let mut stages = Vec::new();
+        if self.dc_blocker || self.bluetooth_safe {
+            stages.push("DC blocker".to_string());
+        }
+        if !self.eq.is_empty() {
+            stages.push(format!("EQ ({} bands)", self.eq.len()));
+        }
+        if self.intro_assist {
+            stages.push("intro assist".to_string());
+        }
+        if let Some(leveling) = self.loudness_leveling {
+            stages.push(format!("loudness leveling ({} LUFS)", leveling.target_lufs));
+        }
+        if self.ducking.is_some() {
+            stages.push("ducking".to_string());
+        }
+        if self.gain_envelope.is_some() {
+            stages.push("gain envelope".to_string());
+        }
+        if let Some(compression) = self.compressor {
+            stages.push(format!(
+                "compressor ({} dB, {}:1, {:+} dB makeup)",
+                compression.threshold_db, compression.ratio, compression.makeup_db
+            ));
+        }
+        if self.bluetooth_safe {
+            stages.push("Bluetooth width reduction".to_string());
+        }
+        if self.limiter || self.bluetooth_safe {
+            stages.push("limiter".to_string());
+        }
+        stages
+    }
+
+    /// The speaker corrections applied after volume, one line each, for `format_report`
+    pub(crate) fn output_corrections(&self, layout: &ChannelLayout) -> Vec<String> {
+        let name = |channel: usize| match layout.labels().get(channel) {
+            Some(label) => format!("{:?}", label),
+            None => format!("channel {}", channel),
+        };
+        let mut lines = Vec::new();
+        if self.channel_delays.iter().any(|&ms| ms > 0.0) {
+            let delays: Vec<_> = self
+                .channel_delays
+                .iter()
+                .enumerate()
+                .map(|(channel, ms)| format!("{} {} ms", name(channel), ms))
+                .collect();
+            lines.push(format!("Channel delays: {}", delays.join(", ")));
+        }
+        let inverted: Vec<_> = self
+            .invert_polarity
+            .iter()
+            .enumerate()
+            .filter(|(_, &invert)| invert)
+            .map(|(channel, _)| name(channel))
+            .collect();
+        if !inverted.is_empty() {
+            lines.push(format!("Polarity inverted: {}", inverted.join(", ")));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_every_active_stage_in_order() {
+        let dsp = DspSettings {
+            compressor: Some(Compression::night()),
+            bluetooth_safe: true,
+            ..Default::default()
+        };
+        let stages = dsp.stage_names();
+        assert_eq!(stages.first().map(String::as_str), Some("DC blocker"));
+        assert!(
+            stages[1].starts_with("compressor (-30 dB, 4:1"),
+            "{:?}",
+            stages
+        );
+        assert_eq!(stages[2..], ["Bluetooth width reduction", "limiter"]);
+
+        let plain = DspSettings {
+            dc_blocker: false,
+            limiter: true,
+            ..Default::default()
+        };
+        assert_eq!(plain.stage_names(), ["limiter"]);
+    }
+
+    #[test]
+    fn describes_speaker_corrections() {
+        let layout = ChannelLayout::for_channels(2);
+        assert!(DspSettings::default()
+            .output_corrections(&layout)
+            .is_empty());
+
+        let dsp = DspSettings {
+            channel_delays: vec![0.0, 1.5],
+            invert_polarity: vec![true, false],
+            ..Default::default()
+        };
+        assert_eq!(
+            dsp.output_corrections(&layout),
+            [
+                "Channel delays: FrontLeft 0 ms, FrontRight 1.5 ms",
+                "Polarity inverted: FrontLeft",
+            ]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Something the player did by itself, without a call asking for it, delivered through
+/// `Player::on_event`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    /// `failures` queue tracks in a row couldn't be opened, reaching the limit set with
+    /// `Player::set_error_skip_backoff`, so playback stopped. `last_error` describes the last.
+    TooManyErrors { failures: u32, last_error: String },
+    /// Files were added to or taken out of the queue by a `Player::watch_folder` watch
+    QueueChanged,
+    /// The queue played to its end under `QueueEndAction::Shutdown`; the app should quit
+    ShutdownRequested,
+}
+
+/// Delivers `PlayerEvent`s to subscribers
+#[derive(Default)]
+pub(crate) struct Events {
+    subscribers: Vec<Sender<PlayerEvent>>,
+}
+
+impl Events {
+    pub(crate) fn subscribe(&mut self) -> Receiver<PlayerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&mut self, event: PlayerEvent) {
+        // Drop subscribers whose receiver has gone away
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
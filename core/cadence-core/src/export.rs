@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::{Decoder, Source};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Progress is reported roughly this many times over a whole export
+const PROGRESS_STEPS: u64 = 100;
+
+/// Decode `src` and write it to `dest` as 16-bit PCM WAV
+pub fn export_to_wav<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> Result<()> {
+    export_to_wav_with_progress(src, dest, |_| {})
+}
+
+/// Like `export_to_wav`, calling `cb` with the fraction done (0.0 to 1.0) as decoding proceeds.
+///
+/// Progress is measured against the track's reported duration and is monotonic; the final
+/// call is always exactly 1.0. When the duration is unknown, only the final call is made.
+/// Decoding a whole file can take seconds, so call this off the UI thread.
+pub fn export_to_wav_with_progress<P, Q>(src: P, dest: Q, cb: impl Fn(f32)) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    let file = File::open(src).with_context(|| format!("Failed to open {:?}", src))?;
+    let decoder = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Unsupported/invalid audio: {:?}", src))?;
+
+    let spec = WavSpec {
+        channels: decoder.channels(),
+        sample_rate: decoder.sample_rate(),
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let total_samples = decoder
+        .total_duration()
+        .map(|d| (d.as_secs_f64() * spec.sample_rate as f64 * spec.channels as f64) as u64);
+    let step = total_samples.map(|total| (total / PROGRESS_STEPS).max(1));
+
+    let mut writer =
+        WavWriter::create(dest, spec).with_context(|| format!("Failed to create {:?}", dest))?;
+    let mut written = 0u64;
+    for sample in decoder {
+        writer.write_sample(sample)?;
+        written += 1;
+        if let (Some(total), Some(step)) = (total_samples, step) {
+            if written.is_multiple_of(step) {
+                // Reported durations can be slightly short; never claim to be done early
+                cb((written as f64 / total as f64).min(0.99) as f32);
+            }
+        }
+    }
+    writer
+        .finalize()
+        .with_context(|| format!("Failed to finalize {:?}", dest))?;
+    cb(1.0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+    use std::cell::RefCell;
+
+    #[test]
+    fn reports_increasing_progress_up_to_done() {
+        let src = TempFile::wav(&sine(440.0, 44_100 * 3, 2, 44_100), 2, 44_100);
+        let dest = TempFile::new("wav");
+        let progress = RefCell::new(Vec::new());
+        export_to_wav_with_progress(src.path(), dest.path(), |f| progress.borrow_mut().push(f))
+            .unwrap();
+
+        let progress = progress.into_inner();
+        assert!(
+            progress.len() >= PROGRESS_STEPS as usize / 2,
+            "{:?}",
+            progress
+        );
+        assert!(progress.windows(2).all(|w| w[0] <= w[1]), "{:?}", progress);
+        assert_eq!(progress.last(), Some(&1.0));
+        assert!(progress[progress.len() - 2] > 0.95);
+
+        let exported = hound::WavReader::open(dest.path()).unwrap();
+        assert_eq!(exported.spec().bits_per_sample, 16);
+        assert_eq!(exported.duration(), 44_100 * 3);
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: u32 = 5;
+/// How long to wait for a connection or for the server to send more data
+const TIMEOUT: Duration = Duration::from_secs(15);
+/// Size of the blocks read ahead of the decoder
+const CHUNK_LEN: usize = 16 * 1024;
+/// Blocks read ahead of the decoder, about 1 MiB
+const READ_AHEAD: usize = 64;
+
+/// Whether `location` looks like something `open` should handle rather than a file path
+pub(crate) fn is_url(location: &str) -> bool {
+    let lower = location.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// The body of an HTTP response, downloaded on a thread of its own while it's being read
+pub(crate) struct HttpStream {
+    /// Behind a mutex only so the stream is `Sync`, as Symphonia requires
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    len: Option<u64>,
+}
+
+/// Response to a GET of `url`, following redirects, and its Content-Type if given
+pub(crate) fn open(url: &str) -> Result<(HttpStream, Option<String>)> {
+    let agent = ureq::AgentBuilder::new()
+        .redirects(MAX_REDIRECTS)
+        .timeout_connect(TIMEOUT)
+        .timeout_read(TIMEOUT)
+        .user_agent("cadence")
+        .build();
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => anyhow::bail!("HTTP {} from {}", status, url),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", url)),
+    };
+    // Not set for chunked responses
+    let len = response
+        .header("content-length")
+        .and_then(|len| len.parse().ok());
+    let content_type = response.header("content-type").map(str::to_string);
+    Ok((HttpStream::spawn(response.into_reader(), len), content_type))
+}
+
+impl HttpStream {
+    /// Start downloading `body` in the background, up to `READ_AHEAD` chunks ahead
+    fn spawn(mut body: Box<dyn Read + Send + Sync>, len: Option<u64>) -> Self {
+        let (tx, rx) = mpsc::sync_channel(READ_AHEAD);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_LEN];
+            let read = match body.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            chunk.truncate(read);
+            // The stream was dropped; stop downloading
+            if tx.send(Ok(chunk)).is_err() {
+                return;
+            }
+        });
+        Self {
+            chunks: Mutex::new(rx),
+            chunk: Vec::new(),
+            pos: 0,
+            len,
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.chunks.get_mut().recv() {
+                Ok(chunk) => self.chunk = chunk?,
+                // Download finished
+                Err(_) => return Ok(0),
+            }
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "HTTP streams can't be seeked",
+        ))
+    }
+}
+
+impl MediaSource for HttpStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::SymphoniaDecoder;
+    use crate::testutil::{serve, sine, TempFile};
+    use std::sync::Arc;
+    use symphonia::core::probe::Hint;
+
+    /// One second of 8 kHz mono WAV
+    fn wav() -> Vec<u8> {
+        let file = TempFile::wav(&sine(440.0, 8_000, 1, 8_000), 1, 8_000);
+        std::fs::read(file.path()).unwrap()
+    }
+
+    fn download(url: &str) -> Result<(Vec<u8>, Option<String>, Option<u64>)> {
+        let (mut stream, content_type) = open(url)?;
+        let len = stream.byte_len();
+        let mut body = Vec::new();
+        stream.read_to_end(&mut body)?;
+        Ok((body, content_type, len))
+    }
+
+    #[test]
+    fn downloads_plain_redirected_and_chunked_responses() {
+        let body = wav();
+        let base = serve(body.clone());
+
+        let (plain, content_type, len) = download(&format!("{}/track.wav", base)).unwrap();
+        assert_eq!(plain, body);
+        assert_eq!(content_type.as_deref(), Some("audio/wav"));
+        assert_eq!(len, Some(body.len() as u64));
+
+        let (moved, _, _) = download(&format!("{}/moved", base)).unwrap();
+        assert_eq!(moved, body);
+
+        let (chunked, _, len) = download(&format!("{}/chunked", base)).unwrap();
+        assert_eq!(chunked, body);
+        assert_eq!(len, None);
+    }
+
+    #[test]
+    fn reports_http_errors() {
+        let base = serve(Vec::new());
+        let err = open(&format!("{}/missing", base)).err().unwrap();
+        assert!(err.to_string().contains("404"), "{}", err);
+    }
+
+    #[test]
+    fn decodes_while_streaming() {
+        let base = serve(wav());
+        let (stream, _) = open(&format!("{}/chunked", base)).unwrap();
+        let mut hint = Hint::new();
+        hint.mime_type("audio/x-wav");
+        let decoder = SymphoniaDecoder::open_stream(Box::new(stream), &hint, "test").unwrap();
+        assert_eq!((decoder.channels(), decoder.sample_rate()), (1, 8_000));
+        let source =
+            crate::stream::SymphoniaSource::new(Arc::new(Mutex::new(decoder)), Arc::default());
+        assert_eq!(source.count(), 8_000);
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+
+/// What a single output channel feeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ChannelLabel {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+    /// A channel with no standard speaker position, by index
+    Aux(u16),
+}
+
+/// The speaker position of each output channel, in channel order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelLayout {
+    labels: Vec<ChannelLabel>,
+}
+
+impl ChannelLayout {
+    /// A layout with explicit labels, one per channel
+    pub fn new(labels: Vec<ChannelLabel>) -> Self {
+        Self { labels }
+    }
+
+    /// The conventional layout for a channel count, in WAVE/SMPTE channel order.
+    ///
+    /// Mono is treated as a single center speaker; counts without a common layout are front
+    /// left/right followed by auxiliary channels.
+    pub fn for_channels(channels: u16) -> Self {
+        use ChannelLabel::*;
+        let labels = match channels {
+            1 => vec![FrontCenter],
+            2 => vec![FrontLeft, FrontRight],
+            3 => vec![FrontLeft, FrontRight, FrontCenter],
+            4 => vec![FrontLeft, FrontRight, BackLeft, BackRight],
+            5 => vec![FrontLeft, FrontRight, FrontCenter, SideLeft, SideRight],
+            6 => vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight,
+            ],
+            8 => vec![
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+                SideLeft,
+                SideRight,
+            ],
+            n => {
+                let mut labels = vec![FrontLeft, FrontRight];
+                labels.extend((2..n).map(Aux));
+                labels.truncate(n as usize);
+                labels
+            }
+        };
+        Self { labels }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.labels.len() as u16
+    }
+
+    pub fn labels(&self) -> &[ChannelLabel] {
+        &self.labels
+    }
+
+    /// Index of the channel carrying `label`, if the layout has one
+    pub fn position(&self, label: ChannelLabel) -> Option<usize> {
+        self.labels.iter().position(|&l| l == label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_six_channels_to_5_1() {
+        use ChannelLabel::*;
+        let layout = ChannelLayout::for_channels(6);
+        assert_eq!(layout.channels(), 6);
+        assert_eq!(
+            layout.labels(),
+            [
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight
+            ]
+        );
+        assert_eq!(layout.position(LowFrequency), Some(3));
+        assert_eq!(layout.position(BackLeft), None);
+    }
+
+    #[test]
+    fn pads_uncommon_counts_with_aux_channels() {
+        use ChannelLabel::*;
+        assert_eq!(
+            ChannelLayout::for_channels(7).labels(),
+            [
+                FrontLeft,
+                FrontRight,
+                Aux(2),
+                Aux(3),
+                Aux(4),
+                Aux(5),
+                Aux(6)
+            ]
+        );
+    }
+}
@@ -1,14 +1,120 @@
+mod ab_loop;
+mod analysis;
+#[cfg(feature = "tokio")]
+mod async_player;
+mod cache;
+mod chapters;
+mod dsp;
+mod events;
+mod export;
+mod http;
+mod layout;
+mod library;
+mod lineup;
+mod output;
+mod pcm_sink;
+mod pipeline;
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+mod power;
+mod probe;
+mod queue;
+mod retry;
+mod signals;
+mod sleep_timer;
+mod spectrum;
+mod store;
+mod stream;
+mod tee;
+#[cfg(test)]
+mod testutil;
+mod track_end;
+mod upsample;
+mod watch;
+
+pub use analysis::{detect_track_boundaries, detect_track_boundaries_with, BoundaryDetection};
+#[cfg(feature = "tokio")]
+pub use async_player::{AsyncPlayer, Reply};
+pub use cache::DecodeCachePolicy;
+pub use chapters::read_chapter_ad_markers;
+pub use dsp::compressor::Compression;
+pub use dsp::ducking::DuckingInput;
+pub use dsp::eq::EqBand;
+pub use dsp::leveler::LoudnessLeveling;
+pub use events::PlayerEvent;
+pub use export::{export_to_wav, export_to_wav_with_progress};
+pub use layout::{ChannelLabel, ChannelLayout};
+pub use library::{export_library_json, LibraryEntry};
+pub use pcm_sink::PcmSink;
+pub use signals::TestSignal;
+pub use spectrum::{AnalyzerSettings, SpectrumCallback};
+pub use store::{Bookmark, BookmarkId};
+pub use stream::InternalFormat;
+pub use watch::WatchHandle;
+
 use anyhow::{Context, Result};
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use cache::PcmCache;
+use dsp::ducking::Ducking;
+use dsp::envelope::GainEnvelope;
+use dsp::eq::TrackBands;
+use events::Events;
+use lineup::{Arrival, Lined, Lineup, Loader, Plan, Prepared};
+use output::{Output, Target};
+use parking_lot::Mutex;
+use pipeline::{Controls, OutputStage, Pipeline};
+use probe::Probe;
+use queue::Queue;
+use retry::IoRetry;
+use rodio::cpal::traits::HostTrait;
+use rodio::{cpal, DeviceTrait, Sink, Source};
 use serde::Serialize;
-use std::path::PathBuf;
-use std::time::Instant;
-use std::{fs::File, io::BufReader};
+use sleep_timer::SleepTimer;
+use spectrum::Analyzer;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use store::Store;
+use stream::{SharedDecoder, SymphoniaDecoder, SymphoniaSource};
+use symphonia::core::probe::Hint;
+use tee::Tee;
+use track_end::NotifyOnEnd;
+use upsample::Upsample;
+use watch::FolderChange;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TrackInfo {
     pub path: PathBuf,
     pub duration_ms: Option<u64>,
+    /// Format the decoder produces, before any resampling for the output
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Tags read from the file when it was loaded; `None` where the file has no such tag
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Position on the album
+    pub track_number: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+}
+
+impl TrackInfo {
+    /// Info for a file whose format and tags haven't been filled in yet
+    fn untagged(path: PathBuf, duration_ms: Option<u64>) -> Self {
+        Self {
+            path,
+            duration_ms,
+            sample_rate: None,
+            channels: None,
+            title: None,
+            artist: None,
+            album: None,
+            track_number: None,
+            year: None,
+            genre: None,
+        }
+    }
 }
 
 /// Represents the current state of a playing track
@@ -20,23 +126,37 @@ pub struct CurrentTrack {
     pub last_playback_time: Option<Instant>,
     /// Position in ms at the time of last playback start/pause
     pub last_playback_position: u64,
+    /// Track time that passes per unit of wall-clock time
+    speed: f32,
+    /// Where the audio picks up again and where it goes back, in ms, while it goes around an
+    /// A-B loop
+    looping: Option<(u64, u64)>,
 }
 
 impl CurrentTrack {
-    /// Create a new CurrentTrack starting from position 0
-    fn new(info: TrackInfo) -> Self {
+    /// Create a new CurrentTrack starting from position 0, playing at `speed`
+    fn new(info: TrackInfo, speed: f32) -> Self {
         Self {
             info,
             last_playback_time: Some(Instant::now()),
             last_playback_position: 0,
+            speed,
+            looping: None,
         }
     }
 
     /// Get the current playback position in milliseconds
     pub fn current_position_ms(&self) -> u64 {
-        match self.last_playback_time {
-            Some(instant) => self.last_playback_position + instant.elapsed().as_millis() as u64,
+        let position = match self.last_playback_time {
+            Some(instant) => {
+                let elapsed = instant.elapsed().as_secs_f64() * 1000.0 * self.speed as f64;
+                self.last_playback_position + elapsed as u64
+            }
             None => self.last_playback_position,
+        };
+        match self.looping {
+            Some((resume_ms, end_ms)) => ab_loop::wrap_position(position, resume_ms, end_ms),
+            None => position,
         }
     }
 
@@ -51,31 +171,512 @@ impl CurrentTrack {
         self.last_playback_time = Some(Instant::now());
     }
 
-    /// Update position and reset time tracking (used after seek)
+    /// Whether time tracking is currently stopped
+    fn is_paused(&self) -> bool {
+        self.last_playback_time.is_none()
+    }
+
+    /// Change the playback speed from now on, keeping the position reached so far
+    fn set_speed(&mut self, speed: f32) {
+        self.set_position(self.current_position_ms());
+        self.speed = speed;
+    }
+
+    /// Update position and reset time tracking (used after seek); a paused track stays paused
     fn set_position(&mut self, position_ms: u64) {
         self.last_playback_position = position_ms;
-        self.last_playback_time = Some(Instant::now());
+        if !self.is_paused() {
+            self.last_playback_time = Some(Instant::now());
+        }
+    }
+}
+
+/// What the loaded track supports, so a frontend can enable the matching controls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Capabilities {
+    /// Position can be changed with `seek`
+    pub seekable: bool,
+    /// Total duration is known
+    pub has_duration: bool,
+    /// The container carries chapter/cue markers
+    pub has_chapters: bool,
+    /// Embedded cover art is present
+    pub has_cover: bool,
+    pub channels: u16,
+    /// Seeks land on the exact sample rather than approximately
+    pub accurate_seek: bool,
+}
+
+/// Cover art embedded in a track file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Artwork {
+    /// Media type of `data`, e.g. "image/jpeg"
+    pub mime: String,
+    /// The image file as stored, ready for a data URL
+    pub data: Vec<u8>,
+}
+
+/// An output device the host offers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AudioDevice {
+    /// Name as reported by the host, unique enough to pick the device by
+    pub name: String,
+    /// The system's current default output
+    pub is_default: bool,
+}
+
+/// Position, duration and remaining time read together, so they always agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct PlaybackTimes {
+    pub position_ms: u64,
+    /// `None` when the track's length is unknown
+    pub duration_ms: Option<u64>,
+    /// `None` when the track's length is unknown
+    pub remaining_ms: Option<u64>,
+}
+
+/// What the player is doing, for showing the right transport controls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    /// Stopped, or played to the end; `play` continues a stopped track from where it stopped
+    /// and starts one that played to the end from the beginning
+    Stopped,
+    /// Nothing has been loaded that `play` could start
+    Empty,
+}
+
+/// What a seek does to a paused track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SeekPausedBehavior {
+    /// Move the position but keep the track paused
+    #[default]
+    StayPaused,
+    /// Resume playback from the new position
+    Resume,
+}
+
+/// What happens to playback when the desktop session locks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockBehavior {
+    /// Keep playing, e.g. for audiobooks
+    #[default]
+    Continue,
+    Pause,
+}
+
+/// What the queue does when a track ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatMode {
+    /// Play through the queue once and stop after the last track
+    #[default]
+    Off,
+    /// Play the current track over and over
+    One,
+    /// Go back to the first track after the last
+    All,
+}
+
+/// What happens once the last track of the queue has played, when `RepeatMode` doesn't
+/// go on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueEndAction {
+    /// Stay stopped at the end
+    #[default]
+    Stop,
+    /// Go back to the first track, as `RepeatMode::All` does
+    RepeatAll,
+    /// Ask the app to quit, with `PlayerEvent::ShutdownRequested`
+    Shutdown,
+    /// Suspend the machine, for falling asleep to a playlist
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    SleepSystem,
+}
+
+/// What `Player::enqueue` does when the queue is at its `Player::set_max_queue_length`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueOverflow {
+    /// Return an error and leave the queue as it was
+    #[default]
+    Reject,
+    /// Make room by taking out the tracks that played longest ago. Tracks yet to play are
+    /// never dropped, so the queue rejects as `Reject` does once nothing played is left.
+    DropOldest,
+}
+
+/// Which changes of track `Player::set_crossfade` overlaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossfadeMode {
+    /// Tracks that end by themselves as well as skips with `next`
+    #[default]
+    Always,
+    /// Only skips with `next`; tracks that end by themselves run straight into the next
+    ManualSkipOnly,
+    /// None, leaving the crossfade length set for later
+    Never,
+}
+
+/// Which ReplayGain tag sets the loudness of each track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayGainMode {
+    /// Play tracks at their own level
+    #[default]
+    Off,
+    /// Even out every track to the same loudness
+    Track,
+    /// Keep the level differences within an album, falling back to the track gain for files
+    /// without an album gain
+    Album,
+}
+
+/// When to narrow the stereo image and limit peaks to avoid Bluetooth codec artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BluetoothSafeMode {
+    #[default]
+    Off,
+    /// Only when the output device's name suggests a Bluetooth sink
+    Auto,
+    /// Always, e.g. when the device name doesn't give it away
+    On,
+}
+
+/// Range covered by the volume control; the bottom of the slider is this far below full
+const VOLUME_RANGE_DB: f32 = 60.0;
+
+/// Default for `Player::set_restart_window`
+const DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(3);
+
+/// Blend across the A-B loop seam, hiding the click of the jump
+const AB_LOOP_CROSSFADE: Duration = Duration::from_millis(10);
+
+/// How long before a sleep timer fires its fade-out starts
+const SLEEP_FADE: Duration = Duration::from_secs(30);
+
+/// A saved position this close to the end of its track counts as finished
+const RESUME_END_MARGIN_MS: u64 = 10_000;
+
+/// A track whose output runs dry further than this from its end stopped short, rather than
+/// playing out, as far as `Player::set_underrun_recovery` is concerned
+const UNDERRUN_END_MARGIN_MS: u64 = 2_000;
+
+/// Default for `Player::set_fade`; long enough to hide a click, too short to hear as a fade
+pub(crate) const DEFAULT_FADE: Duration = Duration::from_millis(20);
+
+/// Cut `source`, which starts `at` into its track, off at `end` of the track, if given
+fn cut_at(source: BoxedSource, end: Option<Duration>, at: Duration) -> BoxedSource {
+    match end {
+        Some(end) => Box::new(source.take_duration(end.saturating_sub(at))),
+        None => source,
+    }
+}
+
+/// Sink gain for a linear 0.0-1.0 volume slider, spread evenly in dB so it sounds even
+fn volume_gain(volume: f32) -> f32 {
+    if volume <= 0.0 {
+        0.0
+    } else {
+        10f32.powf(VOLUME_RANGE_DB * (volume - 1.0) / 20.0)
     }
 }
 
+/// `path` with symlinks and relative parts resolved, or as given if it can't be
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Any decoded source the player can hand to a `Pipeline`
+type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+/// How a track was loaded, so `play` can load it again the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoadMethod {
+    /// `load_and_play`
+    Rodio,
+    /// `load_and_play_symphonia`
+    Symphonia,
+    /// `load_and_play_url`
+    Url,
+}
+
+/// The track `stop` ended, for `play` to pick up again
+struct StoppedTrack {
+    info: TrackInfo,
+    /// Where it was stopped
+    position_ms: u64,
+    method: LoadMethod,
+}
+
 pub struct Player {
-    _stream: OutputStream,
-    _handle: OutputStreamHandle,
+    /// The open output device; `None` while released for being idle
+    output: Option<Output>,
     sink: Sink,
     /// Current track state, if any
     current_track: Option<CurrentTrack>,
+    /// State shared with the sources playing in the sink
+    controls: Arc<Controls>,
+    /// Channel count and sample rate of the source currently in the sink
+    format: Option<(u16, u32)>,
+    /// Whether seeking a paused track resumes it
+    seek_paused_behavior: SeekPausedBehavior,
+    /// How newly loaded tracks are decoded
+    decode_cache: DecodeCachePolicy,
+    /// Fully decoded copy of the current track, when the cache policy made one
+    cache: Option<PcmCache>,
+    /// Packet-by-packet decoder of the current track, when loaded with
+    /// `load_and_play_symphonia`
+    symphonia: Option<SharedDecoder>,
+    /// Feature availability of the current track
+    capabilities: Capabilities,
+    /// Short codec name of the current track, if the probe found one
+    codec: Option<String>,
+    /// A generated test signal is in the sink rather than a track
+    test_signal: bool,
+    /// Track that was playing when `stop` was last called, so `play` can start it again
+    stopped_track: Option<StoppedTrack>,
+    /// Set while an external interruption (e.g. a call) holds playback; the flag says
+    /// whether playback should continue once it ends
+    interrupted: Option<bool>,
+    /// What `screen_locked` does
+    lock_behavior: LockBehavior,
+    /// Length of the blend between the old and new position on seek; zero seeks hard
+    seek_crossfade: Duration,
+    /// Slider position set through `set_volume`, before the perceptual curve
+    volume: f32,
+    /// Output silenced by `mute`; `volume` is kept for `unmute`
+    muted: bool,
+    /// Playback speed factor set through `set_speed`
+    speed: f32,
+    /// Retries for transient read errors on track files
+    io_retry: IoRetry,
+    /// Skip applause at the start and end of newly loaded tracks
+    applause_trim: bool,
+    /// Where the current track's playback is cut short, e.g. before trailing applause
+    play_until: Option<Duration>,
+    /// Which ReplayGain tag adjusts the volume
+    replaygain: ReplayGainMode,
+    /// Added to the ReplayGain adjustment, and all that's applied to untagged tracks, in dB
+    replaygain_preamp_db: f32,
+    /// ReplayGain track and album gains of the current track, in dB
+    replaygain_tags: (Option<f32>, Option<f32>),
+    /// JSON file of positions and bookmarks kept by `save_position` and `add_bookmark`
+    position_store: PathBuf,
+    /// Thread stopping playback for `set_sleep_timer`
+    sleep_timer: Option<SleepTimer>,
+    /// Region `set_ab_loop` repeats
+    ab_loop: Option<ab_loop::Region>,
+    /// Ranges of the current track that `poll_skip_markers` seeks past, sorted by start
+    skip_markers: Vec<(Duration, Duration)>,
+    /// Leave newly loaded tracks paused at the start instead of playing them
+    start_paused: bool,
+    /// Release the output device after being paused or stopped this long
+    close_on_silence: Option<Duration>,
+    /// When the player was first seen idle by `release_idle_output`
+    idle_since: Option<Instant>,
+    /// Open the output device at each track's own sample rate when it supports it
+    auto_device_rate: bool,
+    /// Sample rate requested for the output device instead of its default
+    device_rate: Option<u32>,
+    /// Factor asked of `set_upsample_factor`
+    upsample_factor: u8,
+    /// Factor the current track is upsampled by, which the device may have capped
+    upsample: u8,
+    /// Channel count the output device was opened with
+    output_channels: u16,
+    /// Sample rate the output device was opened with
+    output_sample_rate: u32,
+    /// Output chosen by the constructor
+    target: Target,
+    /// Name of the output device, if the host reports one
+    output_device_name: Option<String>,
+    /// Highest sink gain allowed on each output device, by device name
+    device_max_gain: HashMap<String, f32>,
+    /// User-provided labels for the output channels, replacing the conventional layout
+    output_layout: Option<ChannelLayout>,
+    queue: Queue,
+    /// Skip enqueueing files already in the queue
+    auto_dedup: bool,
+    /// Most tracks the queue takes, and what enqueuing more does
+    max_queue_length: Option<usize>,
+    queue_overflow: QueueOverflow,
+    /// Wait between skipping queue tracks that fail to open, and how many may fail in a row
+    error_skip: Option<(Duration, u32)>,
+    /// Pick up again where the output ran dry when it does well before the end of the track
+    underrun_recovery: bool,
+    /// Queue tracks that failed to open in a row
+    failures: u32,
+    /// When to skip past the queue track that just failed to open
+    skip_at: Option<Instant>,
+    events: Events,
+    /// Files coming and going in the folders passed to `watch_folder`
+    folder_changes: (Sender<FolderChange>, Receiver<FolderChange>),
+    repeat: RepeatMode,
+    /// Overlap between consecutive queued tracks
+    crossfade: Option<Duration>,
+    crossfade_mode: CrossfadeMode,
+    queue_end_action: QueueEndAction,
+    /// Fade out over the end of the last queue track this long
+    queue_end_fade: Duration,
+    /// The current track fades out as the last of the queue
+    fading_out_queue: bool,
+    /// Play on from one track of an album into the next without crossfade or gap
+    no_crossfade_albums: bool,
+    /// Track whose end was found to run into the same album, so it doesn't crossfade
+    album_continues: Option<PathBuf>,
+    /// Sink still playing out the previous track during a crossfade
+    fading: Option<Sink>,
+    /// Open queued tracks ahead of time and join them sample to sample
+    gapless: bool,
+    /// Lines up the queue entries behind the playing one
+    lineup: Lineup,
+    /// Fade applied to the start of the next track loaded, in place of `fade`
+    fade_in: Option<Duration>,
+    /// Fade in over this long on load; `DspSettings::stop_ramp` holds the fade out on stop
+    fade: Duration,
+    /// How long into a track `previous` goes back a track rather than restarting it
+    restart_window: Duration,
+    /// Thread feeding the `set_analyzer` callback
+    analyzer: Option<Analyzer>,
 }
 
 impl Player {
+    /// List the output devices of the default host, e.g. for a device picker. Doesn't need a
+    /// player. A host without output devices gives an empty list, and devices that can't
+    /// report a name are left out. The default device is always included when there is one.
+    pub fn output_devices() -> Result<Vec<AudioDevice>> {
+        let host = cpal::default_host();
+        let default = host.default_output_device().and_then(|d| d.name().ok());
+        let mut devices: Vec<AudioDevice> = host
+            .output_devices()
+            .context("Failed to list output devices")?
+            .filter_map(|device| device.name().ok())
+            .map(|name| AudioDevice {
+                is_default: default.as_ref() == Some(&name),
+                name,
+            })
+            .collect();
+        // Some hosts (e.g. ALSA with a custom default) don't list the default among the rest
+        if let Some(name) = default.filter(|_| !devices.iter().any(|d| d.is_default)) {
+            devices.insert(
+                0,
+                AudioDevice {
+                    name,
+                    is_default: true,
+                },
+            );
+        }
+        Ok(devices)
+    }
+
+    /// Peaks of `path` for drawing a waveform: `buckets` (min, max) pairs in -1.0..=1.0,
+    /// each covering an equal slice of the track, downmixed to mono. The number of buckets
+    /// doesn't depend on the track's length.
+    ///
+    /// This decodes the whole file and blocks until done, so run it off the UI thread.
+    pub fn waveform(path: &Path, buckets: usize) -> Result<Vec<(f32, f32)>> {
+        analysis::waveform(path, buckets)
+    }
+
+    /// Create a player on the system's default output device, opened at its default
+    /// format
     pub fn new() -> Result<Self> {
-        let (stream, handle) =
-            OutputStream::try_default().context("No default output device available")?;
-        let sink = Sink::try_new(&handle).context("Failed to create sink")?;
+        Self::open(Target::Default)
+    }
+
+    /// Create a player on the output device named `name`, as listed by `output_devices`,
+    /// e.g. to play through a USB DAC rather than the system default. The name must match
+    /// exactly, case included.
+    ///
+    /// The player stays on that device, reopening it after releasing it for being idle,
+    /// even if the system default changes.
+    pub fn new_with_device(name: &str) -> Result<Self> {
+        Self::open(Target::Named(name.to_string()))
+    }
+
+    /// Create a player without an output device. Everything plays as it would on a device,
+    /// in real time and through the same processing, but the sound goes nowhere except to
+    /// `set_tee_output` and `set_pcm_sink`, e.g. for a server feeding a network encoder, or
+    /// for tests on machines without audio hardware.
+    ///
+    /// The output is stereo at 44.1 kHz, and takes any other rate `set_auto_device_rate`
+    /// asks for. It has no name.
+    pub fn new_headless() -> Result<Self> {
+        Self::open(Target::Headless)
+    }
+
+    fn open(target: Target) -> Result<Self> {
+        let (output, sink, (output_channels, output_sample_rate)) =
+            output::open_output(&target, None)?;
+        let output_device_name = output::device_name(&target);
+        let controls = Arc::new(Controls::default());
         Ok(Self {
-            _stream: stream,
-            _handle: handle,
+            output: Some(output),
             sink,
             current_track: None,
+            controls,
+            format: None,
+            seek_paused_behavior: SeekPausedBehavior::default(),
+            decode_cache: DecodeCachePolicy::default(),
+            cache: None,
+            symphonia: None,
+            capabilities: Capabilities::default(),
+            codec: None,
+            test_signal: false,
+            stopped_track: None,
+            interrupted: None,
+            lock_behavior: LockBehavior::default(),
+            seek_crossfade: Duration::ZERO,
+            volume: 1.0,
+            muted: false,
+            speed: 1.0,
+            io_retry: IoRetry::default(),
+            applause_trim: false,
+            play_until: None,
+            replaygain: ReplayGainMode::default(),
+            replaygain_preamp_db: 0.0,
+            replaygain_tags: (None, None),
+            position_store: store::default_path(),
+            sleep_timer: None,
+            ab_loop: None,
+            skip_markers: Vec::new(),
+            start_paused: false,
+            close_on_silence: None,
+            idle_since: None,
+            auto_device_rate: false,
+            device_rate: None,
+            upsample_factor: 1,
+            upsample: 1,
+            output_channels,
+            output_sample_rate,
+            target,
+            output_device_name,
+            device_max_gain: HashMap::new(),
+            output_layout: None,
+            queue: Queue::default(),
+            auto_dedup: false,
+            max_queue_length: None,
+            queue_overflow: QueueOverflow::default(),
+            error_skip: None,
+            underrun_recovery: false,
+            failures: 0,
+            skip_at: None,
+            events: Events::default(),
+            folder_changes: mpsc::channel(),
+            repeat: RepeatMode::default(),
+            crossfade: None,
+            crossfade_mode: CrossfadeMode::default(),
+            queue_end_action: QueueEndAction::default(),
+            queue_end_fade: Duration::ZERO,
+            fading_out_queue: false,
+            no_crossfade_albums: false,
+            album_continues: None,
+            fading: None,
+            gapless: true,
+            lineup: Lineup::new()?,
+            fade_in: None,
+            fade: DEFAULT_FADE,
+            restart_window: DEFAULT_RESTART_WINDOW,
+            analyzer: None,
         })
     }
 
@@ -92,83 +693,4005 @@ impl Player {
             .unwrap_or(0)
     }
 
-    pub fn load_and_play(&mut self, path: PathBuf) -> Result<TrackInfo> {
-        // Open once for duration using the same decoder we'll use for playback.
-        let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
-        let src = Decoder::new(BufReader::new(file))
-            .with_context(|| format!("Unsupported/invalid audio: {:?}", path))?;
-        let dur = src.total_duration().map(|d| d.as_millis() as u64);
+    /// Elapsed milliseconds of the current track, or `None` when nothing is loaded.
+    ///
+    /// Stops advancing while paused and is cleared by `stop`. The position is measured by the
+    /// wall clock from the last load, seek or resume rather than by counting samples, so it
+    /// can drift from the audio by a few tens of milliseconds.
+    pub fn position_ms(&self) -> Option<u64> {
+        self.current_track.as_ref().map(|t| t.current_position_ms())
+    }
 
-        let info = TrackInfo {
-            path,
-            duration_ms: dur,
+    /// Position, duration and remaining time of the current track in one snapshot; all zero
+    /// or unknown when nothing is loaded
+    pub fn times(&self) -> PlaybackTimes {
+        let Some(track) = &self.current_track else {
+            return PlaybackTimes::default();
         };
+        let duration_ms = track.info.duration_ms;
+        // Wall-clock position can run slightly past the decoded length
+        let position_ms = duration_ms.map_or(track.current_position_ms(), |d| {
+            track.current_position_ms().min(d)
+        });
+        PlaybackTimes {
+            position_ms,
+            duration_ms,
+            remaining_ms: duration_ms.map(|d| d - position_ms),
+        }
+    }
 
-        self.sink.clear();
-        self.sink.append(src);
-        self.sink.play();
+    /// Bitrate of the current track in bits per second, averaged over the last couple of
+    /// seconds decoded, so it follows a VBR file or a stream as it plays. Only tracks decoded
+    /// packet by packet report it, i.e. those from `load_and_play_symphonia` and
+    /// `load_and_play_url`, and only for lossy codecs; `None` otherwise.
+    pub fn current_bitrate(&self) -> Option<u32> {
+        self.symphonia.as_ref()?.lock().bitrate()
+    }
+
+    pub fn load_and_play(&mut self, path: PathBuf) -> Result<TrackInfo> {
+        let track = self.prepare(path)?;
+        self.format = Some(track.format);
+        self.play_until = track.play_until;
+        self.cache = track.cache;
+        self.symphonia = None;
+        self.start_track(track.info, track.format, track.source, track.start)
+    }
+
+    /// Open `path` the way `load_and_play` plays it, leaving the current track alone
+    fn prepare(&self, path: PathBuf) -> Result<Prepared> {
+        self.loader().prepare(path)
+    }
+
+    /// What opening and processing a track depends on, for use off this thread
+    fn loader(&self) -> Loader {
+        Loader {
+            controls: self.controls.clone(),
+            output_channels: self.output_channels,
+            decode_cache: self.decode_cache,
+            io_retry: self.io_retry,
+            applause_trim: self.applause_trim,
+            upsample: self.upsample,
+        }
+    }
+
+    /// Like `load_and_play`, but decodes with Symphonia one packet at a time.
+    ///
+    /// Playback starts as soon as the first packet is decoded, memory use stays flat however
+    /// long the track is, and `seek` lands on the exact sample without decoding from the
+    /// start. The decode cache policy doesn't apply to tracks loaded this way.
+    pub fn load_and_play_symphonia(&mut self, path: PathBuf) -> Result<TrackInfo> {
+        let mut decoder = SymphoniaDecoder::open(&path, self.io_retry)?;
+        let duration_ms = decoder.duration().map(|d| d.as_millis() as u64);
+        let info = TrackInfo::untagged(path, duration_ms);
+
+        let format = (decoder.channels(), decoder.sample_rate());
+        self.format = Some(format);
+
+        let (start, play_until) = self.loader().detect_trim(&info.path);
+        self.play_until = play_until;
+        if !start.is_zero() {
+            decoder.seek(start)?;
+        }
+        let shared = Arc::new(Mutex::new(decoder));
+        let source = Box::new(SymphoniaSource::new(shared.clone(), self.controls.clone()));
+        let source = self.limit_to_play_until(source, start);
+
+        self.cache = None;
+        self.symphonia = Some(shared);
+        self.start_track(info, format, source, start)
+    }
 
-        self.current_track = Some(CurrentTrack::new(info.clone()));
+    /// Stream `url` over HTTP or HTTPS and decode it with Symphonia as it downloads.
+    ///
+    /// Playback starts once enough has arrived to decode the first packet, so this suits
+    /// internet radio as well as remote files. The stream can't be seeked: `duration_ms` is
+    /// `None`, `capabilities().seekable` is false and `seek` returns an error. Redirects are
+    /// followed, including between `http://` and `https://`.
+    pub fn load_and_play_url(&mut self, url: &str) -> Result<TrackInfo> {
+        let (stream, content_type) = http::open(url)?;
+        let mut hint = Hint::new();
+        if let Some(mime) = &content_type {
+            // Drop parameters such as "; charset=..."
+            hint.mime_type(mime.split(';').next().unwrap_or_default().trim());
+        }
+        let file_name = url.split(['?', '#']).next().unwrap_or_default();
+        if let Some((_, ext)) = file_name
+            .rsplit_once('/')
+            .and_then(|(_, f)| f.rsplit_once('.'))
+        {
+            hint.with_extension(ext);
+        }
+        let decoder = SymphoniaDecoder::open_stream(Box::new(stream), &hint, url)?;
+        let info = TrackInfo::untagged(PathBuf::from(url), None);
+
+        let format = (decoder.channels(), decoder.sample_rate());
+        self.format = Some(format);
+
+        // Finding where applause ends would mean downloading the whole stream first
+        self.play_until = None;
+        let shared = Arc::new(Mutex::new(decoder));
+        let source = Box::new(SymphoniaSource::new(shared.clone(), self.controls.clone()));
+
+        self.cache = None;
+        self.symphonia = Some(shared);
+        self.start_track(info, format, source, Duration::ZERO)
+    }
+
+    /// Replace whatever is playing with `source`, a freshly loaded track starting `start`
+    /// into it. Returns `info` with the format and the file's tags filled in.
+    fn start_track(
+        &mut self,
+        mut info: TrackInfo,
+        format: (u16, u32),
+        source: BoxedSource,
+        start: Duration,
+    ) -> Result<TrackInfo> {
+        self.describe_track(&mut info, format);
+
+        self.upsample = self.upsample_for(format.1);
+        let rate = format.1 * self.upsample as u32;
+        if (self.auto_device_rate || self.upsample > 1)
+            && rate != self.output_sample_rate
+            && self.device_rate != Some(rate)
+        {
+            // Reopened at the track's rate below, or at the default if that fails
+            self.device_rate = Some(rate);
+            self.release_output();
+        }
+        self.ensure_output()?;
+        // Pick up the new track's ReplayGain
+        self.apply_volume();
+        let fade = self.fade_in.take().unwrap_or(self.fade);
+        let source: BoxedSource = if fade.is_zero() {
+            source
+        } else {
+            Box::new(source.fade_in(fade))
+        };
+        self.sink.clear();
+        self.controls.analysis.lock().clear();
+        // Starting another track cancels the sleep timer, before the track can be silenced
+        self.clear_sleep_timer();
+        self.play_source(source, start, &info);
 
+        let mut track = CurrentTrack::new(info.clone(), self.speed);
+        track.set_position(start.as_millis() as u64);
+        if self.start_paused {
+            track.pause();
+            self.sink.pause();
+        } else {
+            self.sink.play();
+        }
+        self.current_track = Some(track);
+        self.skip_markers.clear();
+        self.ab_loop = None;
+        self.test_signal = false;
+        self.interrupted = None;
         Ok(info)
     }
 
+    /// Fill in `info`'s format and the file's tags, and report on it as the current track from
+    /// now on
+    fn describe_track(&mut self, info: &mut TrackInfo, format: (u16, u32)) {
+        // Tags and container details aren't visible through rodio's decoder. A URL would have
+        // to be downloaded again to probe it.
+        let stream = info.path.to_str().is_some_and(http::is_url);
+        let probe = match stream {
+            true => Probe::default(),
+            false => probe::probe_file(&info.path).unwrap_or_default(),
+        };
+        info.sample_rate = Some(format.1);
+        info.channels = Some(format.0);
+        info.title = probe.title;
+        info.artist = probe.artist;
+        info.album = probe.album;
+        info.track_number = probe.track_number;
+        info.year = probe.year;
+        info.genre = probe.genre;
+        self.replaygain_tags = (probe.track_gain_db, probe.album_gain_db);
+        self.capabilities = Capabilities {
+            seekable: !stream,
+            has_duration: info.duration_ms.is_some(),
+            has_chapters: probe.has_chapters,
+            has_cover: probe.has_cover,
+            channels: probe.channels.unwrap_or(format.0),
+            accurate_seek: self.cache.is_some() || self.symphonia.is_some(),
+        };
+        self.codec = probe.codec;
+    }
+
     pub fn pause(&mut self) {
+        // The user wants it paused, so don't pick up again when an interruption ends
+        if let Some(resume_after) = &mut self.interrupted {
+            *resume_after = false;
+        }
+        self.pause_output();
+    }
+
+    pub fn resume(&mut self) {
+        self.interrupted = None;
+        self.resume_output();
+    }
+
+    fn pause_output(&mut self) {
         if let Some(track) = &mut self.current_track {
             track.pause();
         }
         self.sink.pause();
+        if let Some(fading) = &self.fading {
+            fading.pause();
+        }
     }
 
-    pub fn resume(&mut self) {
+    fn resume_output(&mut self) {
+        // Stay paused if the device can't be reopened; `play` reports why
+        if self.reopen_output().is_err() {
+            return;
+        }
         if let Some(track) = &mut self.current_track {
             track.resume();
         }
         self.sink.play();
+        if let Some(fading) = &self.fading {
+            fading.play();
+        }
     }
 
-    pub fn stop(&mut self) {
+    /// Release the output device once the player has been paused or stopped for `timeout`,
+    /// freeing it for other apps. `None` (the default) keeps it open.
+    ///
+    /// The player has no background thread, so the timeout is only checked when
+    /// `release_idle_output` is called. Playback picks up from where it was on the next
+    /// `resume`, `play` or load, reopening the device first.
+    pub fn set_close_on_silence(&mut self, timeout: Option<Duration>) {
+        self.close_on_silence = timeout;
+        self.idle_since = None;
+    }
+
+    /// Check the close-on-silence timeout, releasing the output device if it has passed.
+    ///
+    /// Call this regularly, e.g. once a second from a UI timer; idle time is counted from the
+    /// first call that finds the player paused or stopped. Returns whether the device was
+    /// released by this call.
+    pub fn release_idle_output(&mut self) -> bool {
+        let Some(timeout) = self.close_on_silence else {
+            return false;
+        };
+        if self.output.is_none() {
+            return false;
+        }
+        if !(self.sink.is_paused() || self.sink.empty()) {
+            self.idle_since = None;
+            return false;
+        }
+        let idle_since = *self.idle_since.get_or_insert_with(Instant::now);
+        if idle_since.elapsed() < timeout {
+            return false;
+        }
+        self.release_output();
+        true
+    }
+
+    fn release_output(&mut self) {
+        self.lineup.withdraw();
         self.sink.stop();
-        self.current_track = None;
+        self.fading = None;
+        self.output = None;
+        self.idle_since = None;
     }
 
-    pub fn seek_approx(&mut self, to_ms: u64) -> Result<()> {
-        use std::time::Duration;
+    /// Reopen the output device at each loaded track's native sample rate, so no resampling
+    /// happens on the way out, and return it to its default rate on `stop`.
+    ///
+    /// Tracks at a rate the device doesn't support are resampled as usual. Reopening the
+    /// device between tracks of different rates can cause a short gap or click.
+    pub fn set_auto_device_rate(&mut self, enabled: bool) {
+        self.auto_device_rate = enabled;
+        self.refollow();
+    }
 
-        let path = match &self.current_track {
-            Some(track) => &track.info.path,
-            None => return Ok(()), // No track to seek
+    /// Upsample the output to `factor` (1, 2 or 4) times each track's sample rate before it
+    /// goes to the device, for DACs that sound better fed a higher rate; 1 (the default)
+    /// leaves the rate alone. The device is reopened at the higher rate. Where it doesn't
+    /// support that rate, the factor is lowered until it does. Takes effect from the next
+    /// track loaded.
+    ///
+    /// The interpolation filter costs 32 multiply-adds per output sample, so 4x on a 48 kHz
+    /// stereo track is about 12 million a second, on top of the rest of the processing
+    /// running at the higher rate after it.
+    pub fn set_upsample_factor(&mut self, factor: u8) -> Result<()> {
+        if !matches!(factor, 1 | 2 | 4) {
+            anyhow::bail!("Unsupported upsampling factor {}; use 1, 2 or 4", factor);
+        }
+        self.upsample_factor = factor;
+        Ok(())
+    }
+
+    pub fn upsample_factor(&self) -> u8 {
+        self.upsample_factor
+    }
+
+    /// The highest factor up to `set_upsample_factor`'s that the device supports for a track
+    /// at `rate`
+    fn upsample_for(&self, rate: u32) -> u8 {
+        let mut factor = self.upsample_factor;
+        while factor > 1 && !output::supports_rate(&self.target, rate * factor as u32) {
+            factor /= 2;
+        }
+        factor
+    }
+
+    /// Whether the output device is currently held open
+    pub fn is_output_open(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Reopen a released output device; returns whether it had to be reopened
+    fn ensure_output(&mut self) -> Result<bool> {
+        if self.output.is_some() {
+            return Ok(false);
+        }
+        let (output, sink, format) = output::open_output(&self.target, self.device_rate)?;
+        sink.pause();
+        (self.output_channels, self.output_sample_rate) = format;
+        // The default device may have changed while the output was released
+        self.output_device_name = output::device_name(&self.target);
+        self.output = Some(output);
+        self.sink = sink;
+        self.apply_volume();
+        self.sink.set_speed(self.speed);
+        Ok(true)
+    }
+
+    /// Reopen a released output device and queue the current track where it was left
+    fn reopen_output(&mut self) -> Result<()> {
+        if !self.ensure_output()? {
+            return Ok(());
+        }
+        self.requeue_current()
+    }
+
+    /// Queue the current track again from where it has got to, in an output with nothing in
+    /// it
+    fn requeue_current(&mut self) -> Result<()> {
+        let Some(track) = &self.current_track else {
+            return Ok(());
         };
+        let (info, at) = (
+            track.info.clone(),
+            Duration::from_millis(track.current_position_ms()),
+        );
+        match self.exact_source_at(&info.path, at)? {
+            Some(src) => self.play_source(src, at, &info),
+            None => self.stop(),
+        }
+        Ok(())
+    }
 
-        // Open once to query total duration
-        let file = File::open(path)?;
-        let src = Decoder::new(BufReader::new(file))?;
-        let to = Duration::from_millis(to_ms);
+    /// Whether audio is playing, paused, stopped or there is nothing to play.
+    ///
+    /// A track that plays to its end reports `Stopped` as soon as the sink runs dry, without
+    /// anyone calling `stop`.
+    pub fn state(&self) -> PlaybackState {
+        // The sink only drops stopped sources on its next pass, so don't trust it after `stop`
+        let active = self.current_track.is_some() || self.test_signal;
+        if active && self.output.is_some() && !self.sink.empty() {
+            return if self.sink.is_paused() {
+                PlaybackState::Paused
+            } else {
+                PlaybackState::Playing
+            };
+        }
+        if self.output.is_none() && self.current_track.is_some() {
+            // Device released while paused; resuming picks up where it was
+            return PlaybackState::Paused;
+        }
+        if self.current_track.is_some() || self.stopped_track.is_some() {
+            PlaybackState::Stopped
+        } else {
+            PlaybackState::Empty
+        }
+    }
+
+    /// Subscribe to tracks finishing. The returned channel receives each track's info when
+    /// it plays out to its end; `stop`, seeking past the end and loading another track
+    /// don't count. Drop the receiver to unsubscribe.
+    pub fn on_track_end(&mut self) -> Receiver<TrackInfo> {
+        self.controls.track_end.subscribe()
+    }
+
+    /// Subscribe to `PlayerEvent`s. Drop the receiver to unsubscribe.
+    pub fn on_event(&mut self) -> Receiver<PlayerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Add `path` to the end of the queue. Doesn't interrupt what's playing. Returns false
+    /// when `set_auto_dedup` left it out as already queued, and an error when the queue is
+    /// full as `set_max_queue_length` sets out.
+    pub fn enqueue(&mut self, path: PathBuf) -> Result<bool> {
+        Ok(self.enqueue_many([path])? == 0)
+    }
 
-        if let Some(total) = src.total_duration() {
-            if to >= total {
-                // Seeking past EOF: just stop.
-                self.stop();
-                return Ok(());
+    /// Add each of `paths` to the end of the queue in turn, returning how many
+    /// `set_auto_dedup` left out. When they don't all fit under `set_max_queue_length`, none
+    /// are added and the error says so.
+    pub fn enqueue_many(&mut self, paths: impl IntoIterator<Item = PathBuf>) -> Result<usize> {
+        let mut queued: Option<HashSet<PathBuf>> = self
+            .auto_dedup
+            .then(|| self.queue.entries().iter().map(|p| canonical(p)).collect());
+        let mut skipped = 0;
+        let mut added = Vec::new();
+        for path in paths {
+            if let Some(queued) = &mut queued {
+                if !queued.insert(canonical(&path)) {
+                    skipped += 1;
+                    continue;
+                }
             }
+            added.push(path);
+        }
+        let over = self.max_queue_length.map_or(0, |max| {
+            (self.queue.entries().len() + added.len()).saturating_sub(max)
+        });
+        let droppable = match self.queue_overflow {
+            QueueOverflow::Reject => 0,
+            QueueOverflow::DropOldest => self.queue.played(),
+        };
+        if over > droppable {
+            anyhow::bail!(
+                "The queue is full at {} tracks",
+                self.max_queue_length.unwrap_or_default()
+            );
         }
+        self.refollow_after(|queue| {
+            queue.drop_played(over);
+            added.into_iter().for_each(|path| queue.push(path));
+        });
+        Ok(skipped)
+    }
 
-        let skipped = src.skip_duration(to); // returns a Source wrapper, not a Duration
+    /// Cap the queue at `max` tracks, or lift the cap with `None`, the default. What
+    /// enqueuing past it does is up to `set_queue_overflow`. Lowering the cap below the
+    /// length of the queue leaves the queue as it is until something more is enqueued.
+    pub fn set_max_queue_length(&mut self, max: Option<usize>) {
+        self.max_queue_length = max;
+    }
 
-        self.sink.clear();
-        self.sink.append(skipped);
-        self.sink.play();
+    pub fn max_queue_length(&self) -> Option<usize> {
+        self.max_queue_length
+    }
 
-        if let Some(track) = &mut self.current_track {
-            track.set_position(to_ms);
+    /// Choose whether enqueuing onto a full queue fails or drops the tracks that played
+    /// longest ago to make room
+    pub fn set_queue_overflow(&mut self, overflow: QueueOverflow) {
+        self.queue_overflow = overflow;
+    }
+
+    pub fn queue_overflow(&self) -> QueueOverflow {
+        self.queue_overflow
+    }
+
+    /// Keep the queue in step with the audio files in `dir`: files that appear there are
+    /// enqueued, as `enqueue` would, and files deleted from it are taken out of the queue,
+    /// except the one playing. Files already there are left alone. Subfolders aren't
+    /// watched. Suits a kiosk where files are dropped into a folder to play them.
+    ///
+    /// Changes are picked up by `poll_queue`, which sends `PlayerEvent::QueueChanged` when
+    /// the queue changed. Dropping the returned handle stops watching.
+    pub fn watch_folder<P: AsRef<Path>>(&self, dir: P) -> Result<WatchHandle> {
+        watch::watch(dir.as_ref(), self.folder_changes.0.clone())
+    }
+
+    /// Apply the changes in watched folders to the queue
+    fn follow_folders(&mut self) {
+        let mut changed = false;
+        while let Ok(change) = self.folder_changes.1.try_recv() {
+            match change {
+                // A full queue has no room for it
+                FolderChange::Added(path) => changed |= self.enqueue(path).unwrap_or(false),
+                FolderChange::Removed(path) => {
+                    self.refollow_after(|queue| changed |= queue.remove(&path));
+                }
+            }
+        }
+        if changed {
+            self.events.emit(PlayerEvent::QueueChanged);
+        }
+    }
+
+    /// Leave files out of `enqueue` and `enqueue_many` when they're already in the queue,
+    /// e.g. when building it from several folder scans that overlap. Paths are compared
+    /// after resolving `.`, `..` and symlinks, so different spellings of one file count as
+    /// the same. Files already queued stay as they are. Off by default.
+    pub fn set_auto_dedup(&mut self, enabled: bool) {
+        self.auto_dedup = enabled;
+    }
+
+    /// Empty the queue. The current track keeps playing, but nothing follows it.
+    pub fn clear_queue(&mut self) {
+        self.refollow_after(Queue::clear);
+    }
+
+    /// The queued files, in the order they were added
+    pub fn queue(&self) -> &[PathBuf] {
+        self.queue.entries()
+    }
+
+    /// Indices into `queue` in the order they play, which differs from `queue` while
+    /// shuffling
+    pub fn queue_order(&self) -> &[usize] {
+        self.queue.order()
+    }
+
+    /// Index in `queue` of the track playing from the queue, if any
+    pub fn queue_index(&self) -> Option<usize> {
+        self.queue.current()
+    }
+
+    /// Play the queue in random order, or back in the order it was added. See
+    /// `set_shuffle_seeded`; this seeds from the clock.
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        self.set_shuffle_seeded(enabled, seed);
+    }
+
+    /// Like `set_shuffle`, with the same order every time for a given `seed`.
+    ///
+    /// The track playing now stays put as the first of the new order, so playback doesn't
+    /// jump, and turning shuffle off carries on from it in insertion order. Tracks enqueued
+    /// while shuffling land at a random spot among those yet to play. Enabling shuffle again
+    /// reshuffles.
+    pub fn set_shuffle_seeded(&mut self, enabled: bool, seed: u64) {
+        if enabled {
+            self.refollow_after(|queue| queue.shuffle(seed));
+        } else if self.queue.is_shuffled() {
+            self.refollow_after(Queue::unshuffle);
+        }
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.queue.is_shuffled()
+    }
+
+    /// Shuffle the queue afresh each time it goes back to the start under `RepeatMode::All`,
+    /// instead of replaying the same shuffled order every pass. The track that just played
+    /// isn't put first, so it doesn't play twice in a row. Only applies to `set_shuffle`,
+    /// not `set_album_shuffle`. Off by default.
+    pub fn set_reshuffle_on_wrap(&mut self, enabled: bool) {
+        self.refollow_after(|queue| queue.set_reshuffle_on_wrap(enabled));
+    }
+
+    pub fn reshuffles_on_wrap(&self) -> bool {
+        self.queue.reshuffles_on_wrap()
+    }
+
+    /// Shuffle whole albums instead of single tracks: each album's tracks stay together and
+    /// in track number order while the albums play in random order. Tracks without an album
+    /// tag count as albums of their own. Reads the tags of every queued file, and tracks
+    /// enqueued afterwards go at the end. Turning it off restores insertion order.
+    pub fn set_album_shuffle(&mut self, enabled: bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        self.set_album_shuffle_seeded(enabled, seed);
+    }
+
+    /// Like `set_album_shuffle`, with the same album order every time for a given `seed`
+    pub fn set_album_shuffle_seeded(&mut self, enabled: bool, seed: u64) {
+        if enabled {
+            let tags: Vec<_> = self
+                .queue
+                .entries()
+                .iter()
+                .map(|path| {
+                    let probe = probe::probe_file(path).unwrap_or_default();
+                    (probe.album, probe.track_number)
+                })
+                .collect();
+            self.refollow_after(|queue| queue.shuffle_albums(seed, &tags));
+        } else if self.queue.is_album_shuffled() {
+            self.refollow_after(Queue::unshuffle);
+        }
+    }
+
+    pub fn is_album_shuffled(&self) -> bool {
+        self.queue.is_album_shuffled()
+    }
+
+    /// Shuffle single tracks, but space out tracks by the same artist so they don't come in
+    /// clusters the way a plain shuffle leaves them. Tracks without an artist tag are spaced
+    /// out by album instead, and ones with neither go anywhere, so a queue of untagged files
+    /// gets a plain shuffle. Reads the tags of every queued file; tracks enqueued afterwards,
+    /// and the passes `set_reshuffle_on_wrap` shuffles, aren't spaced out. Turning it off
+    /// restores insertion order.
+    pub fn set_smart_shuffle(&mut self, enabled: bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        self.set_smart_shuffle_seeded(enabled, seed);
+    }
+
+    /// Like `set_smart_shuffle`, with the same order every time for a given `seed`
+    pub fn set_smart_shuffle_seeded(&mut self, enabled: bool, seed: u64) {
+        if enabled {
+            let artists: Vec<_> = self
+                .queue
+                .entries()
+                .iter()
+                .map(|path| {
+                    let probe = probe::probe_file(path).unwrap_or_default();
+                    probe.artist.or(probe.album)
+                })
+                .collect();
+            self.refollow_after(|queue| queue.shuffle_apart(seed, &artists));
+        } else if self.queue.is_spread() {
+            self.refollow_after(Queue::unshuffle);
+        }
+    }
+
+    pub fn is_smart_shuffled(&self) -> bool {
+        self.queue.is_spread()
+    }
+
+    /// Shuffle for "favorites more often": each next track is drawn at random with a chance
+    /// in proportion to its `set_track_weight`, rather than every track playing once per
+    /// pass. Tracks can come up again, even twice in a row, and the queue plays on until
+    /// stopped whatever the repeat mode. Turning it off restores insertion order.
+    pub fn set_weighted_shuffle(&mut self, enabled: bool) {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        self.set_weighted_shuffle_seeded(enabled, seed);
+    }
+
+    /// Like `set_weighted_shuffle`, with the same draws every time for a given `seed`
+    pub fn set_weighted_shuffle_seeded(&mut self, enabled: bool, seed: u64) {
+        if enabled {
+            self.refollow_after(|queue| queue.shuffle_weighted(seed));
+        } else if self.queue.is_weighted() {
+            self.refollow_after(Queue::unshuffle);
         }
+    }
+
+    pub fn is_weighted_shuffle(&self) -> bool {
+        self.queue.is_weighted()
+    }
 
+    /// Weight the queue entry at `index`, in `queue` order, for `set_weighted_shuffle`: one
+    /// weighted 3 comes up about three times as often as one at the default of 1. Weights
+    /// below 0.1 count as 0.1, so no track is left out altogether.
+    pub fn set_track_weight(&mut self, index: usize, weight: f32) -> Result<()> {
+        anyhow::ensure!(
+            self.queue.weight(index).is_some(),
+            "No queue entry {}",
+            index
+        );
+        self.refollow_after(|queue| queue.set_weight(index, weight));
         Ok(())
     }
 
-    pub fn advance_or_rewind(&mut self, delta_ms: i64) -> Result<()> {
-        let current = self.current_position_ms() as i64;
-        let target = (current + delta_ms).max(0) as u64;
-        self.seek_approx(target)
+    pub fn track_weight(&self, index: usize) -> Option<f32> {
+        self.queue.weight(index)
+    }
+
+    /// Play the queue from the first entry in play order. Each following entry starts by
+    /// itself as the one before it ends, as `set_repeat` directs; see `poll_queue`.
+    ///
+    /// An error opening the first entry is returned even when `set_error_skip_backoff` is
+    /// on, but counts as a failure there, so `poll_queue` goes on to the next.
+    pub fn play_queue(&mut self) -> Result<TrackInfo> {
+        let Some(path) = self.queue.start() else {
+            anyhow::bail!("The queue is empty");
+        };
+        self.failures = 0;
+        let loaded = self.load_and_play(path);
+        if let Err(e) = &loaded {
+            self.count_failure(e);
+        }
+        loaded
+    }
+
+    /// Treat the output running dry well before the end of the current track, as a severe
+    /// underrun on a slow system can make it, as a hiccup rather than the end of the track:
+    /// `poll_queue` then queues the track again from its last position instead of moving on
+    /// through the queue. A track is taken to have stopped short when the output empties more
+    /// than two seconds before its end; tracks of unknown length are never. Off by default.
+    pub fn set_underrun_recovery(&mut self, enabled: bool) {
+        self.underrun_recovery = enabled;
+    }
+
+    pub fn underrun_recovery(&self) -> bool {
+        self.underrun_recovery
+    }
+
+    /// Skip queue tracks that fail to open instead of stopping at them, waiting `delay`
+    /// before trying the next so a folder of bad files isn't raced through. After
+    /// `max_consecutive` failures in a row, playback stops and `PlayerEvent::TooManyErrors`
+    /// is sent. Skips happen in `poll_queue`, which then returns `Ok(None)` in place of the
+    /// error. Off by default, when `poll_queue` returns the error and goes no further.
+    pub fn set_error_skip_backoff(&mut self, delay: Duration, max_consecutive: u32) {
+        self.error_skip = Some((delay, max_consecutive.max(1)));
+    }
+
+    /// Stop skipping queue tracks that fail to open
+    pub fn clear_error_skip_backoff(&mut self) {
+        self.error_skip = None;
+        self.skip_at = None;
+    }
+
+    /// Note a queue track that failed to open with `error`, and either schedule the skip
+    /// past it or give up. Returns false when not skipping errors.
+    fn count_failure(&mut self, error: &anyhow::Error) -> bool {
+        let Some((delay, max_consecutive)) = self.error_skip else {
+            return false;
+        };
+        self.failures += 1;
+        if self.failures < max_consecutive {
+            self.skip_at = Some(Instant::now() + delay);
+            return true;
+        }
+        self.stop();
+        self.events.emit(PlayerEvent::TooManyErrors {
+            failures: std::mem::take(&mut self.failures),
+            last_error: format!("{:#}", error),
+        });
+        true
+    }
+
+    /// Load `path` from the queue, skipping it as `set_error_skip_backoff` says if it fails
+    fn load_queued(&mut self, path: PathBuf) -> Result<Option<TrackInfo>> {
+        match self.load_and_play(path) {
+            Ok(info) => {
+                self.failures = 0;
+                Ok(Some(info))
+            }
+            Err(e) if self.count_failure(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Catch up with the queue, returning the track it has moved on to since the last call.
+    ///
+    /// A few seconds before a queued track ends, the next entry is opened in the background
+    /// and lined up behind it, so playback moves on without waiting for this call. Until it's
+    /// made, `current_track`, `queue_index` and the position still describe the track before.
+    /// Call this regularly, e.g. from a UI timer or whenever an `on_track_end` receiver
+    /// fires. It's also where the next entry is loaded when that needs this thread: when
+    /// `set_gapless` is off, or when `set_auto_device_rate` has to reopen the device for it.
+    /// An entry that couldn't be opened is returned as the error, or skipped as
+    /// `set_error_skip_backoff` says. Crossfades start here too, changes in the folders
+    /// given to `watch_folder` reach the queue, and `set_underrun_recovery` picks up a track
+    /// that stopped short.
+    /// Does nothing at the end of the queue or when the current track didn't come from it,
+    /// or after the sleep timer has stopped playback.
+    pub fn poll_queue(&mut self) -> Result<Option<TrackInfo>> {
+        self.follow_folders();
+        // The sleep timer silenced the output on its own; settle the player to match
+        if self.sleep_timer.as_ref().is_some_and(SleepTimer::fired) {
+            self.stop();
+            return Ok(None);
+        }
+        if self.fading.as_ref().is_some_and(|sink| sink.empty()) {
+            self.fading = None;
+        }
+        if let Some(at) = self.skip_at {
+            if Instant::now() < at {
+                return Ok(None);
+            }
+            self.skip_at = None;
+            return match self.queue.advance(self.queue_repeat() != RepeatMode::Off) {
+                Some(path) => self.load_queued(path),
+                None => Ok(None),
+            };
+        }
+        if let Some(info) = self.take_over_lined_up() {
+            self.failures = 0;
+            return Ok(Some(info));
+        }
+        let ended = self.output.is_some() && self.sink.empty();
+        if ended && self.drained_early() {
+            self.requeue_current()?;
+            return Ok(None);
+        }
+        let Some(arrival) = self.lineup.next(ended) else {
+            self.fade_out_queue_end();
+            return self.crossfade_if_due();
+        };
+        let next = self.queue.follow(self.queue_repeat());
+        match (arrival, next) {
+            // Started just now
+            (Arrival::Lined(lined), _) => {
+                self.failures = 0;
+                Ok(Some(self.take_over(*lined)))
+            }
+            (Arrival::Failed(e), _) if self.count_failure(&e) => Ok(None),
+            (Arrival::Failed(e), _) => Err(e),
+            (Arrival::Reload, Some(path)) => self.load_queued(path),
+            (Arrival::End, _) | (Arrival::Reload, None) => self.end_queue().map(|_| None),
+        }
+    }
+
+    /// Carry out the `set_queue_end_action` now that the last queue track has played out
+    fn end_queue(&mut self) -> Result<()> {
+        match self.queue_end_action {
+            // The queue never ends
+            QueueEndAction::Stop | QueueEndAction::RepeatAll => Ok(()),
+            QueueEndAction::Shutdown => {
+                self.events.emit(PlayerEvent::ShutdownRequested);
+                Ok(())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+            QueueEndAction::SleepSystem => power::suspend(),
+        }
+    }
+
+    /// Whether the output ran dry well short of the end of the current track, which
+    /// `set_underrun_recovery` takes for an underrun rather than the track ending
+    fn drained_early(&self) -> bool {
+        let Some(track) = self
+            .current_track
+            .as_ref()
+            .filter(|_| self.underrun_recovery)
+        else {
+            return false;
+        };
+        let end = self
+            .play_until
+            .map(|until| until.as_millis() as u64)
+            .or(track.info.duration_ms);
+        !track.is_paused()
+            && end.is_some_and(|end| track.current_position_ms() + UNDERRUN_END_MARGIN_MS < end)
+    }
+
+    /// Make the lined-up queue tracks the sink has moved on to current, returning the last
+    fn take_over_lined_up(&mut self) -> Option<TrackInfo> {
+        let mut current = None;
+        // Only lined-up tracks come before the end of the track ahead
+        while let Some(Arrival::Lined(lined)) = self.lineup.next(false) {
+            self.queue.follow(self.queue_repeat());
+            current = Some(self.take_over(*lined));
+        }
+        current
+    }
+
+    /// Take a lined-up track, which the sink has moved on to, as the current track
+    fn take_over(&mut self, lined: Lined) -> TrackInfo {
+        let Lined {
+            mut info,
+            format,
+            start,
+            cache,
+            play_until,
+            handover,
+            ..
+        } = lined;
+        self.format = Some(format);
+        self.play_until = play_until;
+        self.cache = cache;
+        self.symphonia = None;
+        self.describe_track(&mut info, format);
+        self.apply_volume();
+
+        // It started as the previous track ran out, a little before this was called
+        let played = handover
+            .started()
+            .map_or(Duration::ZERO, |at| at.elapsed().mul_f32(self.speed));
+        let mut track = CurrentTrack::new(info.clone(), self.speed);
+        track.set_position((start + played).as_millis() as u64);
+        self.current_track = Some(track);
+        self.skip_markers.clear();
+        self.ab_loop = None;
+        self.clear_queue_end_fade();
+        info
+    }
+
+    /// Line the queue up again behind the current track after the queue or how it plays
+    /// changed
+    fn refollow(&mut self) {
+        self.refollow_after(|_| {});
+    }
+
+    /// Change the queue with `f` and line it up again behind the current track
+    fn refollow_after(&mut self, f: impl FnOnce(&mut Queue)) {
+        // Tracks that already took over come first, so `f` sees the queue where it really is
+        self.take_over_lined_up();
+        f(&mut self.queue);
+        let plan = self.plan();
+        self.lineup.refollow(plan);
+    }
+
+    /// Fade out the end of the current track when it's the last of the queue, as
+    /// `set_queue_end_fade` asks, or stop fading it when something was enqueued after it.
+    /// The fade is keyed on the track's position, so it only needs setting before it begins.
+    fn fade_out_queue_end(&mut self) {
+        let Some(track) = &self.current_track else {
+            return;
+        };
+        let end = self
+            .play_until
+            .map(|until| until.as_millis() as u64)
+            .or(track.info.duration_ms)
+            .map(Duration::from_millis);
+        let Some(end) = end else {
+            return;
+        };
+        let near_end = end.saturating_sub(Duration::from_millis(track.current_position_ms()));
+        if self.queue_end_fade.is_zero()
+            || self.ab_loop.is_some()
+            || self.queue.current_path() != Some(track.info.path.as_path())
+            || near_end > self.queue_end_fade + lineup::LEAD
+        {
+            return;
+        }
+        let last = self.queue.clone().follow(self.queue_repeat()).is_none();
+        if last != self.fading_out_queue {
+            let fade = GainEnvelope::new(vec![
+                (end.saturating_sub(self.queue_end_fade), 1.0),
+                (end, 0.0),
+            ]);
+            self.controls
+                .update_dsp(|dsp| dsp.end_fade = fade.filter(|_| last));
+            self.fading_out_queue = last;
+        }
+    }
+
+    /// Stop fading out a track that's no longer playing to the end of the queue
+    fn clear_queue_end_fade(&mut self) {
+        if std::mem::take(&mut self.fading_out_queue) {
+            self.controls.update_dsp(|dsp| dsp.end_fade = None);
+        }
+    }
+
+    /// Start the next queued track over the end of the current one once the current one is
+    /// within the crossfade window of its end
+    fn crossfade_if_due(&mut self) -> Result<Option<TrackInfo>> {
+        let from = self.queue.current();
+        // The next entry isn't known until the queue is followed, so first see whether the
+        // current one is within reach of any crossfade from it
+        let Some(window) = from
+            .and_then(|from| self.queue.longest_crossfade_from(from))
+            .max(self.crossfade)
+            .filter(|_| self.crossfade_mode == CrossfadeMode::Always)
+        else {
+            return Ok(None);
+        };
+        // A track going around an A-B loop doesn't end
+        let remaining = self.times().remaining_ms.map(Duration::from_millis);
+        let due = self.fading.is_none()
+            && self.ab_loop.is_none()
+            && self.state() == PlaybackState::Playing
+            && self.queue.current_path().is_some_and(|path| {
+                self.current_track
+                    .as_ref()
+                    .is_some_and(|track| track.info.path == path)
+            })
+            && remaining.is_some_and(|left| left <= window);
+        if !due {
+            return Ok(None);
+        }
+        let current = self.current_track.as_ref().map(|t| t.info.path.clone());
+        if self.no_crossfade_albums && self.album_continues == current {
+            return Ok(None);
+        }
+        let mut following = self.queue.clone();
+        let Some(path) = following.follow(self.queue_repeat()) else {
+            return Ok(None);
+        };
+        let Some(fade) = self.crossfade_between(from, following.current()) else {
+            return Ok(None);
+        };
+        if remaining.is_some_and(|left| left > fade) {
+            return Ok(None);
+        }
+        if self.no_crossfade_albums
+            && current
+                .as_ref()
+                .is_some_and(|current| probe::same_album(current, &path))
+        {
+            // Checked once per track; it plays on into the next by itself
+            self.album_continues = current;
+            return Ok(None);
+        }
+        self.queue = following;
+        self.crossfade_into(path, fade).map(Some)
+    }
+
+    /// Crossfade from the queue entry at `from` into the one at `to`: the one set for them
+    /// with `set_crossfade_for`, else the `set_crossfade` one. `None` plays them back to back.
+    fn crossfade_between(&self, from: Option<usize>, to: Option<usize>) -> Option<Duration> {
+        from.zip(to)
+            .and_then(|(from, to)| self.queue.crossfade(from, to))
+            .or(self.crossfade)
+            .filter(|fade| !fade.is_zero())
+    }
+
+    /// Load `path` over the current track, fading that out while `path` fades in over `fade`
+    fn crossfade_into(&mut self, path: PathBuf, fade: Duration) -> Result<TrackInfo> {
+        let Some(output) = &self.output else {
+            return self.load_and_play(path);
+        };
+        // A second sink on the same output plays alongside the first
+        let sink = output.sink()?;
+        sink.set_speed(self.speed);
+        self.controls.request_stop(fade);
+        self.fading = Some(std::mem::replace(&mut self.sink, sink));
+        self.fade_in = Some(fade);
+        self.apply_volume();
+        let loaded = self.load_and_play(path);
+        self.fade_in = None;
+        loaded
+    }
+
+    /// Overlap consecutive queued tracks by `duration`, fading the outgoing one out while the
+    /// next fades in; `None` (the default) plays them back to back.
+    ///
+    /// The next track starts from `poll_queue`, so call it several times within the
+    /// crossfade window. Tracks of unknown length, and tracks that end before `poll_queue`
+    /// gets to them, play back to back instead. `set_crossfade_mode` picks whether skipping
+    /// with `next` crossfades too, and `set_crossfade_for` sets a different overlap between
+    /// particular entries.
+    pub fn set_crossfade(&mut self, duration: Option<Duration>) {
+        self.crossfade = duration.filter(|d| !d.is_zero());
+    }
+
+    /// Crossfade over `duration` from the queue entry at `from_index` into the one at
+    /// `to_index`, both in `queue` order, in place of the `set_crossfade` duration; zero
+    /// plays them back to back. It applies only where `to_index` follows `from_index`,
+    /// whether by itself or skipped to with `next`, and stays with the two entries as others
+    /// are taken out of the queue.
+    pub fn set_crossfade_for(
+        &mut self,
+        from_index: usize,
+        to_index: usize,
+        duration: Duration,
+    ) -> Result<()> {
+        for index in [from_index, to_index] {
+            anyhow::ensure!(
+                index < self.queue.entries().len(),
+                "No queue entry {}",
+                index
+            );
+        }
+        self.queue.set_crossfade(from_index, to_index, duration);
+        Ok(())
+    }
+
+    /// Crossfade set with `set_crossfade_for` from the queue entry at `from_index` into the
+    /// one at `to_index`
+    pub fn crossfade_for(&self, from_index: usize, to_index: usize) -> Option<Duration> {
+        self.queue.crossfade(from_index, to_index)
+    }
+
+    /// Play straight on from one queued track into the next when both are tagged with the
+    /// same album, as albums mixed to run continuously need: no crossfade in any
+    /// `CrossfadeMode`, and no gap with `set_gapless` off either. Skipping with `next` still
+    /// crossfades. Off by default.
+    pub fn set_no_crossfade_albums(&mut self, enabled: bool) {
+        self.no_crossfade_albums = enabled;
+        self.refollow();
+    }
+
+    pub fn no_crossfade_albums(&self) -> bool {
+        self.no_crossfade_albums
+    }
+
+    /// Choose which changes of track the `set_crossfade` overlap applies to, e.g. only
+    /// skips, leaving albums to play on gaplessly. Defaults to `CrossfadeMode::Always`.
+    pub fn set_crossfade_mode(&mut self, mode: CrossfadeMode) {
+        self.crossfade_mode = mode;
+    }
+
+    pub fn crossfade_mode(&self) -> CrossfadeMode {
+        self.crossfade_mode
+    }
+
+    /// Open each queued track a few seconds before the one ahead of it ends and join the two
+    /// sample to sample, e.g. for live albums and classical movements where the music runs
+    /// on from one track into the next. On by default.
+    ///
+    /// Turned off, the next track is only opened once the one ahead has ended, on the next
+    /// `poll_queue`, leaving a gap, and fades in over the `set_fade` length. A crossfade
+    /// overlaps tracks either way, and `set_auto_device_rate` may still have to reopen the
+    /// device between them. A track whose sample rate differs is converted for the output as
+    /// always.
+    pub fn set_gapless(&mut self, enabled: bool) {
+        self.gapless = enabled;
+        self.refollow();
+    }
+
+    pub fn is_gapless(&self) -> bool {
+        self.gapless
+    }
+
+    /// Choose what happens when a queued track ends; takes effect from the current track
+    pub fn set_repeat(&mut self, mode: RepeatMode) {
+        self.repeat = mode;
+        self.refollow();
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    /// Choose what happens once the whole queue has played, rather than each track: stay
+    /// stopped, start again from the top, ask the app to quit, or put the machine to sleep.
+    /// Only applies with `RepeatMode::Off`, as the queue doesn't end otherwise. Actions are
+    /// carried out from `poll_queue`, which returns the error if suspending fails.
+    pub fn set_queue_end_action(&mut self, action: QueueEndAction) {
+        self.queue_end_action = action;
+        self.refollow();
+    }
+
+    pub fn queue_end_action(&self) -> QueueEndAction {
+        self.queue_end_action
+    }
+
+    /// Fade out over the last `duration` of the last queue track, when nothing follows it,
+    /// instead of stopping dead at its end. Unlike `set_crossfade`, nothing fades in. The
+    /// fade is set up by `poll_queue` in the seconds before it begins. Zero, the default,
+    /// turns it off.
+    pub fn set_queue_end_fade(&mut self, duration: Duration) {
+        self.queue_end_fade = duration;
+        self.clear_queue_end_fade();
+    }
+
+    pub fn queue_end_fade(&self) -> Duration {
+        self.queue_end_fade
+    }
+
+    /// How the queue repeats, counting a `QueueEndAction::RepeatAll` at the end
+    fn queue_repeat(&self) -> RepeatMode {
+        match (self.repeat, self.queue_end_action) {
+            (RepeatMode::Off, QueueEndAction::RepeatAll) => RepeatMode::All,
+            (repeat, _) => repeat,
+        }
+    }
+
+    /// Skip to the next queued track, returning it, or `None` at the end of the queue, where
+    /// the current track carries on. Wraps to the first track under `RepeatMode::All`.
+    /// Crossfades from a playing track unless `set_crossfade_mode` says otherwise.
+    // A player isn't an iterator; this is the transport's skip button
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<TrackInfo>> {
+        self.take_over_lined_up();
+        let from = self.queue.current();
+        let Some(path) = self.queue.advance(self.queue_repeat() == RepeatMode::All) else {
+            return Ok(None);
+        };
+        let fade = self
+            .crossfade_between(from, self.queue.current())
+            .filter(|_| {
+                self.crossfade_mode != CrossfadeMode::Never
+                    && self.fading.is_none()
+                    && self.state() == PlaybackState::Playing
+            });
+        match fade {
+            Some(fade) => self.crossfade_into(path, fade).map(Some),
+            None => self.load_and_play(path).map(Some),
+        }
+    }
+
+    /// Go back to the previous queued track, or restart the current one once it has played
+    /// for longer than `set_restart_window`, the way most players' back button works.
+    /// The first track is restarted either way, unless `RepeatMode::All` wraps back to the
+    /// last. Returns the track now playing, or `None` when nothing was played from the queue.
+    pub fn previous(&mut self) -> Result<Option<TrackInfo>> {
+        self.take_over_lined_up();
+        let Some(current) = self.queue.current_path().map(Path::to_path_buf) else {
+            return Ok(None);
+        };
+        let position = Duration::from_millis(self.position_ms().unwrap_or(0));
+        if position <= self.restart_window {
+            if let Some(path) = self.queue.back(self.queue_repeat() == RepeatMode::All) {
+                return self.load_and_play(path).map(Some);
+            }
+        }
+        match &self.current_track {
+            Some(track) if track.info.path == current => {
+                let info = track.info.clone();
+                self.seek(0)?;
+                Ok(Some(info))
+            }
+            _ => self.load_and_play(current).map(Some),
+        }
+    }
+
+    /// How long into a track `previous` still goes back a track; after that it restarts the
+    /// current one, so a second press right after goes back. Defaults to 3 seconds.
+    pub fn set_restart_window(&mut self, window: Duration) {
+        self.restart_window = window;
+    }
+
+    /// Hold playback for an external interruption such as a phone call.
+    ///
+    /// Unlike `pause`, this remembers whether the user had playback running, so
+    /// `resume_from_interrupt` only picks up again if they did. Pausing or resuming during the
+    /// interruption counts as the user's decision.
+    pub fn interrupt(&mut self) {
+        if self.interrupted.is_some() {
+            return;
+        }
+        let playing = self.current_track.as_ref().is_some_and(|t| !t.is_paused());
+        if playing {
+            self.pause_output();
+        }
+        self.interrupted = Some(playing);
+    }
+
+    /// End an interruption, resuming playback only if it was running when it began
+    pub fn resume_from_interrupt(&mut self) {
+        if self.interrupted.take() == Some(true) {
+            self.resume_output();
+        }
+    }
+
+    /// Whether an interruption is currently holding playback
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.is_some()
+    }
+
+    /// Set the output volume from a linear 0.0-1.0 slider position.
+    ///
+    /// The position is mapped to gain on a logarithmic curve spanning 60 dB, so equal slider
+    /// steps sound like equal changes in loudness. The volume carries over to every track
+    /// loaded afterwards.
+    ///
+    /// While muted, the new volume is remembered and takes effect on `unmute`.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.apply_volume();
+    }
+
+    /// The slider position last given to `set_volume`; 1.0 by default, even while muted
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Silence the output without losing the volume setting
+    pub fn mute(&mut self) {
+        self.muted = true;
+        self.apply_volume();
+    }
+
+    /// Restore the volume from before `mute`, or as last set while muted
+    pub fn unmute(&mut self) {
+        self.muted = false;
+        self.apply_volume();
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn apply_volume(&self) {
+        let gain = if self.muted {
+            0.0
+        } else {
+            volume_gain(self.volume) * self.replaygain_gain()
+        };
+        let cap = self
+            .output_device_name
+            .as_ref()
+            .and_then(|name| self.device_max_gain.get(name))
+            .copied()
+            .unwrap_or(f32::INFINITY);
+        self.sink.set_volume(gain.min(cap));
+        if let Some(fading) = &self.fading {
+            fading.set_volume(gain.min(cap));
+        }
+        self.controls.set_sink_gain(gain.min(cap));
+    }
+
+    /// Linear gain from the current track's ReplayGain tags
+    fn replaygain_gain(&self) -> f32 {
+        let (track, album) = self.replaygain_tags;
+        let tagged = match self.replaygain {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => track,
+            ReplayGainMode::Album => album.or(track),
+        };
+        10f32.powf((tagged.unwrap_or(0.0) + self.replaygain_preamp_db) / 20.0)
+    }
+
+    /// Adjust each track's volume by its ReplayGain tags, so tracks play at a similar
+    /// loudness. `Off` by default.
+    ///
+    /// The adjustment multiplies the `set_volume` level rather than replacing it, and is
+    /// still capped by `set_device_max_gain`. Tracks that boost their level can clip; the
+    /// limiter guards against that.
+    pub fn set_replaygain_mode(&mut self, mode: ReplayGainMode) {
+        self.replaygain = mode;
+        self.apply_volume();
+    }
+
+    pub fn replaygain_mode(&self) -> ReplayGainMode {
+        self.replaygain
+    }
+
+    /// Extra gain in dB added to the ReplayGain adjustment while it's on. Tracks without
+    /// ReplayGain tags get just this gain. Zero by default.
+    pub fn set_replaygain_preamp(&mut self, db: f32) {
+        self.replaygain_preamp_db = db;
+        self.apply_volume();
+    }
+
+    /// Cap the output gain (linear, 1.0 = unity) whenever `device` is the output, e.g. so
+    /// laptop speakers can't be driven into distortion at full volume. The cap applies on top
+    /// of `set_volume` and follows the device, not the track; 1.0 or more lifts it.
+    pub fn set_device_max_gain(&mut self, device: &str, max_linear: f32) {
+        if max_linear >= 1.0 {
+            self.device_max_gain.remove(device);
+        } else {
+            self.device_max_gain
+                .insert(device.to_string(), max_linear.max(0.0));
+        }
+        self.apply_volume();
+    }
+
+    /// Name of the output device in use, as `set_device_max_gain` expects it
+    pub fn output_device_name(&self) -> Option<&str> {
+        self.output_device_name.as_deref()
+    }
+
+    /// Gain caps set with `set_device_max_gain`, by device name, for saving with the rest
+    /// of the frontend's settings
+    pub fn device_max_gains(&self) -> &HashMap<String, f32> {
+        &self.device_max_gain
+    }
+
+    /// Play faster or slower, e.g. to get through podcasts quicker. The factor is clamped to
+    /// 0.25-4.0 and carries over to every track loaded afterwards.
+    ///
+    /// This is a plain resampling speed change, so pitch moves with it: 2.0 plays an octave
+    /// higher. Pitch-preserving time stretching is not supported.
+    pub fn set_speed(&mut self, factor: f32) {
+        self.speed = factor.clamp(0.25, 4.0);
+        self.sink.set_speed(self.speed);
+        if let Some(fading) = &self.fading {
+            fading.set_speed(self.speed);
+        }
+        if let Some(track) = &mut self.current_track {
+            track.set_speed(self.speed);
+        }
+    }
+
+    /// The playback speed factor; 1.0 by default
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Retry reads of track files up to `attempts` times, `delay` apart, when they fail with a
+    /// transient error such as a timeout on a network share. Applies to tracks loaded from now
+    /// on by either decoder. Zero attempts (the default) fails on the first error. End of file
+    /// is never retried.
+    ///
+    /// Reads happen on the audio thread, so a long `delay` causes an audible dropout while
+    /// waiting; it still beats the track stopping altogether.
+    pub fn set_io_retry(&mut self, attempts: u32, delay: Duration) {
+        self.io_retry = IoRetry { attempts, delay };
+    }
+
+    /// Skip applause at the start and end of tracks loaded from now on, e.g. live recordings
+    /// in a shuffle.
+    ///
+    /// Best-effort: applause is recognised by its noise-like spectrum, so very noisy music can
+    /// be trimmed by mistake and quiet applause missed. Detection decodes the whole file,
+    /// which makes loading noticeably slower.
+    pub fn set_applause_trim(&mut self, enabled: bool) {
+        self.applause_trim = enabled;
+    }
+
+    /// Make `load_and_play` load the track and report its info, but stay paused at the start
+    /// until `resume` or `play`, e.g. to preview a track before playing it
+    pub fn set_start_paused(&mut self, enabled: bool) {
+        self.start_paused = enabled;
+    }
+
+    /// Choose what `screen_locked` does; playback continues by default
+    pub fn set_lock_behavior(&mut self, behavior: LockBehavior) {
+        self.lock_behavior = behavior;
+    }
+
+    /// Tell the player the desktop session was locked.
+    ///
+    /// Frontends call this from their platform's session notifications; the player doesn't
+    /// watch for locks itself. Under `LockBehavior::Pause` this is a user-level pause, so
+    /// unlocking doesn't resume on its own.
+    pub fn screen_locked(&mut self) {
+        if self.lock_behavior == LockBehavior::Pause {
+            self.pause();
+        }
+    }
+
+    /// Start or continue playback, whatever the current state.
+    ///
+    /// Resumes a paused track. A stopped track is loaded again the way it was loaded before
+    /// and continues from where it was stopped; streams start over from the live edge. A track
+    /// that has played to its end restarts from the beginning. Does nothing when a track is
+    /// already playing or nothing has been loaded yet.
+    pub fn play(&mut self) -> Result<()> {
+        self.reopen_output()?;
+        let restart = match &self.current_track {
+            None => self.stopped_track.as_ref().map(|stopped| {
+                (
+                    stopped.info.path.clone(),
+                    stopped.method,
+                    stopped.position_ms,
+                )
+            }),
+            // Played to its end
+            Some(track) => {
+                if self.sink.empty() {
+                    Some((track.info.path.clone(), self.load_method(&track.info), 0))
+                } else {
+                    if track.is_paused() {
+                        self.resume();
+                    }
+                    None
+                }
+            }
+        };
+        if let Some((path, method, position_ms)) = restart {
+            match method {
+                LoadMethod::Rodio => self.load_and_play(path)?,
+                LoadMethod::Symphonia => self.load_and_play_symphonia(path)?,
+                LoadMethod::Url => self.load_and_play_url(&path.to_string_lossy())?,
+            };
+            if position_ms > 0 && self.capabilities.seekable {
+                self.seek(position_ms)?;
+            }
+            self.resume_output();
+        }
+        Ok(())
+    }
+
+    /// How the current track, described by `info`, was loaded
+    fn load_method(&self, info: &TrackInfo) -> LoadMethod {
+        if info.path.to_str().is_some_and(http::is_url) {
+            LoadMethod::Url
+        } else if self.symphonia.is_some() {
+            LoadMethod::Symphonia
+        } else {
+            LoadMethod::Rodio
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.take_over_lined_up();
+        self.sleep_timer = None;
+        self.skip_at = None;
+        self.lineup.withdraw();
+        // A paused sink is already silent, so there is nothing to ramp
+        let ramp = self.controls.dsp().stop_ramp;
+        if ramp.is_zero() || self.sink.is_paused() {
+            self.sink.stop();
+            // Dropping a sink stops it
+            self.fading = None;
+        } else {
+            // The sources fade out and end on the audio thread, in both sinks
+            self.controls.request_stop(ramp);
+        }
+        self.controls.track_end.cancel();
+        self.interrupted = None;
+        self.test_signal = false;
+        if let Some(track) = self.current_track.take() {
+            self.stopped_track = Some(StoppedTrack {
+                method: self.load_method(&track.info),
+                position_ms: track.current_position_ms(),
+                info: track.info,
+            });
+        }
+        self.format = None;
+        self.cache = None;
+        self.symphonia = None;
+        self.play_until = None;
+        self.capabilities = Capabilities::default();
+        self.codec = None;
+        // Give the device back its default rate
+        if self.device_rate.take().is_some() {
+            self.release_output();
+        }
+    }
+
+    /// Play continuous pink noise on every output channel, for level-matching speakers
+    pub fn play_pink_noise(&mut self) -> Result<()> {
+        self.play_test_signal(TestSignal::PinkNoise, None)
+    }
+
+    /// Play continuous white noise on every output channel
+    pub fn play_white_noise(&mut self) -> Result<()> {
+        self.play_test_signal(TestSignal::WhiteNoise, None)
+    }
+
+    /// Play a single exponential sine sweep on every output channel, for checking frequency
+    /// response
+    pub fn play_sweep(&mut self, start_hz: f32, end_hz: f32, duration: Duration) -> Result<()> {
+        self.play_test_signal(
+            TestSignal::Sweep {
+                start_hz,
+                end_hz,
+                duration,
+            },
+            None,
+        )
+    }
+
+    /// Replace whatever is playing with a generated calibration signal.
+    ///
+    /// With `channel` set, only that output channel carries the signal, which helps identify
+    /// which speaker is which (see `output_channel_layout`). Noise plays until `stop` or the
+    /// next load; a sweep plays once. The signal goes through the same processing as tracks,
+    /// so disable effects such as loudness leveling first for an unaltered signal.
+    pub fn play_test_signal(&mut self, signal: TestSignal, channel: Option<u16>) -> Result<()> {
+        if let Some(ch) = channel {
+            if ch >= self.output_channels {
+                anyhow::bail!(
+                    "Channel {} out of range; the output has {} channels",
+                    ch,
+                    self.output_channels
+                );
+            }
+        }
+        let buffer = signals::render(
+            signal,
+            self.output_channels,
+            self.output_sample_rate,
+            channel,
+        );
+        let source: BoxedSource = match signal {
+            TestSignal::Sweep { .. } => Box::new(buffer),
+            _ => Box::new(buffer.repeat_infinite()),
+        };
+
+        self.stop();
+        self.clear_sleep_timer();
+        // Nothing to restart with `play` once the signal is stopped
+        self.stopped_track = None;
+        self.ensure_output()?;
+        self.sink
+            .append(self.processed(source, Duration::ZERO, TrackBands::default()));
+        self.sink.play();
+        self.test_signal = true;
+        Ok(())
+    }
+
+    /// Peak frequency in Hz of what's playing right now, for a tuner or visualizer overlay.
+    ///
+    /// Returns `None` when nothing is playing, or when the audio is too quiet or too noisy to
+    /// have a clear peak.
+    pub fn dominant_frequency(&self) -> Option<f32> {
+        if self.current_track.as_ref().is_none_or(|t| t.is_paused()) {
+            return None;
+        }
+        let (samples, sample_rate) = self.controls.analysis.lock().snapshot()?;
+        spectrum::dominant_frequency(&samples, sample_rate)
+    }
+
+    /// Nearest musical note to `dominant_frequency`, e.g. "A4"
+    pub fn dominant_note(&self) -> Option<String> {
+        spectrum::note_name(self.dominant_frequency()?)
+    }
+
+    /// Call `callback` with the spectrum of what's playing, `settings.rate_hz` times a
+    /// second, for a visualizer. Replaces any earlier analyzer.
+    ///
+    /// The callback runs on a thread of its own and gets one magnitude per band, around 1.0
+    /// for a full-scale sine. It isn't called while nothing new is playing. A callback slower
+    /// than the update rate just gets fewer updates; playback never waits for it. Rates are
+    /// clamped to 1..=120; a rate that isn't a number is an error.
+    pub fn set_analyzer(
+        &mut self,
+        settings: AnalyzerSettings,
+        callback: SpectrumCallback,
+    ) -> Result<()> {
+        self.analyzer = Some(Analyzer::start(self.controls.clone(), settings, callback)?);
+        Ok(())
+    }
+
+    /// Stop calling the `set_analyzer` callback
+    pub fn clear_analyzer(&mut self) {
+        self.analyzer = None;
+    }
+
+    /// What the loaded track supports; all flags are off when nothing is loaded
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Cover art embedded in the current track, preferring the front cover when there are
+    /// several images. `None` when nothing is loaded or the file has no artwork.
+    ///
+    /// The image is read from the file on each call rather than kept in memory, so cache it
+    /// per track if it's needed often.
+    pub fn current_artwork(&self) -> Option<Artwork> {
+        let track = self.current_track.as_ref()?;
+        // Unreadable artwork is treated the same as none
+        probe::read_artwork(&track.info.path).ok().flatten()
+    }
+
+    /// Human-readable description of the signal chain for the current track, from the file's
+    /// format through processing to the output device, for attaching to bug reports
+    pub fn format_report(&self) -> String {
+        let mut lines = Vec::new();
+        match (&self.current_track, self.format) {
+            (Some(track), Some((channels, rate))) => {
+                lines.push(format!(
+                    "Source: {} ({}, {} ch, {} Hz)",
+                    track.info.path.display(),
+                    self.codec.as_deref().unwrap_or("unknown codec"),
+                    channels,
+                    rate
+                ));
+                let sample_format = match self.symphonia {
+                    Some(_) => self.controls.dsp().decode_format(),
+                    None => InternalFormat::F32,
+                };
+                lines.push(format!(
+                    "Decode: {}, {}",
+                    match &self.cache {
+                        Some(cache) if cache.in_memory() => "cached in memory",
+                        Some(_) => "cached in a temp file",
+                        None => "streamed",
+                    },
+                    match sample_format {
+                        InternalFormat::F32 => "32-bit float",
+                        InternalFormat::I32 => "32-bit integer",
+                        InternalFormat::I16 => "16-bit integer",
+                    }
+                ));
+                if let Some(bitrate) = self.current_bitrate() {
+                    lines.push(format!("Bitrate: {} kbps", bitrate / 1000));
+                }
+            }
+            _ => lines.push("Source: nothing loaded".to_string()),
+        }
+
+        let dsp = self.controls.dsp();
+        let mut stages = dsp.stage_names();
+        if stages.is_empty() {
+            stages.push("none".to_string());
+        }
+        lines.push(format!("Processing: {}", stages.join(" -> ")));
+        if let Some(end) = self.play_until {
+            lines.push(format!("Trim: stops at {} ms", end.as_millis()));
+        }
+        lines.push(format!(
+            "Volume: {:.0}% ({:.1} dB){}",
+            self.volume * 100.0,
+            20.0 * volume_gain(self.volume).max(1e-10).log10(),
+            if self.muted { ", muted" } else { "" }
+        ));
+        if self.replaygain != ReplayGainMode::Off {
+            lines.push(format!(
+                "ReplayGain: {:?}, {:+.1} dB",
+                self.replaygain,
+                20.0 * self.replaygain_gain().log10()
+            ));
+        }
+
+        if let Some((channels, rate)) = self.format {
+            if self.upsample > 1 {
+                lines.push(format!(
+                    "Upsampling: {}x, {} Hz -> {} Hz",
+                    self.upsample,
+                    rate,
+                    rate * self.upsample as u32
+                ));
+            }
+            let rate = rate * self.upsample as u32;
+            if rate != self.output_sample_rate {
+                lines.push(format!(
+                    "Resampling: {} Hz -> {} Hz",
+                    rate, self.output_sample_rate
+                ));
+            }
+            if channels != self.output_channels {
+                lines.push(format!(
+                    "Channel conversion: {} ch -> {} ch",
+                    channels, self.output_channels
+                ));
+            }
+        }
+        lines.extend(dsp.output_corrections(&self.output_channel_layout()));
+        lines.push(format!(
+            "Output: {} ({} ch, {} Hz){}",
+            self.output_device_name
+                .as_deref()
+                .unwrap_or("unknown device"),
+            self.output_channels,
+            self.output_sample_rate,
+            if self.output.is_some() {
+                ""
+            } else {
+                ", released"
+            }
+        ));
+        lines.join("\n")
+    }
+
+    /// What each output channel represents: the override from `set_output_layout` if any,
+    /// otherwise the conventional layout for the device's channel count
+    pub fn output_channel_layout(&self) -> ChannelLayout {
+        self.output_layout
+            .clone()
+            .unwrap_or_else(|| ChannelLayout::for_channels(self.output_channels))
+    }
+
+    /// Override the speaker labels of the output channels, e.g. for an unusually wired
+    /// multichannel interface. `None` goes back to the conventional layout. The layout must
+    /// have one label per output channel.
+    pub fn set_output_layout(&mut self, layout: Option<ChannelLayout>) -> Result<()> {
+        if let Some(layout) = &layout {
+            if layout.channels() != self.output_channels {
+                anyhow::bail!(
+                    "Layout has {} channels but the output has {}",
+                    layout.channels(),
+                    self.output_channels
+                );
+            }
+        }
+        self.output_layout = layout;
+        Ok(())
+    }
+
+    /// Record what's sent to the output into a 32-bit float WAV file while it plays.
+    ///
+    /// The recording is taken after every processing stage, channel delay and polarity
+    /// inversion, at the current volume and in the output's channel count. Two things differ
+    /// from what the speakers play: the outgoing track of a crossfade and the ramp-out on
+    /// `stop` aren't recorded, and the recording stays at normal speed when `set_speed` is
+    /// used. It's at the track's own sample rate; the device may resample.
+    ///
+    /// Passing `None` (or another path) stops the current recording and finalizes its file;
+    /// errors writing it, including frames lost because the disk couldn't keep up, are
+    /// returned then. The recording carries on across track changes; if a new track has a
+    /// different channel count or sample rate, the current file is finalized and recording
+    /// continues in `<stem>-<n>.wav` next to it.
+    pub fn set_tee_output<P: AsRef<Path>>(&mut self, path: Option<P>) -> Result<()> {
+        let tee = path
+            .map(|path| Tee::start(path.as_ref().to_path_buf()))
+            .transpose()?;
+        // Swap under the lock, but wait for the old file outside it so the audio thread never
+        // has to skip more than the frame that raced the swap
+        let old = std::mem::replace(&mut *self.controls.tee.lock(), tee);
+        match old {
+            Some(old) => old.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand everything sent to the output to `sink` as well, e.g. to feed an encoder for
+    /// network streaming. `None` (the default) removes it.
+    ///
+    /// The callback runs on the audio thread with blocks of about a thousand interleaved
+    /// frames, plus their sample rate and channel count, so it must return quickly. It sees
+    /// the same samples as `set_tee_output`, with the same exceptions.
+    pub fn set_pcm_sink(&mut self, sink: Option<PcmSink>) {
+        self.controls.pcm_sink.lock().set(sink);
+    }
+
+    /// Choose whether seeking while paused keeps the track paused (the default) or resumes it
+    pub fn set_seek_while_paused(&mut self, behavior: SeekPausedBehavior) {
+        self.seek_paused_behavior = behavior;
+    }
+
+    /// Blend briefly from the old position into the new one on seek instead of jumping.
+    ///
+    /// The outgoing audio fades out over `duration` while the new position fades in. Zero (the
+    /// default) seeks hard. Seeks while paused are never blended.
+    pub fn set_seek_crossfade(&mut self, duration: Duration) {
+        self.seek_crossfade = duration;
+    }
+
+    /// Automate the output gain over the current track's position.
+    ///
+    /// `points` are (position, linear gain) breakpoints; the gain is interpolated linearly
+    /// between them and held at the first/last value outside their range. The envelope stays
+    /// in effect across seeks and track changes until replaced; an empty list removes it.
+    pub fn apply_gain_envelope(&mut self, points: Vec<(Duration, f32)>) {
+        let envelope = GainEnvelope::new(points);
+        self.controls.update_dsp(|dsp| dsp.gain_envelope = envelope);
+    }
+
+    /// Remove any DC offset from the output with a gentle high-pass filter. DC wastes headroom
+    /// and can thump speakers; the filter sits far below the audible range. On by default.
+    pub fn set_dc_blocker(&mut self, enabled: bool) {
+        self.controls.update_dsp(|dsp| dsp.dc_blocker = enabled);
+    }
+
+    /// Sample format tracks decoded packet by packet (`load_and_play_symphonia`,
+    /// `load_and_play_url`) are decoded into. An integer format passes a 16- or 32-bit file's
+    /// samples through untouched when nothing else processes them; while any processing stage
+    /// in `format_report` is on, decoding goes back to `F32`. Applies from the next packet.
+    /// Defaults to `F32`.
+    pub fn set_internal_sample_format(&mut self, format: InternalFormat) {
+        self.controls.update_dsp(|dsp| dsp.sample_format = format);
+    }
+
+    pub fn internal_sample_format(&self) -> InternalFormat {
+        self.controls.dsp().sample_format
+    }
+
+    /// Delay each output channel independently, in milliseconds, to time-align speakers at
+    /// different distances (about 2.9 ms per metre of extra distance).
+    ///
+    /// Give one delay per output channel, each 0-50 ms; all zeros turns alignment off. The tee
+    /// recording includes the delays, as it's what the speakers play.
+    pub fn set_channel_delay(&mut self, delays_ms: Vec<f32>) -> Result<()> {
+        if delays_ms.len() != self.output_channels as usize {
+            anyhow::bail!(
+                "Got {} delays but the output has {} channels",
+                delays_ms.len(),
+                self.output_channels
+            );
+        }
+        let max = dsp::channel_delay::MAX_DELAY_MS;
+        if let Some(bad) = delays_ms.iter().find(|d| !(0.0..=max).contains(*d)) {
+            anyhow::bail!("Channel delay {} ms is outside 0-{} ms", bad, max);
+        }
+        self.controls
+            .update_dsp(|dsp| dsp.channel_delays = delays_ms);
+        Ok(())
+    }
+
+    /// Fade the output to silence over `duration` on `stop` rather than cutting it off, which
+    /// can click. Keep it short, around 10-20 ms; zero cuts off immediately. Defaults to 20 ms.
+    ///
+    /// `stop` still returns at once and reports the player as stopped; the ramp plays out on
+    /// the audio thread. Loading a new track cuts a ramp still in progress short.
+    pub fn set_stop_ramp(&mut self, duration: Duration) {
+        self.controls.update_dsp(|dsp| dsp.stop_ramp = duration);
+    }
+
+    /// Fade each track in over `duration` when `load_and_play` or `load_and_play_symphonia`
+    /// starts it, and out over the same length on `stop`, so neither end pops. Defaults to
+    /// 20 ms; zero starts and stops hard.
+    ///
+    /// Sets the same ramp as `set_stop_ramp`. A crossfade into the next queued track uses its
+    /// own length instead.
+    pub fn set_fade(&mut self, duration: Duration) {
+        self.fade = duration;
+        self.set_stop_ramp(duration);
+    }
+
+    /// Invert the polarity of selected output channels, to correct a miswired speaker or
+    /// compare absolute polarity by ear.
+    ///
+    /// Give one flag per output channel; all false turns inversion off. Like channel delays,
+    /// the inversion is in the tee recording too.
+    pub fn set_invert_polarity(&mut self, channels: Vec<bool>) -> Result<()> {
+        if channels.len() != self.output_channels as usize {
+            anyhow::bail!(
+                "Got {} polarity flags but the output has {} channels",
+                channels.len(),
+                self.output_channels
+            );
+        }
+        self.controls
+            .update_dsp(|dsp| dsp.invert_polarity = channels);
+        Ok(())
+    }
+
+    /// Protect Bluetooth listeners from codec artifacts on wide or hot masters.
+    ///
+    /// When engaged, the stereo width is reduced slightly, the DC blocker is forced on and a
+    /// gentle peak limiter runs last in the chain. `Auto` engages only when the output device
+    /// looks like a Bluetooth sink by name; returns whether the mode is now engaged.
+    pub fn set_bluetooth_safe_mode(&mut self, mode: BluetoothSafeMode) -> bool {
+        let engaged = match mode {
+            BluetoothSafeMode::Off => false,
+            BluetoothSafeMode::Auto => self.output_looks_bluetooth(),
+            BluetoothSafeMode::On => true,
+        };
+        self.controls.update_dsp(|dsp| dsp.bluetooth_safe = engaged);
+        engaged
+    }
+
+    /// Best guess from the device name; hosts don't report the transport directly
+    fn output_looks_bluetooth(&self) -> bool {
+        self.output_device_name.as_deref().is_some_and(|name| {
+            let name = name.to_lowercase();
+            ["bluetooth", "bluez", "a2dp", "airpods"]
+                .iter()
+                .any(|hint| name.contains(hint))
+        })
+    }
+
+    /// Temporarily boost tracks that fade in from near-silence, so listeners don't turn the
+    /// volume up during the intro and get blasted once the track arrives. The boost fades out
+    /// as the track reaches full level and doesn't come back later in the track.
+    pub fn set_intro_assist(&mut self, enabled: bool) {
+        self.controls.update_dsp(|dsp| dsp.intro_assist = enabled);
+    }
+
+    /// Duck the music while an external side-chain signal (e.g. a microphone) is active, the
+    /// building block for talking over music.
+    ///
+    /// While the level fed through `push_duck_level` is above `threshold_db` (dBFS), the music
+    /// is brought down to `duck_level` (linear gain), and restored once it drops below.
+    pub fn set_ducking_input(&mut self, enabled: bool, threshold_db: f32, duck_level: f32) {
+        self.controls.update_dsp(|dsp| {
+            dsp.ducking = enabled.then(|| Ducking {
+                threshold_db,
+                duck_level: duck_level.clamp(0.0, 1.0),
+                ..dsp.ducking.unwrap_or_default()
+            });
+        });
+    }
+
+    /// How quickly ducking engages (`attack`) and lets go (`release`)
+    pub fn set_ducking_timing(&mut self, attack: Duration, release: Duration) {
+        self.controls.update_dsp(|dsp| {
+            if let Some(ducking) = &mut dsp.ducking {
+                ducking.attack = attack;
+                ducking.release = release;
+            }
+        });
+    }
+
+    /// Report the current side-chain level as a linear amplitude
+    pub fn push_duck_level(&self, level: f32) {
+        self.controls.ducking_input.push(level);
+    }
+
+    /// A handle for pushing side-chain levels from another thread, e.g. an audio input callback
+    pub fn ducking_input(&self) -> DuckingInput {
+        self.controls.ducking_input.clone()
+    }
+
+    /// Continuously ride the gain to keep short-term loudness near a target, like a broadcast
+    /// leveler. Unlike per-track normalization this evens out quiet and loud passages within a
+    /// track. `None` turns leveling off.
+    pub fn set_loudness_leveling(&mut self, leveling: Option<LoudnessLeveling>) {
+        self.controls
+            .update_dsp(|dsp| dsp.loudness_leveling = leveling);
+    }
+
+    /// Even out quiet and loud passages with a dynamic range compressor, e.g. for listening
+    /// late at night or somewhere noisy. `None` turns it off.
+    pub fn set_compressor(&mut self, compression: Option<Compression>) {
+        self.controls.update_dsp(|dsp| dsp.compressor = compression);
+    }
+
+    /// Heavy compression plus a peak limiter, so quiet passages stay audible and loud ones
+    /// don't jump out. Turning night mode off removes both, including a compressor set with
+    /// `set_compressor`.
+    pub fn set_night_mode(&mut self, enabled: bool) {
+        self.controls.update_dsp(|dsp| {
+            dsp.compressor = enabled.then(Compression::night);
+            dsp.limiter = enabled;
+        });
+    }
+
+    /// Shape the sound with a parametric equalizer of peaking `bands`, applied in order.
+    /// An empty list (the default) bypasses it; bands with no gain are dropped.
+    pub fn set_eq(&mut self, mut bands: Vec<EqBand>) {
+        bands.retain(|band| band.gain_db != 0.0);
+        self.controls.update_dsp(|dsp| dsp.eq = bands);
+    }
+
+    /// Equalize the queue entry at `index`, in `queue` order, with its own `bands` in place
+    /// of the ones from `set_eq`, or go back to those with `None`. `Some` of no bands plays
+    /// it flat. Takes effect while the entry plays, including when it already is, and stays
+    /// with the entry as the queue around it changes.
+    pub fn set_track_eq(&mut self, index: usize, bands: Option<Vec<EqBand>>) -> Result<()> {
+        let Some(track_eq) = self.queue.track_eq(index) else {
+            anyhow::bail!("No queue entry {}", index);
+        };
+        *track_eq.lock() = bands.map(|mut bands| {
+            bands.retain(|band| band.gain_db != 0.0);
+            bands
+        });
+        // Pipelines playing the entry pick it up with the other settings
+        self.controls.update_dsp(|_| {});
+        Ok(())
+    }
+
+    /// Equalize tracks tagged with a genre known here with the built-in preset that suits it,
+    /// e.g. "Rock" for rock and metal or "Flat" for classical, in place of the bands from
+    /// `set_eq`, which tracks of other genres or none keep. A queue entry's own bands from
+    /// `set_track_eq` still come first. Takes effect on the track playing too. Off by
+    /// default.
+    pub fn set_genre_eq(&mut self, enabled: bool) {
+        self.controls.update_dsp(|dsp| dsp.genre_eq = enabled);
+    }
+
+    pub fn genre_eq(&self) -> bool {
+        self.controls.dsp().genre_eq
+    }
+
+    /// The bands equalizing the current track: its queue entry's own, its genre's preset
+    /// under `set_genre_eq`, or the global ones
+    pub fn current_eq(&self) -> Vec<EqBand> {
+        let dsp = self.controls.dsp();
+        let Some(track) = &self.current_track else {
+            return dsp.eq;
+        };
+        let queued = self.queue.current_path() == Some(track.info.path.as_path());
+        let track_eq = queued.then(|| self.queue.current_track_eq()).flatten();
+        TrackBands::new(track_eq, track.info.genre.as_deref()).resolve(&dsp.eq, dsp.genre_eq)
+    }
+
+    /// The bands `set_track_eq` gave the queue entry at `index`, if any
+    pub fn track_eq(&self, index: usize) -> Option<Vec<EqBand>> {
+        self.queue.track_eq(index)?.lock().clone()
+    }
+
+    /// The equalizer bands in use, as `set_eq` left them
+    pub fn eq(&self) -> Vec<EqBand> {
+        self.controls.dsp().eq.clone()
+    }
+
+    /// Set the equalizer to the preset called `name`: one of the built-ins "Flat", "Rock",
+    /// "Jazz", "Vocal Boost" and "Bass Boost", or one saved with `save_eq_preset`
+    pub fn apply_eq_preset(&mut self, name: &str) -> Result<()> {
+        let bands = match dsp::eq::built_in_preset(name) {
+            Some(bands) => bands,
+            None => Store::load(&self.position_store)?
+                .eq_presets
+                .remove(name)
+                .with_context(|| format!("No equalizer preset {:?}", name))?,
+        };
+        self.set_eq(bands);
+        Ok(())
+    }
+
+    /// Save `bands` as the equalizer preset `name`, replacing any saved under that name.
+    /// Presets are kept in the position store alongside bookmarks. The built-in presets
+    /// can't be replaced.
+    pub fn save_eq_preset(&self, name: &str, bands: Vec<EqBand>) -> Result<()> {
+        if dsp::eq::built_in_preset(name).is_some() {
+            anyhow::bail!("{:?} is a built-in equalizer preset", name);
+        }
+        let mut store = Store::load(&self.position_store)?;
+        store.eq_presets.insert(name.to_string(), bands);
+        store.save(&self.position_store)
+    }
+
+    /// Names of the equalizer presets `apply_eq_preset` takes: the built-ins, then the saved
+    /// ones in alphabetical order
+    pub fn eq_presets(&self) -> Result<Vec<String>> {
+        let store = Store::load(&self.position_store)?;
+        Ok(dsp::eq::built_in_presets()
+            .map(str::to_string)
+            .chain(store.eq_presets.into_keys())
+            .collect())
+    }
+
+    /// Choose how tracks loaded from now on are decoded.
+    ///
+    /// `Memory` and `TempFile` decode the whole track up front, so loading takes longer but
+    /// seeking no longer has to decode from the start of the file. Temp files are removed when
+    /// the track is replaced, stopped, or the player is dropped.
+    pub fn set_decode_cache(&mut self, policy: DecodeCachePolicy) {
+        self.decode_cache = policy;
+    }
+
+    /// Move the current track to `to_ms`, stopping if that is past its end.
+    ///
+    /// Lands on the exact sample for tracks loaded with `load_and_play_symphonia` or held in
+    /// a decode cache, and falls back to `seek_approx` otherwise. Whether a paused track
+    /// stays paused follows `set_seek_while_paused`.
+    pub fn seek(&mut self, to_ms: u64) -> Result<()> {
+        self.seek_to(to_ms, true)
+    }
+
+    /// Seek by re-opening the file with rodio and skipping to `to_ms`, which is approximate
+    /// and has to decode everything before the target. Prefer `seek`.
+    pub fn seek_approx(&mut self, to_ms: u64) -> Result<()> {
+        self.seek_to(to_ms, false)
+    }
+
+    fn seek_to(&mut self, to_ms: u64, exact: bool) -> Result<()> {
+        self.take_over_lined_up();
+        let (info, paused) = match &self.current_track {
+            Some(track) => (track.info.clone(), track.is_paused()),
+            None => return Ok(()), // No track to seek
+        };
+        if !self.capabilities.seekable {
+            anyhow::bail!("Can't seek in {:?}: it's a stream", info.path);
+        }
+        let path = &info.path;
+        let resume = !paused || self.seek_paused_behavior == SeekPausedBehavior::Resume;
+        if resume {
+            self.ensure_output()?;
+        }
+        let from = Duration::from_millis(self.current_position_ms());
+        let to = Duration::from_millis(to_ms);
+
+        let incoming = if exact {
+            self.exact_source_at(path, to)?
+        } else {
+            self.source_at(path, to)?
+        };
+        let Some(incoming) = incoming else {
+            // Seeking past EOF: just stop.
+            self.stop();
+            return Ok(());
+        };
+        // From before the end of an A-B loop, playback runs into it and goes around
+        let looping = self.ab_loop.as_ref().filter(|region| to_ms < region.end_ms);
+        let incoming: BoxedSource = match looping {
+            Some(region) => Box::new(region.wrap(incoming, to)),
+            None => incoming,
+        };
+        let looping = looping.map(|region| (region.resume_ms, region.end_ms));
+        // Only blend when the old position was actually audible. The outgoing audio can't
+        // share the Symphonia decoder with the incoming, so it always comes from rodio.
+        let source = match (self.seek_crossfade, paused) {
+            (fade, false) if !fade.is_zero() => match self.source_at(path, from) {
+                Ok(Some(outgoing)) => {
+                    let mut outgoing = outgoing.take_duration(fade);
+                    outgoing.set_filter_fadeout();
+                    Box::new(incoming.fade_in(fade).mix(outgoing))
+                }
+                _ => incoming,
+            },
+            _ => incoming,
+        };
+
+        self.sink.clear();
+        self.play_source(source, to, &info);
+        // `clear` leaves the sink paused
+        if resume {
+            self.sink.play();
+        }
+
+        if let Some(track) = &mut self.current_track {
+            track.set_position(to_ms);
+            track.looping = looping;
+            if resume && track.is_paused() {
+                track.resume();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Seek to a fraction (0.0 to 1.0) of the current track's length
+    pub fn seek_fraction(&mut self, fraction: f64) -> Result<()> {
+        let Some(track) = &self.current_track else {
+            return Ok(()); // No track to seek
+        };
+        let Some(duration_ms) = track.info.duration_ms else {
+            anyhow::bail!("Track length is unknown");
+        };
+        self.seek((duration_ms as f64 * fraction.clamp(0.0, 1.0)) as u64)
+    }
+
+    /// Wrap `source`, which starts `at` into the track, in the processing chain for the sink,
+    /// equalized with `track_bands` where the track has its own
+    fn processed(
+        &self,
+        source: BoxedSource,
+        at: Duration,
+        track_bands: TrackBands,
+    ) -> OutputStage<Upsample<Pipeline<BoxedSource>>> {
+        self.loader().processed(source, at, track_bands)
+    }
+
+    /// Put `source`, the current track described by `info` from `at`, in the sink, reporting
+    /// `info` to `on_track_end` subscribers if it plays out. A track from the queue plays
+    /// from a nested queue that the following entries are lined up in.
+    fn play_source(&mut self, source: BoxedSource, at: Duration, info: &TrackInfo) {
+        self.lineup.withdraw();
+        self.clear_queue_end_fade();
+        let queued = self.queue.current_path() == Some(info.path.as_path());
+        let track_eq = queued.then(|| self.queue.current_track_eq()).flatten();
+        let track_bands = TrackBands::new(track_eq, info.genre.as_deref());
+        let source = NotifyOnEnd::new(
+            self.processed(source, at, track_bands),
+            self.controls.clone(),
+            info.clone(),
+        );
+        if !queued {
+            self.sink.append(source);
+            return;
+        }
+        // Kept alive while the next entry is on its way; `Lineup` lets it end after the last
+        let (lined_up, output) = rodio::queue::queue(true);
+        let (id, near_end) = self.lineup.track();
+        let remaining = info
+            .duration_ms
+            .map(|d| Duration::from_millis(d).saturating_sub(at));
+        lined_up.append(source.near_end(remaining, lineup::LEAD, near_end));
+        self.sink.append(output);
+        let plan = self.plan();
+        self.lineup.follow(lined_up, plan, id);
+    }
+
+    /// How the queue goes on from the current track, for `Lineup`
+    fn plan(&self) -> Plan {
+        Plan {
+            queue: self.queue.clone(),
+            repeat: self.queue_repeat(),
+            loader: self.loader(),
+            preload: self.gapless,
+            join_albums: self.no_crossfade_albums,
+            device_rate: (self.auto_device_rate || self.upsample > 1)
+                .then_some((self.output_sample_rate, self.device_rate)),
+        }
+    }
+
+    /// The current track from exactly `at` through its Symphonia decoder, falling back to
+    /// `source_at` when it wasn't loaded with one
+    fn exact_source_at(&self, path: &Path, at: Duration) -> Result<Option<BoxedSource>> {
+        let Some(shared) = &self.symphonia else {
+            return self.source_at(path, at);
+        };
+        // A stream carries on from wherever the download has got to
+        if !self.capabilities.seekable {
+            return Ok(Some(Box::new(SymphoniaSource::new(
+                shared.clone(),
+                self.controls.clone(),
+            ))));
+        }
+        if self.play_until.is_some_and(|end| at >= end) || !shared.lock().seek(at)? {
+            return Ok(None);
+        }
+        let src = Box::new(SymphoniaSource::new(shared.clone(), self.controls.clone()));
+        Ok(Some(self.limit_to_play_until(src, at)))
+    }
+
+    /// The current track from `at`, or `None` if that is past its end
+    fn source_at(&self, path: &Path, at: Duration) -> Result<Option<BoxedSource>> {
+        if self.play_until.is_some_and(|end| at >= end) {
+            return Ok(None);
+        }
+        if let Some(cache) = &self.cache {
+            if at >= cache.duration() {
+                return Ok(None);
+            }
+            let src = Box::new(cache.source_at(at)?);
+            return Ok(Some(self.limit_to_play_until(src, at)));
+        }
+
+        // Open once to query total duration
+        let src = self.loader().open_decoder(path)?;
+        if src.total_duration().is_some_and(|total| at >= total) {
+            return Ok(None);
+        }
+        let skipped = src.skip_duration(at); // returns a Source wrapper, not a Duration
+        let src = Box::new(skipped.convert_samples());
+        Ok(Some(self.limit_to_play_until(src, at)))
+    }
+
+    /// Cut `source`, which starts `at` into the track, off at the trimmed end of the track
+    fn limit_to_play_until(&self, source: BoxedSource, at: Duration) -> BoxedSource {
+        cut_at(source, self.play_until, at)
+    }
+
+    /// Skip forward (positive `delta_ms`) or back (negative) from the current position.
+    ///
+    /// Rewinding past the start lands at the start; going past the end stops the track,
+    /// the same as `seek`. Does nothing when no track is loaded.
+    pub fn advance_or_rewind(&mut self, delta_ms: i64) -> Result<()> {
+        let Some(current) = self.position_ms() else {
+            return Ok(()); // No track to seek
+        };
+        let mut target = current.saturating_add_signed(delta_ms);
+        if let Some(duration_ms) = self.current_track.as_ref().and_then(|t| t.info.duration_ms) {
+            target = target.min(duration_ms);
+        }
+        self.seek(target)
+    }
+
+    /// Mark `(start, end)` ranges of the current track, such as ads, to skip during playback.
+    /// Replaces any earlier markers; empty ranges are ignored. `read_chapter_ad_markers`
+    /// finds the ads in a Podcasting 2.0 chapters file.
+    ///
+    /// Markers belong to the loaded track, so set them after loading it; loading another
+    /// track clears them. The skip happens in `poll_skip_markers`.
+    pub fn set_skip_markers(&mut self, mut markers: Vec<(Duration, Duration)>) {
+        markers.retain(|(start, end)| start < end);
+        markers.sort();
+        self.skip_markers = markers;
+    }
+
+    /// Seek to the end of the marked range the position is in, if any, returning the range
+    /// skipped. A range running past the end of the track stops it.
+    ///
+    /// Call this regularly while playing, e.g. from the same timer as `poll_queue`; a skip
+    /// lands at most one call late. Seeking into a marked range by hand skips it as well.
+    pub fn poll_skip_markers(&mut self) -> Result<Option<(Duration, Duration)>> {
+        if self.skip_markers.is_empty() || self.state() != PlaybackState::Playing {
+            return Ok(None);
+        }
+        let Some(position) = self.position_ms().map(Duration::from_millis) else {
+            return Ok(None);
+        };
+        // Where markers overlap, land past all of them at once
+        let mut skipped: Option<(Duration, Duration)> = None;
+        for &(start, end) in &self.skip_markers {
+            let at = skipped.map_or(position, |(_, to)| to);
+            if start <= at && at < end {
+                skipped = Some((skipped.map_or(start, |(from, _)| from), end));
+            }
+        }
+        let Some(range) = skipped else {
+            return Ok(None);
+        };
+        self.seek(u64::try_from(range.1.as_millis()).unwrap_or(u64::MAX))?;
+        Ok(Some(range))
+    }
+
+    /// Repeat the current track from `start_ms` to `end_ms`, e.g. to practise a passage. Fails
+    /// unless `start_ms` is before `end_ms` and both are within the track, or when the track
+    /// can't seek.
+    ///
+    /// The region is decoded into memory and played around in the source, blended over 10 ms
+    /// at the seam so it doesn't click, with no polling needed; the position wraps with it.
+    /// Seeking elsewhere keeps the loop: from before the region, playback runs into it and
+    /// loops; from past its end, playback carries on. Loading another track clears it.
+    pub fn set_ab_loop(&mut self, start_ms: u64, end_ms: u64) -> Result<()> {
+        let Some(track) = &self.current_track else {
+            anyhow::bail!("No track loaded");
+        };
+        if start_ms >= end_ms {
+            anyhow::bail!(
+                "Loop start {} ms is not before its end {} ms",
+                start_ms,
+                end_ms
+            );
+        }
+        if let Some(duration_ms) = track.info.duration_ms {
+            if end_ms > duration_ms {
+                anyhow::bail!(
+                    "Loop end {} ms is past the end of the track at {} ms",
+                    end_ms,
+                    duration_ms
+                );
+            }
+        }
+        if !self.capabilities.seekable {
+            anyhow::bail!("Can't loop {:?}: it's a stream", track.info.path);
+        }
+        let path = track.info.path.clone();
+        let Some(source) = self.source_at(&path, Duration::from_millis(start_ms))? else {
+            anyhow::bail!("Loop start {} ms is past the end of the track", start_ms);
+        };
+        let (channels, sample_rate) = (source.channels(), source.sample_rate());
+        let samples: Vec<f32> = source
+            .take_duration(Duration::from_millis(end_ms - start_ms))
+            .collect();
+        let region = ab_loop::Region::new(
+            start_ms,
+            end_ms,
+            &samples,
+            channels,
+            sample_rate,
+            AB_LOOP_CROSSFADE,
+        )
+        .context("Loop region is empty")?;
+        self.ab_loop = Some(region);
+        self.reloop()
+    }
+
+    /// Stop repeating the A-B loop; playback carries on from where it is
+    pub fn clear_ab_loop(&mut self) -> Result<()> {
+        if self.ab_loop.take().is_none() {
+            return Ok(());
+        }
+        self.reloop()
+    }
+
+    /// The A-B loop region as (start, end) in ms, if one is set
+    pub fn ab_loop(&self) -> Option<(u64, u64)> {
+        self.ab_loop
+            .as_ref()
+            .map(|region| (region.start_ms, region.end_ms))
+    }
+
+    /// Replace the playing source with one from the current position that follows the A-B
+    /// loop as it is now, leaving a paused track paused
+    fn reloop(&mut self) -> Result<()> {
+        let Some(track) = &self.current_track else {
+            return Ok(());
+        };
+        let position = track.current_position_ms();
+        let loops = self.ab_loop.as_ref().is_some_and(|r| position < r.end_ms);
+        if !loops && track.looping.is_none() {
+            return Ok(());
+        }
+        let crossfade = self.seek_crossfade;
+        let behavior = self.seek_paused_behavior;
+        self.seek_crossfade = crossfade.max(AB_LOOP_CROSSFADE);
+        self.seek_paused_behavior = SeekPausedBehavior::StayPaused;
+        let seeked = self.seek(position);
+        self.seek_crossfade = crossfade;
+        self.seek_paused_behavior = behavior;
+        seeked
+    }
+
+    /// Stop playback `after` from now, e.g. to fall asleep to music. With `fade_out`, the
+    /// volume fades to silence over the last 30 seconds first. Replaces any earlier timer.
+    ///
+    /// The timer runs on its own thread and stops the sound on time without being polled;
+    /// the player catches up with it on its next `poll_queue` or `stop`. It carries on as the
+    /// queue moves on by itself, and is cancelled by `stop` and by loading another track,
+    /// including through `next` and `previous`.
+    pub fn set_sleep_timer(&mut self, after: Duration, fade_out: bool) {
+        self.clear_sleep_timer();
+        self.sleep_timer = Some(SleepTimer::start(
+            self.controls.clone(),
+            after,
+            fade_out.then_some(SLEEP_FADE),
+        ));
+    }
+
+    pub fn cancel_sleep_timer(&mut self) {
+        self.clear_sleep_timer();
+    }
+
+    /// Cancel the sleep timer and undo its fade, or its stop once it has fired
+    fn clear_sleep_timer(&mut self) {
+        // Stopped first, so it can't fade or stop the output again after the reset
+        self.sleep_timer = None;
+        self.controls.sleep.reset();
+    }
+
+    /// Time left until the sleep timer stops playback, for a countdown; `None` when no
+    /// timer is set or it has fired
+    pub fn sleep_timer_remaining(&self) -> Option<Duration> {
+        let timer = self.sleep_timer.as_ref().filter(|timer| !timer.fired())?;
+        Some(timer.remaining())
+    }
+
+    /// Keep the positions saved by `save_position`, and bookmarks, in the JSON file at `path`
+    /// instead of the default `cadence/positions.json` in the platform's config directory
+    pub fn set_position_store(&mut self, path: PathBuf) {
+        self.position_store = path;
+    }
+
+    pub fn position_store(&self) -> &Path {
+        &self.position_store
+    }
+
+    /// Remember the current track's position for `load_and_resume`, e.g. when closing an
+    /// audiobook. A track within 10 seconds of its end counts as finished and its saved
+    /// position is removed instead.
+    pub fn save_position(&self) -> Result<()> {
+        let Some(track) = &self.current_track else {
+            anyhow::bail!("No track loaded");
+        };
+        let position = track.current_position_ms();
+        let mut store = Store::load(&self.position_store)?;
+        let finished = track
+            .info
+            .duration_ms
+            .is_some_and(|d| position + RESUME_END_MARGIN_MS >= d);
+        if finished {
+            store.positions.remove(&track.info.path);
+        } else {
+            store.positions.insert(track.info.path.clone(), position);
+        }
+        store.save(&self.position_store)
+    }
+
+    /// Play `path` from the position last saved for it with `save_position`, or from the
+    /// start if there is none or it was within 10 seconds of the end. That nearly finished
+    /// position is removed from the store.
+    ///
+    /// Loads with `load_and_play_symphonia`, so even a long audiobook resumes without decoding
+    /// up to the saved position.
+    pub fn load_and_resume(&mut self, path: PathBuf) -> Result<TrackInfo> {
+        let mut store = Store::load(&self.position_store)?;
+        let saved = store.positions.get(&path).copied();
+        let info = self.load_and_play_symphonia(path)?;
+        let Some(saved) = saved else {
+            return Ok(info);
+        };
+        if info
+            .duration_ms
+            .is_some_and(|d| saved + RESUME_END_MARGIN_MS >= d)
+        {
+            store.positions.remove(&info.path);
+            store.save(&self.position_store)?;
+        } else {
+            self.seek(saved)?;
+        }
+        Ok(info)
+    }
+
+    /// Bookmark the current position of the current track under `label`. Bookmarks are kept
+    /// in the position store alongside resume positions.
+    pub fn add_bookmark(&self, label: String) -> Result<Bookmark> {
+        let Some(track) = &self.current_track else {
+            anyhow::bail!("No track loaded");
+        };
+        let mut store = Store::load(&self.position_store)?;
+        let bookmark =
+            store.add_bookmark(track.info.path.clone(), track.current_position_ms(), label);
+        store.save(&self.position_store)?;
+        Ok(bookmark)
+    }
+
+    /// The bookmarks saved for `path`, in track order
+    pub fn bookmarks(&self, path: &Path) -> Result<Vec<Bookmark>> {
+        let store = Store::load(&self.position_store)?;
+        let mut bookmarks: Vec<Bookmark> = store
+            .bookmarks
+            .into_iter()
+            .filter(|b| b.path == path)
+            .collect();
+        bookmarks.sort_by_key(|b| (b.position_ms, b.id));
+        Ok(bookmarks)
+    }
+
+    /// Seek to bookmark `id`, first loading its track with `load_and_play_symphonia` if
+    /// another one is playing
+    pub fn jump_to_bookmark(&mut self, id: BookmarkId) -> Result<Bookmark> {
+        let store = Store::load(&self.position_store)?;
+        let Some(bookmark) = store.bookmarks.into_iter().find(|b| b.id == id) else {
+            anyhow::bail!("No bookmark with id {}", id.0);
+        };
+        let loaded = self
+            .current_track
+            .as_ref()
+            .is_some_and(|t| t.info.path == bookmark.path);
+        if !loaded {
+            self.load_and_play_symphonia(bookmark.path.clone())?;
+        }
+        self.seek(bookmark.position_ms)?;
+        Ok(bookmark)
+    }
+
+    /// Delete bookmark `id`, returning whether there was one
+    pub fn remove_bookmark(&self, id: BookmarkId) -> Result<bool> {
+        let mut store = Store::load(&self.position_store)?;
+        let before = store.bookmarks.len();
+        store.bookmarks.retain(|b| b.id != id);
+        if store.bookmarks.len() == before {
+            return Ok(false);
+        }
+        store.save(&self.position_store)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{serve, sine, TempDir, TempFile};
+
+    /// A player that needs no output device, so the tests run on any machine
+    fn player() -> Player {
+        Player::new_headless().unwrap()
+    }
+
+    #[test]
+    fn play_continues_a_stopped_track_where_it_stopped() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        for symphonia in [false, true] {
+            let path = wav.path().to_path_buf();
+            match symphonia {
+                false => player.load_and_play(path).unwrap(),
+                true => player.load_and_play_symphonia(path).unwrap(),
+            };
+            player.seek(5_000).unwrap();
+            player.stop();
+            assert_eq!(player.state(), PlaybackState::Stopped);
+            assert_eq!(player.position_ms(), None);
+
+            player.play().unwrap();
+            assert_eq!(player.state(), PlaybackState::Playing);
+            let position = player.position_ms().unwrap();
+            assert!((5_000..5_500).contains(&position), "{}", position);
+            // Loaded again the same way
+            assert_eq!(player.symphonia.is_some(), symphonia);
+        }
+    }
+
+    #[test]
+    fn reports_the_format_and_tags_of_the_loaded_track() {
+        let mut player = player();
+        let samples = sine(440.0, 44_100, 2, 44_100);
+        let frames = [("TIT2", "So What"), ("TPE1", "Miles Davis")];
+        let tagged = TempFile::tagged_wav(&samples, 2, 44_100, &frames, None);
+        for symphonia in [false, true] {
+            let path = tagged.path().to_path_buf();
+            let info = match symphonia {
+                false => player.load_and_play(path).unwrap(),
+                true => player.load_and_play_symphonia(path).unwrap(),
+            };
+            assert_eq!((info.sample_rate, info.channels), (Some(44_100), Some(2)));
+            assert_eq!(info.title.as_deref(), Some("So What"));
+            assert_eq!(info.artist.as_deref(), Some("Miles Davis"));
+            assert_eq!(info.album, None);
+        }
+    }
+
+    /// Whether `position` is `expected` give or take the time the test takes to get there
+    fn near(position: Option<u64>, expected: u64) -> bool {
+        position.is_some_and(|p| (expected..expected + 500).contains(&p))
+    }
+
+    #[test]
+    fn moves_through_the_queue_without_polling() {
+        let mut player = player();
+        let first = TempFile::wav(&sine(440.0, 4_410, 2, 44_100), 2, 44_100);
+        let second = TempFile::wav(&sine(880.0, 44_100 * 10, 2, 44_100), 2, 44_100);
+        player.enqueue(first.path().to_path_buf()).unwrap();
+        player.enqueue(second.path().to_path_buf()).unwrap();
+        player.play_queue().unwrap();
+
+        // The first track ends well before this, and nothing polls in between
+        std::thread::sleep(Duration::from_millis(700));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        let info = player.poll_queue().unwrap().unwrap();
+        assert_eq!(info.path, second.path());
+        assert_eq!(player.queue_index(), Some(1));
+        assert!(
+            player.position_ms().is_some_and(|p| p >= 400),
+            "{:?}",
+            player.position_ms()
+        );
+    }
+
+    #[test]
+    fn advance_or_rewind_moves_within_the_track() {
+        let mut player = player();
+        // Nothing loaded yet
+        player.advance_or_rewind(1_000).unwrap();
+
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.seek(5_000).unwrap();
+        player.advance_or_rewind(3_000).unwrap();
+        assert!(
+            near(player.position_ms(), 8_000),
+            "{:?}",
+            player.position_ms()
+        );
+        player.advance_or_rewind(-2_000).unwrap();
+        assert!(
+            near(player.position_ms(), 6_000),
+            "{:?}",
+            player.position_ms()
+        );
+
+        // Clamped to the start
+        player.advance_or_rewind(-60_000).unwrap();
+        assert!(near(player.position_ms(), 0), "{:?}", player.position_ms());
+
+        // Past the end stops cleanly
+        player.advance_or_rewind(60_000).unwrap();
+        assert_ne!(player.state(), PlaybackState::Playing);
+    }
+
+    #[test]
+    fn resumes_from_a_saved_position() {
+        let mut player = player();
+        let store = TempFile::new("json");
+        player.set_position_store(store.path().to_path_buf());
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 30, 1, 44_100), 1, 44_100);
+        let path = wav.path().to_path_buf();
+
+        player.load_and_play(path.clone()).unwrap();
+        player.seek(12_000).unwrap();
+        player.save_position().unwrap();
+        player.stop();
+        player.load_and_resume(path.clone()).unwrap();
+        assert!(
+            near(player.position_ms(), 12_000),
+            "{:?}",
+            player.position_ms()
+        );
+
+        // Within 10 s of the end counts as finished: the position is dropped, not resumed
+        player.seek(25_000).unwrap();
+        player.save_position().unwrap();
+        player.load_and_resume(path.clone()).unwrap();
+        assert!(near(player.position_ms(), 0), "{:?}", player.position_ms());
+        assert!(!Store::load(store.path())
+            .unwrap()
+            .positions
+            .contains_key(&path));
+    }
+
+    #[test]
+    fn skips_marked_segments() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 1, 44_100), 1, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        let secs = Duration::from_secs;
+        player.set_skip_markers(vec![(secs(5), secs(10))]);
+
+        assert_eq!(player.poll_skip_markers().unwrap(), None);
+        player.seek(5_000).unwrap();
+        assert_eq!(
+            player.poll_skip_markers().unwrap(),
+            Some((secs(5), secs(10)))
+        );
+        assert!(
+            near(player.position_ms(), 10_000),
+            "{:?}",
+            player.position_ms()
+        );
+        assert_eq!(player.poll_skip_markers().unwrap(), None);
+    }
+
+    #[test]
+    fn seeking_while_paused_stays_paused_unless_asked_to_resume() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.pause();
+        player.seek(5_000).unwrap();
+        assert_eq!(player.state(), PlaybackState::Paused);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.position_ms(), Some(5_000));
+
+        player.set_seek_while_paused(SeekPausedBehavior::Resume);
+        player.seek(8_000).unwrap();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(
+            near(player.position_ms(), 8_000),
+            "{:?}",
+            player.position_ms()
+        );
+    }
+
+    #[test]
+    fn reports_what_the_loaded_track_supports() {
+        let mut player = player();
+        assert_eq!(player.capabilities(), Capabilities::default());
+        let wav = TempFile::wav(&sine(440.0, 44_100, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        let file = player.capabilities();
+        assert!(file.seekable && file.has_duration);
+        assert!(!file.accurate_seek && !file.has_chapters && !file.has_cover);
+        assert_eq!(file.channels, 2);
+        player
+            .load_and_play_symphonia(wav.path().to_path_buf())
+            .unwrap();
+        assert!(player.capabilities().accurate_seek);
+
+        let mono = TempFile::wav(&sine(440.0, 44_100, 1, 44_100), 1, 44_100);
+        let base = serve(std::fs::read(mono.path()).unwrap());
+        player
+            .load_and_play_url(&format!("{}/chunked", base))
+            .unwrap();
+        let stream = player.capabilities();
+        assert!(!stream.seekable && !stream.has_duration);
+        assert_eq!(stream.channels, 1);
+        assert!(player.seek(500).is_err());
+
+        player.stop();
+        assert_eq!(player.capabilities(), Capabilities::default());
+    }
+
+    #[test]
+    fn an_interruption_only_resumes_what_was_playing() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+
+        player.interrupt();
+        assert_eq!(player.state(), PlaybackState::Paused);
+        assert!(player.is_interrupted());
+        player.resume_from_interrupt();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(!player.is_interrupted());
+
+        // Paused by the user first, so it stays paused
+        player.pause();
+        player.interrupt();
+        player.resume_from_interrupt();
+        assert_eq!(player.state(), PlaybackState::Paused);
+
+        // Paused by the user during the interruption
+        player.resume();
+        player.interrupt();
+        player.pause();
+        player.resume_from_interrupt();
+        assert_eq!(player.state(), PlaybackState::Paused);
+    }
+
+    #[test]
+    fn pauses_on_screen_lock_only_when_asked() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+
+        player.screen_locked();
+        assert_eq!(player.state(), PlaybackState::Playing);
+
+        player.set_lock_behavior(LockBehavior::Pause);
+        player.screen_locked();
+        assert_eq!(player.state(), PlaybackState::Paused);
+        // A user-level pause: ending an interruption doesn't undo it
+        player.interrupt();
+        player.resume_from_interrupt();
+        assert_eq!(player.state(), PlaybackState::Paused);
+    }
+
+    #[test]
+    fn starts_paused_when_asked() {
+        let mut player = player();
+        player.set_start_paused(true);
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        let info = player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(info.duration_ms, Some(20_000));
+        assert_eq!(player.state(), PlaybackState::Paused);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.position_ms(), Some(0));
+
+        player.resume();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(player.position_ms().is_some_and(|p| p >= 100));
+
+        player.set_start_paused(false);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(player.state(), PlaybackState::Playing);
+    }
+
+    /// Collect everything `player` sends to the output from now on, downmixed to mono
+    fn capture(player: &mut Player) -> Arc<Mutex<Vec<f32>>> {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let sink = captured.clone();
+        player.set_pcm_sink(Some(Box::new(move |samples, _, channels| {
+            let channels = channels as usize;
+            sink.lock().extend(
+                samples
+                    .chunks(channels)
+                    .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+            );
+        })));
+        captured
+    }
+
+    /// Largest step between neighbouring samples
+    fn largest_step(samples: &[f32]) -> f32 {
+        samples
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn blends_across_a_seek() {
+        let mut player = player();
+        player.set_dc_blocker(false);
+        // Five seconds at +0.25 and five at -0.25, so a seek across shows as a jump or a ramp
+        let samples = [vec![0.25; 44_100 * 5], vec![-0.25; 44_100 * 5]].concat();
+        let wav = TempFile::wav(&samples, 1, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let captured = capture(&mut player);
+        player.seek(7_000).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        let hard = std::mem::take(&mut *captured.lock());
+        assert!(largest_step(&hard) > 0.4, "{}", largest_step(&hard));
+
+        player.seek(2_000).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        captured.lock().clear();
+        player.set_seek_crossfade(Duration::from_millis(100));
+        player.seek(7_000).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        let blended = std::mem::take(&mut *captured.lock());
+        assert!(largest_step(&blended) < 0.01, "{}", largest_step(&blended));
+        // Halfway through, both positions are heard equally
+        assert!(blended.iter().any(|s| s.abs() < 0.01));
+        assert!((blended.last().unwrap() + 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn position_and_remaining_add_up_to_the_duration() {
+        let mut player = player();
+        assert_eq!(player.times(), PlaybackTimes::default());
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.seek(5_000).unwrap();
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(50));
+            let times = player.times();
+            assert_eq!(times.duration_ms, Some(20_000));
+            assert!(near(Some(times.position_ms), 5_000), "{:?}", times);
+            assert_eq!(
+                times.position_ms + times.remaining_ms.unwrap(),
+                times.duration_ms.unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_volume_across_loads() {
+        let mut player = player();
+        player.set_volume(0.5);
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        for _ in 0..2 {
+            player.load_and_play(wav.path().to_path_buf()).unwrap();
+            assert_eq!(player.volume(), 0.5);
+            assert_eq!(player.sink.volume(), volume_gain(0.5));
+        }
+        player
+            .load_and_play_symphonia(wav.path().to_path_buf())
+            .unwrap();
+        assert_eq!(player.sink.volume(), volume_gain(0.5));
+        // Half way down the slider is 30 dB down
+        assert!((20.0 * volume_gain(0.5).log10() + 30.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mutes_without_losing_the_volume() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.set_volume(0.5);
+
+        player.mute();
+        assert!(player.is_muted());
+        assert_eq!(player.sink.volume(), 0.0);
+        assert_eq!(player.volume(), 0.5);
+        // Remembered for unmute, and still muted across a load
+        player.set_volume(0.8);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(player.sink.volume(), 0.0);
+
+        player.unmute();
+        assert!(!player.is_muted());
+        assert_eq!(player.sink.volume(), volume_gain(0.8));
+    }
+
+    #[test]
+    fn opens_the_output_at_each_tracks_rate() {
+        let mut player = player();
+        let cd = TempFile::wav(&sine(440.0, 44_100 * 5, 2, 44_100), 2, 44_100);
+        let dat = TempFile::wav(&sine(440.0, 48_000 * 5, 2, 48_000), 2, 48_000);
+        player.load_and_play(dat.path().to_path_buf()).unwrap();
+        assert_eq!(player.output_sample_rate, 44_100);
+        assert!(player
+            .format_report()
+            .contains("Resampling: 48000 Hz -> 44100 Hz"));
+
+        player.set_auto_device_rate(true);
+        player.load_and_play(dat.path().to_path_buf()).unwrap();
+        assert_eq!(player.output_sample_rate, 48_000);
+        assert!(!player.format_report().contains("Resampling"));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        player.load_and_play(cd.path().to_path_buf()).unwrap();
+        assert_eq!(player.output_sample_rate, 44_100);
+        player.load_and_play(dat.path().to_path_buf()).unwrap();
+
+        // Back to the default rate once stopped
+        player.stop();
+        assert!(!player.is_output_open());
+        player.play().unwrap();
+        assert_eq!(player.output_sample_rate, 48_000);
+        player.set_auto_device_rate(false);
+        player.stop();
+        player.play().unwrap();
+        assert_eq!(player.output_sample_rate, 44_100);
+    }
+
+    #[test]
+    fn moves_through_the_playback_states() {
+        let mut player = player();
+        assert_eq!(player.state(), PlaybackState::Empty);
+        let wav = TempFile::wav(&sine(440.0, 4_410, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        player.pause();
+        assert_eq!(player.state(), PlaybackState::Paused);
+        player.resume();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        player.stop();
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        player.play().unwrap();
+        assert_eq!(player.state(), PlaybackState::Playing);
+
+        // Played to the end, with nobody calling `stop`
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        player.play().unwrap();
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(near(player.position_ms(), 0), "{:?}", player.position_ms());
+    }
+
+    #[test]
+    fn keeps_the_speed_across_loads() {
+        let mut player = player();
+        player.set_speed(2.0);
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        for _ in 0..2 {
+            player.load_and_play(wav.path().to_path_buf()).unwrap();
+            assert_eq!(player.speed(), 2.0);
+            assert_eq!(player.sink.speed(), 2.0);
+        }
+        // Track time runs twice as fast as the clock
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(
+            near(player.position_ms(), 500),
+            "{:?}",
+            player.position_ms()
+        );
+
+        player.set_speed(10.0);
+        assert_eq!(player.speed(), 4.0);
+    }
+
+    #[test]
+    fn caps_the_gain_on_a_capped_device() {
+        let mut player = player();
+        // A headless output has no name; pretend to be on the laptop speakers
+        player.output_device_name = Some("Laptop Speakers".to_string());
+        player.set_device_max_gain("Laptop Speakers", 0.8);
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 20, 2, 44_100), 2, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(player.sink.volume(), 0.8);
+        // Below the cap the volume applies as usual
+        player.set_volume(0.5);
+        assert_eq!(player.sink.volume(), volume_gain(0.5));
+
+        player.set_volume(1.0);
+        player.output_device_name = Some("Headphones".to_string());
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert_eq!(player.sink.volume(), 1.0);
+
+        player.set_device_max_gain("Laptop Speakers", 1.0);
+        assert!(player.device_max_gains().is_empty());
+    }
+
+    #[test]
+    fn crossfades_into_the_next_queued_track() {
+        let mut player = player();
+        let first = TempFile::wav(&sine(440.0, 44_100 * 2, 2, 44_100), 2, 44_100);
+        let second = TempFile::wav(&sine(880.0, 44_100 * 10, 2, 44_100), 2, 44_100);
+        player.enqueue(first.path().to_path_buf()).unwrap();
+        player.enqueue(second.path().to_path_buf()).unwrap();
+        player.set_crossfade(Some(Duration::from_millis(500)));
+        player.play_queue().unwrap();
+
+        let started = Instant::now();
+        let next = loop {
+            if let Some(info) = player.poll_queue().unwrap() {
+                break info;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(3),
+                "never crossfaded"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        // Half a second before the first one ends, both play for a while
+        let at = started.elapsed();
+        assert!(at >= Duration::from_millis(1_400) && at < Duration::from_millis(1_700));
+        assert_eq!(next.path, second.path());
+        assert_eq!(player.queue_index(), Some(1));
+        assert!(player.fading.is_some());
+        assert_eq!(player.state(), PlaybackState::Playing);
+
+        std::thread::sleep(Duration::from_millis(700));
+        assert!(player.poll_queue().unwrap().is_none());
+        assert!(player.fading.is_none());
+        assert_eq!(player.state(), PlaybackState::Playing);
+    }
+
+    #[test]
+    fn fades_in_on_load_and_silences_after_the_stop_ramp() {
+        let mut player = player();
+        player.set_dc_blocker(false);
+        player.set_fade(Duration::from_millis(100));
+        let wav = TempFile::wav(&vec![0.5; 44_100 * 10], 1, 44_100);
+        let captured = capture(&mut player);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        {
+            let captured = captured.lock();
+            assert!(captured[0].abs() < 0.01, "{}", captured[0]);
+            assert!(captured.windows(2).take(4_410).all(|w| w[1] >= w[0]));
+            assert!((captured[4_410] - 0.5).abs() < 0.01, "{}", captured[4_410]);
+        }
+
+        player.stop();
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        // Still ramping down on the audio thread
+        assert!(!player.sink.empty());
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(player.sink.empty());
+
+        // Without a fade, both ends are hard
+        player.set_fade(Duration::ZERO);
+        captured.lock().clear();
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!((captured.lock()[0] - 0.5).abs() < 0.01);
+        player.stop();
+        // Gone by the sink's next pass
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(player.sink.empty());
+    }
+
+    #[test]
+    fn adjusts_the_volume_by_replaygain_tags() {
+        let mut player = player();
+        let samples = sine(440.0, 44_100 * 5, 2, 44_100);
+        let frames = [
+            ("TXXX", "REPLAYGAIN_TRACK_GAIN\0-6.00 dB"),
+            ("TXXX", "REPLAYGAIN_ALBUM_GAIN\0-9.00 dB"),
+        ];
+        let tagged = TempFile::tagged_wav(&samples, 2, 44_100, &frames, None);
+        let untagged = TempFile::wav(&samples, 2, 44_100);
+        let db = |db: f32| 10f32.powf(db / 20.0);
+        let close = |gain: f32, expected: f32| (gain - expected).abs() < 1e-4;
+
+        player.load_and_play(tagged.path().to_path_buf()).unwrap();
+        assert_eq!(player.sink.volume(), 1.0);
+        player.set_replaygain_mode(ReplayGainMode::Track);
+        assert!(close(player.sink.volume(), db(-6.0)));
+        player.set_replaygain_mode(ReplayGainMode::Album);
+        assert!(close(player.sink.volume(), db(-9.0)));
+        // On top of the volume setting, not instead of it
+        player.set_volume(0.5);
+        assert!(close(player.sink.volume(), volume_gain(0.5) * db(-9.0)));
+
+        player.set_volume(1.0);
+        player.set_replaygain_mode(ReplayGainMode::Track);
+        player.load_and_play(untagged.path().to_path_buf()).unwrap();
+        assert_eq!(player.sink.volume(), 1.0);
+        player.set_replaygain_preamp(-3.0);
+        assert!(close(player.sink.volume(), db(-3.0)));
+    }
+
+    #[test]
+    fn lists_the_output_devices() {
+        let devices = Player::output_devices().unwrap();
+        assert!(devices.iter().filter(|d| d.is_default).count() <= 1);
+        assert!(devices.iter().all(|d| !d.name.is_empty()));
+    }
+
+    #[test]
+    fn names_a_missing_output_device() {
+        let err = Player::new_with_device("no such device").err().unwrap();
+        assert!(
+            format!("{:#}", err).contains("\"no such device\""),
+            "{:#}",
+            err
+        );
+    }
+
+    /// Three queued five-second tones
+    fn three_tracks(player: &mut Player) -> Vec<TempFile> {
+        let tracks: Vec<TempFile> = [440.0, 550.0, 660.0]
+            .into_iter()
+            .map(|freq| TempFile::wav(&sine(freq, 44_100 * 5, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        tracks
+    }
+
+    #[test]
+    fn previous_restarts_past_the_window_and_goes_back_within_it() {
+        let mut player = player();
+        let tracks = three_tracks(&mut player);
+        player.play_queue().unwrap();
+        assert_eq!(player.next().unwrap().unwrap().path, tracks[1].path());
+
+        // Past the default 3 s the current track restarts
+        player.seek(4_000).unwrap();
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[1].path());
+        assert_eq!(player.queue_index(), Some(1));
+        assert!(near(player.position_ms(), 0));
+
+        // Within it, and again right after the restart, it goes back a track
+        player.seek(2_000).unwrap();
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[0].path());
+        assert_eq!(player.queue_index(), Some(0));
+
+        // The first track has nowhere to go back to, so restarts
+        player.seek(1_000).unwrap();
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[0].path());
+        assert_eq!(player.queue_index(), Some(0));
+        assert!(near(player.position_ms(), 0));
+    }
+
+    #[test]
+    fn next_stops_at_the_end_of_the_queue_unless_repeating() {
+        let mut player = player();
+        let tracks = three_tracks(&mut player);
+        player.play_queue().unwrap();
+        player.next().unwrap();
+        assert_eq!(player.next().unwrap().unwrap().path, tracks[2].path());
+
+        // The last track carries on
+        assert!(player.next().unwrap().is_none());
+        assert_eq!(player.queue_index(), Some(2));
+        assert_eq!(player.current_track().unwrap().info.path, tracks[2].path());
+        assert_eq!(player.state(), PlaybackState::Playing);
+
+        player.set_repeat(RepeatMode::All);
+        assert_eq!(player.next().unwrap().unwrap().path, tracks[0].path());
+        assert_eq!(player.queue_index(), Some(0));
+    }
+
+    #[test]
+    fn previous_follows_the_restart_window() {
+        let mut player = player();
+        let tracks = three_tracks(&mut player);
+        player.set_restart_window(Duration::from_secs(1));
+        player.play_queue().unwrap();
+        player.next().unwrap();
+
+        player.seek(2_000).unwrap();
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[1].path());
+        assert!(near(player.position_ms(), 0));
+
+        player.seek(500).unwrap();
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[0].path());
+    }
+
+    /// Poll the queue until it moves on, returning the new queue index, or None if it hasn't
+    /// within `within`
+    fn follow_queue(player: &mut Player, within: Duration) -> Option<usize> {
+        let started = Instant::now();
+        while started.elapsed() < within {
+            if player.poll_queue().unwrap().is_some() {
+                return player.queue_index();
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+
+    #[test]
+    fn follows_each_repeat_mode_through_a_two_track_queue() {
+        let tracks: Vec<TempFile> = [440.0, 660.0]
+            .into_iter()
+            .map(|freq| TempFile::wav(&sine(freq, 44_100 * 2 / 5, 1, 44_100), 1, 44_100))
+            .collect();
+        let within = Duration::from_secs(1);
+        let mut runs = Vec::new();
+        for mode in [RepeatMode::Off, RepeatMode::One, RepeatMode::All] {
+            let mut player = player();
+            for track in &tracks {
+                player.enqueue(track.path().to_path_buf()).unwrap();
+            }
+            player.set_repeat(mode);
+            player.play_queue().unwrap();
+            let order: Vec<Option<usize>> =
+                (0..3).map(|_| follow_queue(&mut player, within)).collect();
+            runs.push((order, player.state()));
+        }
+        assert_eq!(
+            runs,
+            [
+                (vec![Some(1), None, None], PlaybackState::Stopped),
+                (vec![Some(0), Some(0), Some(0)], PlaybackState::Playing),
+                (vec![Some(1), Some(0), Some(1)], PlaybackState::Playing),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_sleep_timer_stops_playback_by_itself() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 10, 1, 44_100), 1, 44_100);
+        let captured = capture(&mut player);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.set_sleep_timer(Duration::from_millis(300), false);
+        assert!(player.sleep_timer_remaining().unwrap() > Duration::from_millis(250));
+
+        // Nothing polls the player meanwhile
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        assert!(player.sleep_timer_remaining().is_none());
+        let played = captured.lock().len();
+        assert!(played < 44_100 / 2, "{}", played);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(captured.lock().len(), played);
+
+        // The player catches up, and the next track plays as usual
+        assert!(player.poll_queue().unwrap().is_none());
+        assert!(player.current_track().is_none());
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(captured.lock().len() > played);
+    }
+
+    #[test]
+    fn cancelling_or_loading_a_track_stops_the_sleep_timer() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 10, 1, 44_100), 1, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.set_sleep_timer(Duration::from_millis(200), true);
+        player.cancel_sleep_timer();
+        assert!(player.sleep_timer_remaining().is_none());
+
+        player.set_sleep_timer(Duration::from_millis(200), false);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert!(player.sleep_timer_remaining().is_none());
+
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        // Full volume, with no fade left over
+        assert_eq!(player.controls.sleep.gain(), 1.0);
+    }
+
+    #[test]
+    fn goes_around_the_ab_loop_by_itself() {
+        let mut player = player();
+        player.set_dc_blocker(false);
+        // Each sample's value is its time in tens of seconds
+        let samples: Vec<f32> = (0..44_100 * 3).map(|i| i as f32 / 441_000.0).collect();
+        let wav = TempFile::wav(&samples, 1, 44_100);
+        let captured = capture(&mut player);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        player.set_ab_loop(500, 1_000).unwrap();
+        assert_eq!(player.ab_loop(), Some((500, 1_000)));
+
+        // Nothing polls the player meanwhile
+        std::thread::sleep(Duration::from_millis(1_700));
+        let position = player.position_ms().unwrap();
+        assert!((500..1_000).contains(&position), "{}", position);
+        {
+            let captured = captured.lock();
+            // Never past B, and back to A after reaching it
+            assert!(captured.iter().all(|&s| s < 0.101));
+            let at_b = captured.iter().position(|&s| s > 0.098).unwrap();
+            assert!(captured[at_b..].iter().any(|&s| s < 0.06));
+        }
+
+        // Seeking past the loop plays on
+        player.seek(2_000).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(player.position_ms().unwrap() > 2_000);
+        // From before it, playback runs into it again
+        player.seek(200).unwrap();
+        std::thread::sleep(Duration::from_millis(1_000));
+        let position = player.position_ms().unwrap();
+        assert!((500..1_000).contains(&position), "{}", position);
+
+        player.clear_ab_loop().unwrap();
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(player.position_ms().unwrap() > 1_000);
+        assert!(player.ab_loop().is_none());
+    }
+
+    #[test]
+    fn checks_the_ab_loop_region() {
+        let mut player = player();
+        assert!(player.set_ab_loop(0, 100).is_err());
+        let wav = TempFile::wav(&sine(440.0, 44_100, 1, 44_100), 1, 44_100);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        assert!(player.set_ab_loop(500, 500).is_err());
+        assert!(player.set_ab_loop(500, 2_000).is_err());
+        assert!(player.ab_loop().is_none());
+    }
+
+    #[test]
+    fn releases_the_idle_output_and_reopens_it_to_resume() {
+        let mut player = player();
+        let wav = TempFile::wav(&sine(440.0, 44_100 * 10, 1, 44_100), 1, 44_100);
+        player.set_close_on_silence(Some(Duration::from_millis(100)));
+        let captured = capture(&mut player);
+        player.load_and_play(wav.path().to_path_buf()).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        // Playing isn't idle
+        assert!(!player.release_idle_output());
+
+        player.seek(3_000).unwrap();
+        player.pause();
+        // Idle time counts from the first check
+        assert!(!player.release_idle_output());
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(player.release_idle_output());
+        assert!(player.output.is_none());
+        assert_eq!(player.state(), PlaybackState::Paused);
+        assert!(near(player.position_ms(), 3_000));
+        let played = captured.lock().len();
+
+        player.resume();
+        assert!(player.output.is_some());
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(near(player.position_ms(), 3_200));
+        assert!(captured.lock().len() > played + 44_100 / 10);
+        assert!(!player.release_idle_output());
+    }
+
+    #[test]
+    fn leaves_a_gap_between_queued_tracks_when_not_gapless() {
+        let mut player = player();
+        assert!(player.is_gapless());
+        let tracks: Vec<TempFile> = [440.0, 660.0]
+            .into_iter()
+            .map(|freq| TempFile::wav(&sine(freq, 44_100 * 3 / 10, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_gapless(false);
+        player.play_queue().unwrap();
+
+        // Nothing moves the queue on until it's polled
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        assert_eq!(player.queue_index(), Some(0));
+        assert_eq!(player.poll_queue().unwrap().unwrap().path, tracks[1].path());
+        assert_eq!(player.state(), PlaybackState::Playing);
+    }
+
+    #[test]
+    fn upsamples_to_twice_the_rate() {
+        let mut player = player();
+        assert!(player.set_upsample_factor(3).is_err());
+        player.set_upsample_factor(2).unwrap();
+        let frames = 44_100 / 5;
+        let track = TempFile::wav(&sine(440.0, frames, 1, 44_100), 1, 44_100);
+        let seen = Arc::new(Mutex::new((0, 0)));
+        let sink = seen.clone();
+        player.set_pcm_sink(Some(Box::new(move |samples, rate, channels| {
+            let mut seen = sink.lock();
+            seen.0 = rate;
+            seen.1 += samples.len() / channels as usize;
+        })));
+        player.load_and_play(track.path().to_path_buf()).unwrap();
+        assert_eq!(player.output_sample_rate, 88_200);
+        assert!(player.format_report().contains("Upsampling: 2x"));
+
+        std::thread::sleep(Duration::from_millis(600));
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        assert_eq!(*seen.lock(), (88_200, 2 * frames));
+    }
+
+    #[test]
+    fn follows_the_bitrate_of_a_vbr_track() {
+        let mut player = player();
+        // About 5 s alternating between 96 and 160 kbps
+        let kbps: Vec<u32> = (0..190).map(|i| [96, 160][i % 2]).collect();
+        let track = TempFile::silent_mp3(&kbps);
+        player
+            .load_and_play_symphonia(track.path().to_path_buf())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        let bitrate = player.current_bitrate().unwrap();
+        assert!((95_000..=160_000).contains(&bitrate), "{}", bitrate);
+        assert!(player.format_report().contains("Bitrate: "));
+
+        // PCM has no bitrate worth showing
+        let wav = TempFile::wav(&sine(440.0, 44_100, 1, 44_100), 1, 44_100);
+        player
+            .load_and_play_symphonia(wav.path().to_path_buf())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.current_bitrate(), None);
+    }
+
+    #[test]
+    fn plays_a_16_bit_track_bit_exact_through_integer_decoding() {
+        let mut player = player();
+        player.set_internal_sample_format(InternalFormat::I16);
+        let samples: Vec<i16> = sine(440.0, 44_100 / 5, 1, 44_100)
+            .iter()
+            .map(|s| (s * 32_767.0) as i16)
+            .collect();
+        let track = TempFile::wav_i16(&samples, 1, 44_100);
+
+        // Nothing else may touch the samples
+        player.set_dc_blocker(false);
+        player.set_fade(Duration::ZERO);
+        let captured = capture(&mut player);
+        player
+            .load_and_play_symphonia(track.path().to_path_buf())
+            .unwrap();
+        assert!(player.format_report().contains("16-bit integer"));
+        std::thread::sleep(Duration::from_millis(500));
+        let played: Vec<f32> = captured.lock().clone();
+        assert_eq!(played.len(), samples.len());
+        for (&played, &original) in played.iter().zip(&samples) {
+            assert_eq!(played * 32_768.0, original as f32);
+        }
+
+        // The DC blocker would filter the samples, so decoding goes back to float under it
+        player.set_dc_blocker(true);
+        assert!(player.format_report().contains("32-bit float"));
+    }
+
+    #[test]
+    fn leaves_out_files_already_queued_when_deduplicating() {
+        let mut player = player();
+        let track = TempFile::wav(&sine(440.0, 4_410, 1, 44_100), 1, 44_100);
+        let path = track.path().to_path_buf();
+        let dir = path.parent().unwrap();
+        // The same file spelled another way
+        let other = dir.join(".").join(path.file_name().unwrap());
+
+        player.set_auto_dedup(true);
+        assert!(player.enqueue(path.clone()).unwrap());
+        assert!(!player.enqueue(other.clone()).unwrap());
+        assert_eq!(player.queue().len(), 1);
+        assert_eq!(
+            player.enqueue_many([path.clone(), other.clone()]).unwrap(),
+            2
+        );
+        assert_eq!(player.queue().len(), 1);
+
+        player.set_auto_dedup(false);
+        assert!(player.enqueue(path).unwrap());
+        assert_eq!(player.queue().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_too_many_tracks_fail_in_a_row() {
+        let mut player = player();
+        let events = player.on_event();
+        let bad: Vec<TempFile> = (0..5)
+            .map(|_| {
+                let file = TempFile::new("wav");
+                std::fs::write(file.path(), b"not audio").unwrap();
+                file
+            })
+            .collect();
+        for file in &bad {
+            player.enqueue(file.path().to_path_buf()).unwrap();
+        }
+        // Wrapping around would go on forever without the limit
+        player.set_repeat(RepeatMode::All);
+        player.set_error_skip_backoff(Duration::from_millis(20), 3);
+        assert!(player.play_queue().is_err());
+
+        // Too soon to skip
+        assert!(player.poll_queue().unwrap().is_none());
+        assert_eq!(player.queue_index(), Some(0));
+        for _ in 0..20 {
+            std::thread::sleep(Duration::from_millis(10));
+            assert!(player.poll_queue().unwrap().is_none());
+        }
+        assert_eq!(player.queue_index(), Some(2));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            PlayerEvent::TooManyErrors { failures: 3, .. }
+        ));
+        assert!(events.try_recv().is_err());
+        assert_ne!(player.state(), PlaybackState::Playing);
+    }
+
+    #[test]
+    fn crossfades_only_on_skips_when_asked() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = [(440.0, 1), (660.0, 10), (880.0, 10)]
+            .into_iter()
+            .map(|(freq, secs)| TempFile::wav(&sine(freq, 44_100 * secs, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_crossfade(Some(Duration::from_millis(300)));
+        player.set_crossfade_mode(CrossfadeMode::ManualSkipOnly);
+        player.play_queue().unwrap();
+
+        // The first track runs straight into the second
+        let started = Instant::now();
+        let next = loop {
+            if let Some(info) = player.poll_queue().unwrap() {
+                break info;
+            }
+            assert!(started.elapsed() < Duration::from_secs(2), "never moved on");
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert_eq!(next.path, tracks[1].path());
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert!(player.fading.is_none());
+
+        // Skipping crossfades
+        assert_eq!(player.next().unwrap().unwrap().path, tracks[2].path());
+        assert!(player.fading.is_some());
+        assert_eq!(player.state(), PlaybackState::Playing);
+
+        // Once the fade is over, going back and skipping again doesn't under `Never`
+        std::thread::sleep(Duration::from_millis(400));
+        player.poll_queue().unwrap();
+        assert!(player.fading.is_none());
+        player.set_crossfade_mode(CrossfadeMode::Never);
+        assert_eq!(player.previous().unwrap().unwrap().path, tracks[1].path());
+        player.next().unwrap();
+        assert!(player.fading.is_none());
+    }
+
+    #[test]
+    fn keeps_the_queue_in_step_with_a_watched_folder() {
+        let mut player = player();
+        let events = player.on_event();
+        let dir = TempDir::new();
+        let watch = player.watch_folder(dir.path()).unwrap();
+        let added = dir.path().join("dropped.wav");
+        let poll_until = |player: &mut Player, done: &dyn Fn(&Player) -> bool| {
+            for _ in 0..100 {
+                player.poll_queue().unwrap();
+                if done(player) {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            panic!("the queue didn't change");
+        };
+
+        std::fs::write(&added, b"RIFF").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        poll_until(&mut player, &|p| !p.queue().is_empty());
+        assert_eq!(player.queue(), std::slice::from_ref(&added));
+        assert_eq!(events.try_recv(), Ok(PlayerEvent::QueueChanged));
+
+        std::fs::remove_file(&added).unwrap();
+        poll_until(&mut player, &|p| p.queue().is_empty());
+        assert_eq!(events.try_recv(), Ok(PlayerEvent::QueueChanged));
+
+        // Nothing more once the handle is gone
+        drop(watch);
+        std::fs::write(dir.path().join("late.wav"), b"RIFF").unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        player.poll_queue().unwrap();
+        assert!(player.queue().is_empty());
+    }
+
+    #[test]
+    fn plays_straight_through_an_album_without_crossfade_or_gap() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = [440.0, 660.0]
+            .into_iter()
+            .map(|freq| {
+                let samples = sine(freq, 44_100 / 2, 1, 44_100);
+                TempFile::tagged_wav(&samples, 1, 44_100, &[("TALB", "Live Set")], None)
+            })
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_crossfade(Some(Duration::from_millis(300)));
+        player.set_gapless(false);
+        player.set_no_crossfade_albums(true);
+        player.play_queue().unwrap();
+
+        // Polling all through the first track never starts a crossfade
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(400) {
+            assert!(player.poll_queue().unwrap().is_none());
+            assert!(player.fading.is_none());
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        // And the second follows on by itself although gapless playback is off
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert_eq!(player.poll_queue().unwrap().unwrap().path, tracks[1].path());
+        assert!(player.fading.is_none());
+    }
+
+    #[test]
+    fn picks_up_where_it_was_when_the_output_runs_dry_mid_track() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = [440.0, 660.0]
+            .into_iter()
+            .map(|freq| TempFile::wav(&sine(freq, 44_100 * 10, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_underrun_recovery(true);
+        player.play_queue().unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+
+        // The sink finishes as though the track had played out
+        player.sink.skip_one();
+        let drained = Instant::now();
+        while !player.sink.empty() {
+            assert!(drained.elapsed() < Duration::from_secs(1), "never drained");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let position = player.current_position_ms();
+        assert!(player.poll_queue().unwrap().is_none());
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert_eq!(player.queue_index(), Some(0));
+        assert!(player.current_position_ms() >= position);
+
+        // Without it, the drain is taken for the end of the track
+        player.set_underrun_recovery(false);
+        player.sink.skip_one();
+        while !player.sink.empty() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        player.poll_queue().unwrap();
+        assert_eq!(player.state(), PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn bass_boost_raises_the_low_bands() {
+        let mut player = player();
+        player.apply_eq_preset("Bass Boost").unwrap();
+        let low: Vec<EqBand> = player
+            .eq()
+            .into_iter()
+            .filter(|band| band.freq_hz <= 250.0)
+            .collect();
+        assert!(!low.is_empty());
+        assert!(low.iter().all(|band| band.gain_db > 0.0), "{:?}", low);
+        assert!(player.eq().iter().all(|band| band.freq_hz <= 250.0));
+
+        player.apply_eq_preset("Flat").unwrap();
+        assert!(player.eq().is_empty());
+        assert!(player.apply_eq_preset("Polka").is_err());
+    }
+
+    #[test]
+    fn saved_eq_presets_load_back() {
+        let store = TempFile::new("json");
+        let mut saving = player();
+        saving.set_position_store(store.path().to_path_buf());
+        let bands = vec![
+            EqBand {
+                freq_hz: 80.0,
+                gain_db: -3.0,
+                q: 0.7,
+            },
+            EqBand {
+                freq_hz: 3_000.0,
+                gain_db: 2.5,
+                q: 2.0,
+            },
+        ];
+        saving.save_eq_preset("Podcast", bands.clone()).unwrap();
+        assert!(saving.save_eq_preset("Rock", Vec::new()).is_err());
+
+        // A fresh player reading the same store finds it
+        let mut player = player();
+        player.set_position_store(store.path().to_path_buf());
+        let names = player.eq_presets().unwrap();
+        assert_eq!(names.last().map(String::as_str), Some("Podcast"));
+        assert!(names.iter().any(|name| name == "Bass Boost"));
+        player.apply_eq_preset("Podcast").unwrap();
+        assert_eq!(player.eq(), bands);
+    }
+
+    #[test]
+    fn stops_or_starts_over_at_the_end_of_the_queue() {
+        let tracks: Vec<TempFile> = [440.0, 660.0]
+            .into_iter()
+            .map(|freq| TempFile::wav(&sine(freq, 44_100 / 5, 1, 44_100), 1, 44_100))
+            .collect();
+        let play_out = |action| {
+            let mut player = player();
+            let events = player.on_event();
+            let ends = player.on_track_end();
+            for track in &tracks {
+                player.enqueue(track.path().to_path_buf()).unwrap();
+            }
+            player.set_queue_end_action(action);
+            player.play_queue().unwrap();
+            for _ in 0..40 {
+                player.poll_queue().unwrap();
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            (player, events, ends.try_iter().count())
+        };
+
+        let (player, _, ended) = play_out(QueueEndAction::Stop);
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        assert_eq!(player.queue_index(), Some(1));
+        assert_eq!(ended, 2);
+
+        // Going around takes well under the 800 ms polled for
+        let (player, _, ended) = play_out(QueueEndAction::RepeatAll);
+        assert_eq!(player.state(), PlaybackState::Playing);
+        assert!(ended > 2, "{}", ended);
+
+        let (player, events, _) = play_out(QueueEndAction::Shutdown);
+        assert_eq!(player.state(), PlaybackState::Stopped);
+        assert_eq!(events.try_recv(), Ok(PlayerEvent::ShutdownRequested));
+    }
+
+    #[test]
+    fn equalizes_a_queue_entry_with_its_own_bands_only_while_it_plays() {
+        let mut player = player();
+        player.set_dc_blocker(false);
+        let frames = 44_100 * 2 / 5;
+        let quiet: Vec<f32> = sine(80.0, frames, 1, 44_100)
+            .into_iter()
+            .map(|s| s * 0.25)
+            .collect();
+        let tracks: Vec<TempFile> = (0..3).map(|_| TempFile::wav(&quiet, 1, 44_100)).collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.apply_eq_preset("Flat").unwrap();
+        let boost = dsp::eq::built_in_preset("Bass Boost");
+        player.set_track_eq(1, boost).unwrap();
+        assert!(player.track_eq(1).is_some());
+        assert_eq!(player.track_eq(0), None);
+        assert!(player.set_track_eq(3, None).is_err());
+
+        let captured = capture(&mut player);
+        player.play_queue().unwrap();
+        std::thread::sleep(Duration::from_millis(1_500));
+        let captured = captured.lock();
+        assert!(captured.len() >= 3 * frames, "{}", captured.len());
+
+        // The middle of each track, clear of fades and the filters settling
+        let rms = |track: usize| {
+            let middle = &captured[track * frames + frames / 4..track * frames + frames * 3 / 4];
+            (middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32).sqrt()
+        };
+        let gain_db = |track| 20.0 * (rms(track) / rms(0)).log10();
+        assert!(gain_db(1) > 3.0, "{}", gain_db(1));
+        assert!(gain_db(2).abs() < 0.5, "{}", gain_db(2));
+    }
+
+    #[test]
+    fn drops_the_oldest_played_tracks_to_stay_under_the_limit() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = (0..8)
+            .map(|_| TempFile::wav(&sine(440.0, 44_100 * 3, 1, 44_100), 1, 44_100))
+            .collect();
+        let paths: Vec<PathBuf> = tracks.iter().map(|t| t.path().to_path_buf()).collect();
+        player.set_max_queue_length(Some(5));
+        assert_eq!(player.enqueue_many(paths[..5].to_vec()).unwrap(), 0);
+        // Rejected by default, leaving the queue alone
+        assert!(player.enqueue(paths[5].clone()).is_err());
+        assert_eq!(player.queue(), &paths[..5]);
+
+        player.set_queue_overflow(QueueOverflow::DropOldest);
+        player.play_queue().unwrap();
+        player.next().unwrap();
+        player.next().unwrap();
+        player.enqueue_many(paths[5..7].to_vec()).unwrap();
+        assert_eq!(player.queue(), &paths[2..7]);
+        assert_eq!(player.current_track().unwrap().info.path, paths[2]);
+        assert_eq!(player.queue_index(), Some(0));
+
+        // Nothing played is left to drop, and the tracks to come stay
+        assert!(player.enqueue(paths[7].clone()).is_err());
+        assert_eq!(player.queue(), &paths[2..7]);
+    }
+
+    #[test]
+    fn equalizes_by_genre_falling_back_to_the_global_bands() {
+        let mut player = player();
+        let global = vec![EqBand {
+            freq_hz: 1_000.0,
+            gain_db: 3.0,
+            q: 1.0,
+        }];
+        player.set_eq(global.clone());
+        player.set_genre_eq(true);
+        let samples = sine(440.0, 44_100, 2, 44_100);
+        let rock = TempFile::tagged_wav(&samples, 2, 44_100, &[("TCON", "Hard Rock")], None);
+        let untagged = TempFile::wav(&samples, 2, 44_100);
+
+        player.load_and_play(rock.path().to_path_buf()).unwrap();
+        assert_eq!(
+            player.current_track().unwrap().info.genre.as_deref(),
+            Some("Hard Rock")
+        );
+        assert_eq!(Some(player.current_eq()), dsp::eq::built_in_preset("Rock"));
+        player.set_genre_eq(false);
+        assert_eq!(player.current_eq(), global);
+        player.set_genre_eq(true);
+
+        player.load_and_play(untagged.path().to_path_buf()).unwrap();
+        assert_eq!(player.current_eq(), global);
+        assert_eq!(
+            dsp::eq::genre_preset("Classical"),
+            dsp::eq::built_in_preset("Flat")
+        );
+    }
+
+    #[test]
+    fn weighted_shuffle_keeps_playing_heavier_tracks_more_often() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = (0..3)
+            .map(|_| TempFile::wav(&sine(440.0, 4_410, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_track_weight(2, 3.0).unwrap();
+        assert_eq!(player.track_weight(2), Some(3.0));
+        assert!(player.set_track_weight(3, 1.0).is_err());
+        player.set_weighted_shuffle_seeded(true, 5);
+        assert!(player.is_weighted_shuffle() && player.is_shuffled());
+
+        player.play_queue().unwrap();
+        let mut picks = [0usize; 3];
+        for _ in 0..200 {
+            player.next().unwrap();
+            picks[player.queue_index().unwrap()] += 1;
+        }
+        assert!(
+            picks[2] > picks[0] * 2 && picks[2] > picks[1] * 2,
+            "{:?}",
+            picks
+        );
+        player.set_weighted_shuffle(false);
+        assert!(!player.is_shuffled());
+    }
+
+    #[test]
+    fn fades_out_the_end_of_the_queue() {
+        let mut player = player();
+        player.set_dc_blocker(false);
+        let frames = 44_100;
+        let track = TempFile::wav(&sine(440.0, frames, 1, 44_100), 1, 44_100);
+        player.enqueue(track.path().to_path_buf()).unwrap();
+        player.set_queue_end_fade(Duration::from_millis(500));
+        let captured = capture(&mut player);
+        player.play_queue().unwrap();
+        let started = Instant::now();
+        while player.state() == PlaybackState::Playing {
+            assert!(started.elapsed() < Duration::from_secs(3), "never ended");
+            player.poll_queue().unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(player.state(), PlaybackState::Stopped);
+
+        // Loud until the fade, then quieter and quieter down to nothing
+        let captured = captured.lock();
+        assert!(captured.len() > frames * 9 / 10, "{}", captured.len());
+        let end = captured.len();
+        let peak = |from_end_ms: usize| {
+            let at = end - from_end_ms * 441 / 10;
+            captured[at..at + 441]
+                .iter()
+                .fold(0.0f32, |m, s| m.max(s.abs()))
+        };
+        assert!(peak(700) > 0.45, "{}", peak(700));
+        assert!(peak(300) < peak(450), "{} {}", peak(300), peak(450));
+        assert!(peak(150) < peak(300), "{} {}", peak(150), peak(300));
+        assert!(peak(20) < 0.05, "{}", peak(20));
+    }
+
+    #[test]
+    fn crossfades_longer_between_the_entries_asked_for() {
+        let mut player = player();
+        let tracks: Vec<TempFile> = [(440.0, 1), (660.0, 3), (880.0, 10)]
+            .into_iter()
+            .map(|(freq, secs)| TempFile::wav(&sine(freq, 44_100 * secs, 1, 44_100), 1, 44_100))
+            .collect();
+        for track in &tracks {
+            player.enqueue(track.path().to_path_buf()).unwrap();
+        }
+        player.set_crossfade(Some(Duration::from_millis(300)));
+        player
+            .set_crossfade_for(1, 2, Duration::from_secs(2))
+            .unwrap();
+        assert!(player
+            .set_crossfade_for(2, 3, Duration::from_secs(2))
+            .is_err());
+        assert_eq!(player.crossfade_for(1, 2), Some(Duration::from_secs(2)));
+        assert_eq!(player.crossfade_for(0, 1), None);
+        player.play_queue().unwrap();
+
+        let mut crossfade = || {
+            let started = Instant::now();
+            loop {
+                if let Some(info) = player.poll_queue().unwrap() {
+                    assert!(player.fading.is_some());
+                    break (info, started.elapsed());
+                }
+                assert!(started.elapsed() < Duration::from_secs(4), "never moved on");
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        };
+        // 300 ms before the end of the first track, as for any other
+        let (next, at) = crossfade();
+        assert_eq!(next.path, tracks[1].path());
+        assert!(
+            at >= Duration::from_millis(600) && at < Duration::from_millis(850),
+            "{at:?}"
+        );
+        // 2 s before the end of the second
+        let (next, at) = crossfade();
+        assert_eq!(next.path, tracks[2].path());
+        assert!(
+            at >= Duration::from_millis(900) && at < Duration::from_millis(1_200),
+            "{at:?}"
+        );
     }
 }
@@ -0,0 +1,134 @@
+use crate::probe::probe_file;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// One file in a library export
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+    /// Short codec name, e.g. "flac" or "mp3"
+    pub codec: Option<String>,
+    pub size_bytes: Option<u64>,
+    /// Last modification time in seconds since the Unix epoch
+    pub modified_secs: Option<u64>,
+    /// Why the file couldn't be probed; the other tag fields are empty when set
+    pub error: Option<String>,
+}
+
+/// Probe every file in `paths` and write the results to `out` as a JSON array.
+///
+/// Files are probed in parallel and the array keeps the order of `paths`. A file that
+/// can't be read or isn't audio doesn't fail the export; its entry carries an `error`
+/// instead. Only writing `out` itself can fail.
+pub fn export_library_json(paths: &[PathBuf], out: &Path) -> Result<()> {
+    let entries = scan_library(paths);
+    let file = File::create(out).with_context(|| format!("Failed to create {:?}", out))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &entries)
+        .with_context(|| format!("Failed to write {:?}", out))?;
+    Ok(())
+}
+
+/// Probe `paths` across the available cores
+fn scan_library(paths: &[PathBuf]) -> Vec<LibraryEntry> {
+    if paths.is_empty() {
+        return Vec::new();
+    }
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = paths.len().div_ceil(workers);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|p| entry(p)).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("library scan worker panicked"))
+            .collect()
+    })
+}
+
+fn entry(path: &Path) -> LibraryEntry {
+    let meta = std::fs::metadata(path).ok();
+    let mut entry = LibraryEntry {
+        path: path.to_path_buf(),
+        title: None,
+        artist: None,
+        album: None,
+        duration_ms: None,
+        codec: None,
+        size_bytes: meta.as_ref().map(|m| m.len()),
+        modified_secs: meta
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        error: None,
+    };
+
+    match probe_file(path) {
+        Ok(probe) => {
+            entry.title = probe.title;
+            entry.artist = probe.artist;
+            entry.album = probe.album;
+            entry.duration_ms = probe.duration_ms;
+            entry.codec = probe.codec;
+        }
+        Err(err) => entry.error = Some(format!("{:#}", err)),
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+
+    #[test]
+    fn exports_an_entry_per_file_in_order() {
+        let short = TempFile::wav(&sine(440.0, 22_050, 1, 44_100), 1, 44_100);
+        let long = TempFile::wav(&sine(440.0, 88_200, 2, 44_100), 2, 44_100);
+        let text = TempFile::new("txt");
+        std::fs::write(text.path(), "not audio").unwrap();
+        let missing = TempFile::new("flac");
+        let out = TempFile::new("json");
+
+        let paths: Vec<PathBuf> = [&short, &text, &long, &missing]
+            .iter()
+            .map(|f| f.path().to_path_buf())
+            .collect();
+        export_library_json(&paths, out.path()).unwrap();
+        let entries: Vec<LibraryEntry> =
+            serde_json::from_reader(File::open(out.path()).unwrap()).unwrap();
+
+        assert_eq!(
+            entries.iter().map(|e| &e.path).collect::<Vec<_>>(),
+            paths.iter().collect::<Vec<_>>()
+        );
+        let [short, text, long, missing] = &entries[..] else {
+            panic!("{:?}", entries);
+        };
+        assert_eq!(short.duration_ms, Some(500));
+        assert_eq!(long.duration_ms, Some(2_000));
+        for entry in [short, long] {
+            assert_eq!(entry.error, None);
+            assert!(entry.codec.is_some());
+            let meta = std::fs::metadata(&entry.path).unwrap();
+            assert_eq!(entry.size_bytes, Some(meta.len()));
+            assert!(entry.modified_secs.is_some());
+        }
+        // Failures are recorded rather than failing the export
+        assert!(text.error.is_some());
+        assert_eq!(text.size_bytes, Some(9));
+        assert!(missing.error.is_some());
+        assert_eq!(missing.size_bytes, None);
+    }
+}
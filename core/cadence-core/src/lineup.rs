@@ -0,0 +1,664 @@
+use crate::analysis;
+use crate::cache::{DecodeCachePolicy, PcmCache};
+use crate::dsp::eq::TrackBands;
+use crate::pipeline::{Controls, OutputStage, Pipeline};
+use crate::probe;
+use crate::queue::Queue;
+use crate::retry::{IoRetry, RetryReader};
+use crate::track_end::{Handover, NotifyOnEnd};
+use crate::upsample::Upsample;
+use crate::{cut_at, BoxedSource, RepeatMode, TrackInfo};
+use anyhow::{Context, Result};
+use rodio::queue::SourcesQueueInput;
+use rodio::{Decoder, Source};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long before the playing queue track ends the one after it is opened and lined up
+pub(crate) const LEAD: Duration = Duration::from_secs(5);
+
+/// Everything `Player::prepare` reads, copied so a track can be opened off the player's thread
+#[derive(Clone)]
+pub(crate) struct Loader {
+    pub(crate) controls: Arc<Controls>,
+    pub(crate) output_channels: u16,
+    pub(crate) decode_cache: DecodeCachePolicy,
+    pub(crate) io_retry: IoRetry,
+    pub(crate) applause_trim: bool,
+    /// Factor `set_upsample_factor` raises the sample rate by on the way out
+    pub(crate) upsample: u8,
+}
+
+/// A track opened by `Loader::prepare`, ready to start without having touched what's playing
+pub(crate) struct Prepared {
+    pub(crate) info: TrackInfo,
+    pub(crate) format: (u16, u32),
+    pub(crate) source: BoxedSource,
+    pub(crate) start: Duration,
+    pub(crate) cache: Option<PcmCache>,
+    pub(crate) play_until: Option<Duration>,
+}
+
+impl Loader {
+    /// Open `path` the way `load_and_play` plays it
+    pub(crate) fn prepare(&self, path: PathBuf) -> Result<Prepared> {
+        // Open once for duration using the same decoder we'll use for playback.
+        let src = self.open_decoder(&path)?;
+        let dur = src.total_duration().map(|d| d.as_millis() as u64);
+
+        let info = TrackInfo::untagged(path, dur);
+        let format = (src.channels(), src.sample_rate());
+        let (start, play_until) = self.detect_trim(&info.path);
+
+        let (source, cache): (BoxedSource, _) = match self.decode_cache.resolve(&src) {
+            DecodeCachePolicy::Stream => {
+                (Box::new(src.skip_duration(start).convert_samples()), None)
+            }
+            policy => {
+                let cache = PcmCache::decode(src.convert_samples(), policy)?;
+                (Box::new(cache.source_at(start)?), Some(cache))
+            }
+        };
+        Ok(Prepared {
+            info,
+            format,
+            source: cut_at(source, play_until, start),
+            start,
+            cache,
+            play_until,
+        })
+    }
+
+    /// Decoder for a track file, reading through the configured I/O retries
+    pub(crate) fn open_decoder(
+        &self,
+        path: &Path,
+    ) -> Result<Decoder<BufReader<RetryReader<File>>>> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        Decoder::new(BufReader::new(RetryReader::new(file, self.io_retry)))
+            .with_context(|| format!("Unsupported/invalid audio: {:?}", path))
+    }
+
+    /// Where the track at `path` should start, and where it should end if not at its end,
+    /// when applause trimming is on
+    pub(crate) fn detect_trim(&self, path: &Path) -> (Duration, Option<Duration>) {
+        // A file that can't be analysed just plays untrimmed
+        let trim = self
+            .applause_trim
+            .then(|| analysis::detect_applause(path).ok())
+            .flatten();
+        (
+            trim.map_or(Duration::ZERO, |t| t.start),
+            trim.and_then(|t| t.end),
+        )
+    }
+
+    /// Wrap `source`, which starts `at` into the track, in the processing chain for the sink,
+    /// equalized with `track_bands` where the track has its own
+    pub(crate) fn processed(
+        &self,
+        source: BoxedSource,
+        at: Duration,
+        track_bands: TrackBands,
+    ) -> OutputStage<Upsample<Pipeline<BoxedSource>>> {
+        OutputStage::new(
+            Upsample::new(
+                Pipeline::new(source, self.controls.clone(), at, track_bands),
+                self.upsample,
+            ),
+            self.controls.clone(),
+            self.output_channels,
+        )
+    }
+}
+
+/// The queue as it stood when the playing track started, and how the tracks after it join
+pub(crate) struct Plan {
+    pub(crate) queue: Queue,
+    pub(crate) repeat: RepeatMode,
+    pub(crate) loader: Loader,
+    /// Open each following track ahead of time and join it sample to sample; otherwise the
+    /// player loads it once the track ahead has ended
+    pub(crate) preload: bool,
+    /// Preload anyway between tracks of the same album, so albums mixed to run on from one
+    /// track into the next stay joined
+    pub(crate) join_albums: bool,
+    /// Rate the output device runs at and the rate requested of it, when
+    /// `set_auto_device_rate` wants each track at its own rate. A following track at another
+    /// rate needs the device reopened, which only the player can do.
+    pub(crate) device_rate: Option<(u32, Option<u32>)>,
+}
+
+/// A queue track lined up behind the playing one, as `Player` needs it once it takes over
+pub(crate) struct Lined {
+    /// Identifies the track's source to `Lineup`
+    id: u64,
+    pub(crate) info: TrackInfo,
+    pub(crate) format: (u16, u32),
+    pub(crate) start: Duration,
+    pub(crate) cache: Option<PcmCache>,
+    pub(crate) play_until: Option<Duration>,
+    pub(crate) handover: Handover,
+}
+
+/// What follows the playing track, in the order the tracks play
+pub(crate) enum Arrival {
+    /// Plays as soon as the track ahead of it ends
+    Lined(Box<Lined>),
+    /// The next queue entry couldn't be opened; nothing follows
+    Failed(anyhow::Error),
+    /// The player has to load the next queue entry: preloading is off, or it needs the
+    /// output device reopened at its own rate
+    Reload,
+    /// The queue is done
+    End,
+}
+
+enum Message {
+    Follow(Box<Chain>),
+    NearEnd(u64),
+    Quit,
+}
+
+/// Handed to `NotifyOnEnd::near_end` for a source `Lineup` knows as `id`
+pub(crate) struct NearEnd {
+    id: u64,
+    messages: Sender<Message>,
+}
+
+impl NearEnd {
+    pub(crate) fn signal(self) {
+        let _ = self.messages.send(Message::NearEnd(self.id));
+    }
+}
+
+/// A playing queue track and what to line up behind it, on the lineup thread
+struct Chain {
+    plan: Plan,
+    /// The nested queue the track plays from
+    output: Arc<SourcesQueueInput<f32>>,
+    withdrawn: Arc<AtomicBool>,
+    arrivals: Sender<Arrival>,
+    /// Source whose end the next track follows
+    tail: u64,
+}
+
+/// The player's side of a chain
+struct Following {
+    output: Arc<SourcesQueueInput<f32>>,
+    withdrawn: Arc<AtomicBool>,
+    arrivals: Receiver<Arrival>,
+    /// Next arrival, held until its turn comes
+    waiting: Option<Arrival>,
+    /// Source of the track the player is on
+    playing: u64,
+}
+
+/// Keeps the queue playing without the host's help.
+///
+/// Each queue track plays from a nested rodio queue. When the track gets within `LEAD` of its
+/// end, a thread opens the next queue entry and appends it to that queue, so the audio moves
+/// on by itself the moment the track ends. The player picks up what happened from the
+/// arrivals, in play order, whenever it next looks.
+pub(crate) struct Lineup {
+    messages: Sender<Message>,
+    next_id: Arc<AtomicU64>,
+    following: Option<Following>,
+}
+
+impl Lineup {
+    pub(crate) fn new() -> Result<Self> {
+        let (messages, messages_rx) = mpsc::channel();
+        let next_id = Arc::new(AtomicU64::new(0));
+        let (own, ids) = (messages.clone(), next_id.clone());
+        thread::Builder::new()
+            .name("cadence-lineup".to_string())
+            .spawn(move || run(messages_rx, own, ids))
+            .context("Failed to start the queue thread")?;
+        Ok(Self {
+            messages,
+            next_id,
+            following: None,
+        })
+    }
+
+    /// Id and near-end signal for a source the player puts in the sink itself
+    pub(crate) fn track(&self) -> (u64, NearEnd) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let near_end = NearEnd {
+            id,
+            messages: self.messages.clone(),
+        };
+        (id, near_end)
+    }
+
+    /// Line up the queue as `plan` has it behind the source `playing`, which plays from
+    /// `output`, withdrawing anything lined up before
+    pub(crate) fn follow(&mut self, output: Arc<SourcesQueueInput<f32>>, plan: Plan, playing: u64) {
+        if let Some(old) = self.following.take() {
+            old.withdrawn.store(true, Ordering::Release);
+        }
+        let withdrawn = Arc::new(AtomicBool::new(false));
+        let (arrivals, arrivals_rx) = mpsc::channel();
+        let _ = self.messages.send(Message::Follow(Box::new(Chain {
+            plan,
+            output: output.clone(),
+            withdrawn: withdrawn.clone(),
+            arrivals,
+            tail: playing,
+        })));
+        self.following = Some(Following {
+            output,
+            withdrawn,
+            arrivals: arrivals_rx,
+            waiting: None,
+            playing,
+        });
+    }
+
+    /// Line up again after the queue changed under the playing track
+    pub(crate) fn refollow(&mut self, plan: Plan) {
+        if let Some(following) = &self.following {
+            let (output, playing) = (following.output.clone(), following.playing);
+            self.follow(output, plan, playing);
+        }
+    }
+
+    /// Stop following: anything lined up that hasn't started won't, and the nested queue
+    /// ends with the track playing now
+    pub(crate) fn withdraw(&mut self) {
+        if let Some(following) = self.following.take() {
+            following.withdrawn.store(true, Ordering::Release);
+            following.output.set_keep_alive_if_empty(false);
+        }
+    }
+
+    /// The next arrival once its turn has come: a lined-up track once it has started, the
+    /// rest once `ended` says the track ahead of them has played out
+    pub(crate) fn next(&mut self, ended: bool) -> Option<Arrival> {
+        let following = self.following.as_mut()?;
+        if following.waiting.is_none() {
+            following.waiting = following.arrivals.try_recv().ok();
+        }
+        let due = match following.waiting.as_ref()? {
+            Arrival::Lined(lined) => lined.handover.started().is_some(),
+            _ => ended,
+        };
+        if !due {
+            return None;
+        }
+        let arrival = following.waiting.take();
+        match &arrival {
+            Some(Arrival::Lined(lined)) => following.playing = lined.id,
+            // Nothing more comes
+            _ => self.following = None,
+        }
+        arrival
+    }
+}
+
+impl Drop for Lineup {
+    fn drop(&mut self) {
+        let _ = self.messages.send(Message::Quit);
+    }
+}
+
+/// The lineup thread. Opening a track can take a while, so it happens here rather than on
+/// the player's thread or the audio thread.
+fn run(messages: Receiver<Message>, own: Sender<Message>, next_id: Arc<AtomicU64>) {
+    let mut chain: Option<Chain> = None;
+    // Most recent source to get near its end
+    let mut near_end = None;
+    for message in messages.iter() {
+        match message {
+            Message::Follow(followed) => chain = Some(*followed),
+            Message::NearEnd(id) => near_end = Some(id),
+            Message::Quit => return,
+        }
+        let Some(current) = &mut chain else {
+            continue;
+        };
+        if near_end != Some(current.tail) || current.withdrawn.load(Ordering::Acquire) {
+            continue;
+        }
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        let near_end = NearEnd {
+            id,
+            messages: own.clone(),
+        };
+        let arrival = line_up(current, id, near_end);
+        let done = !matches!(arrival, Arrival::Lined(_));
+        if done {
+            if !current.withdrawn.load(Ordering::Acquire) {
+                current.output.set_keep_alive_if_empty(false);
+            }
+        } else {
+            current.tail = id;
+        }
+        let _ = current.arrivals.send(arrival);
+        if done {
+            chain = None;
+        }
+    }
+}
+
+/// Open the queue entry after the chain's tail and append it to the chain's nested queue
+fn line_up(chain: &mut Chain, id: u64, near_end: NearEnd) -> Arrival {
+    let plan = &mut chain.plan;
+    let current = plan.queue.current_path().map(Path::to_path_buf);
+    let Some(path) = plan.queue.follow(plan.repeat) else {
+        return Arrival::End;
+    };
+    let joined = plan.join_albums && current.is_some_and(|c| probe::same_album(&c, &path));
+    if !plan.preload && !joined {
+        return Arrival::Reload;
+    }
+    let track = match plan.loader.prepare(path.clone()) {
+        Ok(track) => track,
+        Err(e) => return Arrival::Failed(e),
+    };
+    let rate = track.format.1 * plan.loader.upsample as u32;
+    if plan
+        .device_rate
+        .is_some_and(|(output, requested)| rate != output && requested != Some(rate))
+    {
+        return Arrival::Reload;
+    }
+    // Tagged for `set_genre_eq`, which can be turned on while the track plays
+    let genre = probe::probe_file(&path).ok().and_then(|probe| probe.genre);
+    let track_bands = TrackBands::new(plan.queue.current_track_eq(), genre.as_deref());
+    let handover = Handover::new(chain.withdrawn.clone());
+    let remaining = track
+        .info
+        .duration_ms
+        .map(|d| Duration::from_millis(d).saturating_sub(track.start));
+    chain.output.append(
+        NotifyOnEnd::queued(
+            plan.loader
+                .processed(track.source, track.start, track_bands),
+            plan.loader.controls.clone(),
+            track.info.clone(),
+            handover.clone(),
+        )
+        .near_end(remaining, LEAD, near_end),
+    );
+    Arrival::Lined(Box::new(Lined {
+        id,
+        info: track.info,
+        format: track.format,
+        start: track.start,
+        cache: track.cache,
+        play_until: track.play_until,
+        handover,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tee::Tee;
+    use crate::testutil::{sine, TempFile};
+    use rodio::queue::SourcesQueueOutput;
+
+    /// Loader that passes mono tracks through unchanged
+    fn loader() -> Loader {
+        let controls = Arc::new(Controls::default());
+        controls.update_dsp(|dsp| dsp.dc_blocker = false);
+        Loader {
+            controls,
+            output_channels: 1,
+            decode_cache: DecodeCachePolicy::Stream,
+            io_retry: IoRetry::default(),
+            applause_trim: false,
+            upsample: 1,
+        }
+    }
+
+    /// Start the queue's first entry from a nested queue the way `Player` does, with `lineup`
+    /// following it
+    fn play(
+        queue: &mut Queue,
+        lineup: &mut Lineup,
+        loader: Loader,
+        adjust: impl FnOnce(&mut Plan),
+    ) -> SourcesQueueOutput<f32> {
+        let track = loader.prepare(queue.start().unwrap()).unwrap();
+        let (input, output) = rodio::queue::queue(true);
+        let (id, near_end) = lineup.track();
+        let remaining = track.info.duration_ms.map(Duration::from_millis);
+        input.append(
+            NotifyOnEnd::new(
+                loader.processed(track.source, Duration::ZERO, TrackBands::default()),
+                loader.controls.clone(),
+                track.info,
+            )
+            .near_end(remaining, LEAD, near_end),
+        );
+        let mut plan = Plan {
+            queue: queue.clone(),
+            repeat: RepeatMode::Off,
+            loader,
+            preload: true,
+            join_albums: false,
+            device_rate: None,
+        };
+        adjust(&mut plan);
+        lineup.follow(input, plan, id);
+        output
+    }
+
+    /// Wait for the lineup thread's next arrival, leaving it for `Lineup::next`
+    fn wait_for_arrival(lineup: &mut Lineup) {
+        let following = lineup.following.as_mut().unwrap();
+        if following.waiting.is_none() {
+            following.waiting = following.arrivals.recv_timeout(Duration::from_secs(5)).ok();
+        }
+        assert!(following.waiting.is_some(), "nothing arrived");
+    }
+
+    /// A queue of a track at `first_rate` and then one at `second_rate`, mono and 800 frames
+    /// each, with every sample different
+    fn two_tracks(first_rate: u32, second_rate: u32) -> (Queue, [TempFile; 2]) {
+        let ramp = |from: usize| {
+            (from..from + 800)
+                .map(|i| i as f32 / 2_000.0)
+                .collect::<Vec<_>>()
+        };
+        let files = [
+            TempFile::wav(&ramp(0), 1, first_rate),
+            TempFile::wav(&ramp(800), 1, second_rate),
+        ];
+        let mut queue = Queue::default();
+        for file in &files {
+            queue.push(file.path().to_path_buf());
+        }
+        (queue, files)
+    }
+
+    /// What `file` plays on its own
+    fn alone(file: &TempFile) -> Vec<f32> {
+        let loader = loader();
+        let track = loader.prepare(file.path().to_path_buf()).unwrap();
+        loader
+            .processed(track.source, Duration::ZERO, TrackBands::default())
+            .collect()
+    }
+
+    /// Everything `output` plays over `tracks` 800-frame tracks, waiting on `lineup` after
+    /// each track's first sample so keep-alive silence never fills in for a track that's slow
+    /// to open
+    fn play_out(
+        output: &mut SourcesQueueOutput<f32>,
+        lineup: &mut Lineup,
+        tracks: usize,
+    ) -> Vec<f32> {
+        let mut played = Vec::new();
+        for _ in 0..tracks {
+            // Every track here is within `LEAD` of its end from its first sample
+            played.extend(output.next());
+            // Take over the track that just started, then wait for what follows it
+            while lineup.next(false).is_some() {}
+            wait_for_arrival(lineup);
+            played.extend(output.by_ref().take(799));
+        }
+        played.extend(output.by_ref());
+        played
+    }
+
+    #[test]
+    fn joins_queued_tracks_without_a_gap_or_repeat() {
+        let (mut queue, files) = two_tracks(8_000, 8_000);
+        let loader = loader();
+        let tee = TempFile::new("wav");
+        *loader.controls.tee.lock() = Some(Tee::start(tee.path().to_path_buf()).unwrap());
+        let controls = loader.controls.clone();
+        let mut lineup = Lineup::new().unwrap();
+        let mut output = play(&mut queue, &mut lineup, loader, |_| {});
+
+        let played = play_out(&mut output, &mut lineup, 2);
+        let expected = [alone(&files[0]), alone(&files[1])].concat();
+        assert_eq!(expected.len(), 1_600);
+        assert_eq!(played, expected);
+        // The tee records the join the same way, in one file
+        controls.tee.lock().take().unwrap().finish().unwrap();
+        let mut recorded = hound::WavReader::open(tee.path()).unwrap();
+        let recorded: Vec<f32> = recorded.samples().map(Result::unwrap).collect();
+        assert_eq!(recorded, expected);
+    }
+
+    #[test]
+    fn starts_a_new_tee_file_where_the_rate_changes() {
+        let (mut queue, files) = two_tracks(8_000, 16_000);
+        let (first, second) = (alone(&files[0]), alone(&files[1]));
+        let loader = loader();
+        let tee = TempFile::new("wav");
+        *loader.controls.tee.lock() = Some(Tee::start(tee.path().to_path_buf()).unwrap());
+        let controls = loader.controls.clone();
+        let mut lineup = Lineup::new().unwrap();
+        let mut output = play(&mut queue, &mut lineup, loader, |_| {});
+
+        assert_eq!(
+            play_out(&mut output, &mut lineup, 2),
+            [first.clone(), second.clone()].concat()
+        );
+        controls.tee.lock().take().unwrap().finish().unwrap();
+        let stem = tee
+            .path()
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        // Moved to a `TempFile` so it's removed with the rest
+        let rotated = TempFile::new("wav");
+        std::fs::rename(
+            tee.path().with_file_name(format!("{}-1.wav", stem)),
+            rotated.path(),
+        )
+        .unwrap();
+        for (file, (samples, rate)) in [(&tee, (first, 8_000)), (&rotated, (second, 16_000))] {
+            let mut reader = hound::WavReader::open(file.path()).unwrap();
+            assert_eq!(reader.spec().sample_rate, rate);
+            let recorded: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+            assert_eq!(recorded, samples);
+        }
+    }
+
+    #[test]
+    fn leaves_a_track_needing_another_device_rate_to_the_player() {
+        let (mut queue, _files) = two_tracks(8_000, 16_000);
+        let mut lineup = Lineup::new().unwrap();
+        // The device runs at the first track's rate
+        let mut output = play(&mut queue, &mut lineup, loader(), |plan| {
+            plan.device_rate = Some((8_000, None))
+        });
+
+        output.next();
+        wait_for_arrival(&mut lineup);
+        // Nothing is lined up, and the nested queue ends with the first track
+        assert!(lineup.next(false).is_none());
+        assert_eq!(output.count(), 799);
+        assert!(matches!(lineup.next(true), Some(Arrival::Reload)));
+    }
+
+    #[test]
+    fn moves_on_to_the_next_entry_by_itself() {
+        let first = TempFile::wav(&sine(440.0, 800, 1, 8_000), 1, 8_000);
+        let second = TempFile::wav(&sine(880.0, 800, 1, 8_000), 1, 8_000);
+        let mut queue = Queue::default();
+        queue.push(first.path().to_path_buf());
+        queue.push(second.path().to_path_buf());
+        let mut lineup = Lineup::new().unwrap();
+        let mut output = play(&mut queue, &mut lineup, loader(), |_| {});
+
+        // The first track is within `LEAD` of its end from the start
+        output.next();
+        wait_for_arrival(&mut lineup);
+        // Lined up, but the player doesn't take it over before it starts
+        assert!(lineup.next(false).is_none());
+
+        output.by_ref().take(800).for_each(drop);
+        match lineup.next(false) {
+            Some(Arrival::Lined(lined)) => assert_eq!(lined.info.path, second.path()),
+            _ => panic!("the second track didn't take over"),
+        }
+        wait_for_arrival(&mut lineup);
+        assert!(lineup.next(false).is_none());
+        // Nothing follows, so the nested queue ends with the second track
+        assert_eq!(output.count(), 799);
+        assert!(matches!(lineup.next(true), Some(Arrival::End)));
+        assert!(lineup.next(true).is_none());
+    }
+
+    #[test]
+    fn leaves_the_next_track_to_the_player_without_preloading() {
+        let (mut queue, _files) = two_tracks(8_000, 8_000);
+        let mut lineup = Lineup::new().unwrap();
+        let mut output = play(&mut queue, &mut lineup, loader(), |plan| {
+            plan.preload = false
+        });
+
+        output.next();
+        wait_for_arrival(&mut lineup);
+        assert!(lineup.next(false).is_none());
+        assert_eq!(output.count(), 799);
+        assert!(matches!(lineup.next(true), Some(Arrival::Reload)));
+    }
+
+    #[test]
+    fn drops_a_lined_up_track_when_the_queue_changes() {
+        let (mut queue, files) = two_tracks(8_000, 8_000);
+        let replacement = TempFile::wav(&sine(880.0, 800, 1, 8_000), 1, 8_000);
+        let loader = loader();
+        let mut lineup = Lineup::new().unwrap();
+        let mut output = play(&mut queue, &mut lineup, loader.clone(), |_| {});
+
+        // The second entry is lined up behind the first
+        let mut played: Vec<f32> = output.next().into_iter().collect();
+        wait_for_arrival(&mut lineup);
+
+        // Then the queue changes so another track comes next
+        let mut changed = Queue::default();
+        changed.push(files[0].path().to_path_buf());
+        changed.push(replacement.path().to_path_buf());
+        changed.start();
+        lineup.refollow(Plan {
+            queue: changed,
+            repeat: RepeatMode::Off,
+            loader,
+            preload: true,
+            join_albums: false,
+            device_rate: None,
+        });
+        wait_for_arrival(&mut lineup);
+
+        played.extend(output.by_ref().take(799));
+        played.extend(play_out(&mut output, &mut lineup, 1));
+        assert_eq!(played, [alone(&files[0]), alone(&replacement)].concat());
+    }
+}
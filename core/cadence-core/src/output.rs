@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use rodio::cpal::traits::HostTrait;
+use rodio::dynamic_mixer::{self, DynamicMixerController};
+use rodio::{cpal, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Format of a headless output unless a track asks for another rate
+const HEADLESS_FORMAT: (u16, u32) = (2, 44_100);
+/// How often the headless output takes what has played since the last time
+const HEADLESS_TICK: Duration = Duration::from_millis(5);
+
+/// Which output a player opens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// The system default, whichever device that is when the output is opened
+    Default,
+    /// The device with exactly this name
+    Named(String),
+    /// No device; see `Player::new_headless`
+    Headless,
+}
+
+/// An open output that the player's sinks play on
+pub(crate) enum Output {
+    Device {
+        /// Plays for as long as it's held
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+    },
+    Headless(Headless),
+}
+
+impl Output {
+    /// A new sink on this output, playing alongside any others
+    pub(crate) fn sink(&self) -> Result<Sink> {
+        match self {
+            Output::Device { handle, .. } => Sink::try_new(handle).context("Failed to create sink"),
+            Output::Headless(headless) => {
+                let (sink, queue) = Sink::new_idle();
+                headless.mixer.add(queue);
+                Ok(sink)
+            }
+        }
+    }
+}
+
+/// Mixes what's played on it and discards the result at the pace a device would take it,
+/// so sources run through their processing and end in real time
+pub(crate) struct Headless {
+    mixer: Arc<DynamicMixerController<f32>>,
+    /// Dropped with the output, which ends the thread
+    _stop: Sender<()>,
+}
+
+impl Headless {
+    fn open(channels: u16, sample_rate: u32) -> Result<Self> {
+        let (mixer, mut mixed) = dynamic_mixer::mixer(channels, sample_rate);
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::Builder::new()
+            .name("cadence-headless".to_string())
+            .spawn(move || {
+                let started = Instant::now();
+                let mut taken = 0u64;
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEADLESS_TICK) {
+                    let frames = (started.elapsed().as_secs_f64() * sample_rate as f64) as u64;
+                    for _ in taken..frames * channels as u64 {
+                        mixed.next();
+                    }
+                    taken = taken.max(frames * channels as u64);
+                }
+            })
+            .context("Failed to start the headless output")?;
+        Ok(Self { mixer, _stop: stop })
+    }
+}
+
+/// Open `target` with a sink on it, at `rate` if given and the device supports it, otherwise
+/// at its default config. Also returns the channel count and sample rate it was opened with.
+pub(crate) fn open_output(
+    target: &Target,
+    rate: Option<u32>,
+) -> Result<(Output, Sink, (u16, u32))> {
+    let name = match target {
+        Target::Default => None,
+        Target::Named(name) => Some(name.as_str()),
+        // Takes any rate
+        Target::Headless => {
+            let format = (HEADLESS_FORMAT.0, rate.unwrap_or(HEADLESS_FORMAT.1));
+            let output = Output::Headless(Headless::open(format.0, format.1)?);
+            let sink = output.sink()?;
+            return Ok((output, sink, format));
+        }
+    };
+    let device = match name {
+        Some(name) => Some(find_output_device(name)?),
+        None => cpal::default_host().default_output_device(),
+    };
+    let default = device.as_ref().and_then(|d| d.default_output_config().ok());
+    let at_rate = device.as_ref().zip(rate).and_then(|(device, rate)| {
+        let config = config_at(device, rate)?;
+        let opened = OutputStream::try_from_device_config(device, config.clone()).ok()?;
+        Some((opened, (config.channels(), rate)))
+    });
+
+    let ((stream, handle), format) = match at_rate {
+        Some(opened) => opened,
+        None => {
+            let opened = match (name, &device) {
+                (Some(name), Some(device)) => OutputStream::try_from_device(device)
+                    .with_context(|| format!("Failed to open output device {:?}", name))?,
+                // rodio falls back to any other device if the default won't open
+                _ => OutputStream::try_default().context("No default output device available")?,
+            };
+            // rodio opens the default device with its default config, so ask for the same
+            let format = default
+                .map(|c| (c.channels(), c.sample_rate().0))
+                .unwrap_or((2, 44_100));
+            (opened, format)
+        }
+    };
+    let output = Output::Device {
+        _stream: stream,
+        handle,
+    };
+    let sink = output.sink()?;
+    Ok((output, sink, format))
+}
+
+/// A config for `device` at `rate`, with the channels of its default config, if it supports
+/// that rate
+fn config_at(device: &cpal::Device, rate: u32) -> Option<cpal::SupportedStreamConfig> {
+    let default = device.default_output_config().ok()?;
+    let config = device
+        .supported_output_configs()
+        .ok()?
+        .find(|c| {
+            c.channels() == default.channels()
+                && (c.min_sample_rate().0..=c.max_sample_rate().0).contains(&rate)
+        })?
+        .with_sample_rate(cpal::SampleRate(rate));
+    Some(config)
+}
+
+/// Whether `open_output` can open `target` at `rate`
+pub(crate) fn supports_rate(target: &Target, rate: u32) -> bool {
+    let device = match target {
+        Target::Default => cpal::default_host().default_output_device(),
+        Target::Named(name) => find_output_device(name).ok(),
+        Target::Headless => return true,
+    };
+    device.is_some_and(|device| config_at(&device, rate).is_some())
+}
+
+/// The output device whose name is exactly `name`
+fn find_output_device(name: &str) -> Result<cpal::Device> {
+    let host = cpal::default_host();
+    let listed = host
+        .output_devices()
+        .context("Failed to list output devices")?
+        .find(|device| device.name().is_ok_and(|n| n == name));
+    // Matches `Player::output_devices`, which adds the default if the host doesn't list it
+    let default = || {
+        host.default_output_device()
+            .filter(|device| device.name().is_ok_and(|n| n == name))
+    };
+    listed
+        .or_else(default)
+        .with_context(|| format!("No output device named {:?}", name))
+}
+
+/// Name of the device `target` opens, if there is one and the host reports it
+pub(crate) fn device_name(target: &Target) -> Option<String> {
+    match target {
+        Target::Default => cpal::default_host()
+            .default_output_device()
+            .and_then(|d| d.name().ok()),
+        Target::Named(name) => Some(name.clone()),
+        Target::Headless => None,
+    }
+}
@@ -0,0 +1,91 @@
+/// Callback for `Player::set_pcm_sink`, given interleaved samples, the sample rate and the
+/// channel count
+pub type PcmSink = Box<dyn FnMut(&[f32], u32, u16) + Send>;
+
+/// Frames collected before they're handed to the sink; about 20 ms at 48 kHz
+const BLOCK_FRAMES: usize = 1024;
+
+/// Forwards the processed PCM to a user callback in blocks, so it isn't called per frame
+#[derive(Default)]
+pub(crate) struct PcmForward {
+    sink: Option<PcmSink>,
+    buffer: Vec<f32>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl PcmForward {
+    /// Replace the callback, handing anything still buffered to the old one first
+    pub(crate) fn set(&mut self, sink: Option<PcmSink>) {
+        self.flush();
+        self.sink = sink;
+    }
+
+    /// Append one processed frame; called from the audio thread
+    pub(crate) fn write(&mut self, frame: &[f32], channels: u16, sample_rate: u32) {
+        if self.sink.is_none() {
+            return;
+        }
+        // A block holds a single format
+        if (channels, sample_rate) != (self.channels, self.sample_rate) {
+            self.flush();
+            self.channels = channels;
+            self.sample_rate = sample_rate;
+        }
+        self.buffer.extend_from_slice(frame);
+        if self.buffer.len() >= BLOCK_FRAMES * channels as usize {
+            self.flush();
+        }
+    }
+
+    /// Hand over whatever is buffered, e.g. at the end of a track
+    pub(crate) fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        if let Some(sink) = &mut self.sink {
+            sink(&self.buffer, self.sample_rate, self.channels);
+        }
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{Controls, OutputStage};
+    use crate::testutil::sine;
+    use parking_lot::Mutex;
+    use rodio::buffer::SamplesBuffer;
+    use std::sync::Arc;
+
+    #[test]
+    fn hands_the_sink_every_sample_at_the_source_format() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let blocks = received.clone();
+        let controls = Arc::new(Controls::default());
+        controls
+            .pcm_sink
+            .lock()
+            .set(Some(Box::new(move |samples, rate, channels| {
+                blocks.lock().push((samples.to_vec(), rate, channels));
+            })));
+
+        let samples = sine(440.0, 4_410, 2, 22_050);
+        let source = SamplesBuffer::new(2, 22_050, samples.clone());
+        let played: Vec<f32> = OutputStage::new(source, controls, 2).collect();
+        assert_eq!(played.len(), samples.len());
+
+        let received = received.lock();
+        assert!(received.len() > 1, "handed over in one block");
+        assert!(received
+            .iter()
+            .all(|(block, rate, channels)| block.len() <= BLOCK_FRAMES * 2
+                && (*rate, *channels) == (22_050, 2)));
+        let forwarded: Vec<f32> = received
+            .iter()
+            .flat_map(|(block, ..)| block.clone())
+            .collect();
+        assert_eq!(forwarded, played);
+    }
+}
@@ -0,0 +1,689 @@
+use crate::dsp::channel_delay::ChannelDelay;
+use crate::dsp::compressor::Compressor;
+use crate::dsp::dc_blocker::DcBlocker;
+use crate::dsp::ducking::{Ducker, DuckingInput};
+use crate::dsp::eq::{Equalizer, TrackBands};
+use crate::dsp::intro_assist::IntroAssist;
+use crate::dsp::leveler::Leveler;
+use crate::dsp::limiter::{self, Limiter};
+use crate::dsp::DspSettings;
+use crate::pcm_sink::PcmForward;
+use crate::sleep_timer::SleepState;
+use crate::spectrum::AnalysisTap;
+use crate::tee::Tee;
+use crate::track_end::TrackEnd;
+use parking_lot::Mutex;
+use rodio::Source;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Stereo width kept in Bluetooth-safe mode; 1.0 leaves the image untouched
+const BLUETOOTH_WIDTH: f32 = 0.8;
+/// Peak ceiling (dBFS) of the safety limiter, leaving Bluetooth codecs some headroom
+const LIMITER_CEILING_DB: f32 = -1.0;
+const LIMITER_RELEASE: Duration = Duration::from_millis(200);
+
+/// Settings shared between the `Player` and the sources it has handed to the sink
+#[derive(Default)]
+pub(crate) struct Controls {
+    /// WAV recording of what's sent to the output, while one is running
+    pub tee: Mutex<Option<Tee>>,
+    /// User callback receiving the same PCM as the tee
+    pub pcm_sink: Mutex<PcmForward>,
+    /// Volume the sink applies on top of the processing chain, copied into the tee
+    sink_gain: SinkGain,
+    /// Recent output for spectrum analysis
+    pub analysis: Mutex<AnalysisTap>,
+    /// Side-chain level for ducking
+    pub ducking_input: DuckingInput,
+    /// Subscribers waiting for the current track to finish
+    pub track_end: TrackEnd,
+    /// Fade-out and stop of the sleep timer
+    pub sleep: SleepState,
+    dsp: Mutex<DspSettings>,
+    /// Bumped on every `dsp` change so pipelines know to pick up a fresh copy
+    dsp_version: AtomicU64,
+    /// Bumped by `request_stop`; sources created before the bump fade out and end
+    stop_requests: AtomicU64,
+    /// Ramp length of the latest `request_stop`
+    stop_ramp: Mutex<Duration>,
+}
+
+impl Controls {
+    /// Copy of the current processing settings
+    pub(crate) fn dsp(&self) -> DspSettings {
+        self.dsp.lock().clone()
+    }
+
+    /// Change the processing settings of every playing pipeline
+    pub(crate) fn update_dsp(&self, f: impl FnOnce(&mut DspSettings)) {
+        f(&mut self.dsp.lock());
+        self.dsp_version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Current `dsp` change count, for sources to notice when to pick up a fresh copy
+    pub(crate) fn dsp_version(&self) -> u64 {
+        self.dsp_version.load(Ordering::Acquire)
+    }
+
+    /// Ask every source playing now to ramp down to silence over `ramp` and end
+    pub(crate) fn request_stop(&self, ramp: Duration) {
+        *self.stop_ramp.lock() = ramp;
+        self.stop_requests.fetch_add(1, Ordering::Release);
+    }
+
+    /// Current `request_stop` count, for sources to remember when they're created
+    pub(crate) fn stop_request(&self) -> u64 {
+        self.stop_requests.load(Ordering::Acquire)
+    }
+
+    /// Record the linear volume just set on the sink
+    pub(crate) fn set_sink_gain(&self, gain: f32) {
+        self.sink_gain.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn sink_gain(&self) -> f32 {
+        f32::from_bits(self.sink_gain.0.load(Ordering::Relaxed))
+    }
+}
+
+/// An `f32` kept as its bits, so the audio thread reads it without locking
+struct SinkGain(AtomicU32);
+
+impl Default for SinkGain {
+    fn default() -> Self {
+        Self(AtomicU32::new(1f32.to_bits()))
+    }
+}
+
+/// Wraps a decoded source and runs it frame by frame through the processing chain
+pub(crate) struct Pipeline<S> {
+    inner: S,
+    controls: Arc<Controls>,
+    channels: u16,
+    sample_rate: u32,
+    /// Current frame, one sample per channel
+    frame: Vec<f32>,
+    /// Next sample of `frame` to hand out
+    cursor: usize,
+    /// Track position of the first frame, in seconds
+    start_secs: f64,
+    /// Frames processed so far
+    frames: u64,
+    /// Value of `Controls::stop_requests` when this pipeline was created
+    stop_request: u64,
+    dsp: DspSettings,
+    dsp_version: u64,
+    /// Bands for this track in place of `dsp.eq`, if it has its own
+    track_bands: TrackBands,
+    dc_blocker: Option<DcBlocker>,
+    eq: Option<Equalizer>,
+    leveler: Option<Leveler>,
+    intro_assist: Option<IntroAssist>,
+    ducker: Option<Ducker>,
+    compressor: Option<Compressor>,
+    limiter: Option<Limiter>,
+}
+
+impl<S> Pipeline<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Wrap `inner`, which starts `start` into the track and is equalized with
+    /// `track_bands` where the track has its own
+    pub(crate) fn new(
+        inner: S,
+        controls: Arc<Controls>,
+        start: Duration,
+        track_bands: TrackBands,
+    ) -> Self {
+        let channels = inner.channels().max(1);
+        let sample_rate = inner.sample_rate();
+        let dsp_version = controls.dsp_version.load(Ordering::Acquire);
+        let dsp = controls.dsp.lock().clone();
+        let stop_request = controls.stop_request();
+        let mut pipeline = Self {
+            inner,
+            controls,
+            channels,
+            sample_rate,
+            frame: Vec::with_capacity(channels as usize),
+            cursor: 0,
+            start_secs: start.as_secs_f64(),
+            frames: 0,
+            stop_request,
+            dsp,
+            dsp_version,
+            track_bands,
+            dc_blocker: None,
+            eq: None,
+            leveler: None,
+            intro_assist: None,
+            ducker: None,
+            compressor: None,
+            limiter: None,
+        };
+        pipeline.sync_stages();
+        pipeline
+    }
+
+    /// Pick up settings changed through the `Player` since the last frame
+    fn refresh_dsp(&mut self) {
+        let version = self.controls.dsp_version.load(Ordering::Acquire);
+        if version != self.dsp_version {
+            self.dsp = self.controls.dsp.lock().clone();
+            self.dsp_version = version;
+            self.sync_stages();
+        }
+    }
+
+    /// Create, rebuild or drop stateful stages to match the current settings
+    fn sync_stages(&mut self) {
+        let (channels, rate) = (self.channels, self.sample_rate);
+        let dc_blocker = self.dsp.dc_blocker || self.dsp.bluetooth_safe;
+        match (dc_blocker, self.dc_blocker.is_some()) {
+            (true, false) => self.dc_blocker = Some(DcBlocker::new(channels, rate)),
+            (false, true) => self.dc_blocker = None,
+            _ => {}
+        }
+        let bands = self.track_bands.resolve(&self.dsp.eq, self.dsp.genre_eq);
+        if bands.is_empty() {
+            self.eq = None;
+        } else if self.eq.as_ref().map(|eq| eq.bands()) != Some(&bands[..]) {
+            self.eq = Some(Equalizer::new(bands, channels, rate));
+        }
+        match self.dsp.loudness_leveling {
+            Some(settings) if self.leveler.as_ref().map(|l| l.settings()) != Some(settings) => {
+                self.leveler = Some(Leveler::new(settings, channels, rate));
+            }
+            Some(_) => {}
+            None => self.leveler = None,
+        }
+        match (self.dsp.intro_assist, self.intro_assist.is_some()) {
+            (true, false) => {
+                let start = Duration::from_secs_f64(self.position_secs());
+                self.intro_assist = Some(IntroAssist::new(rate, start));
+            }
+            (false, true) => self.intro_assist = None,
+            _ => {}
+        }
+        match self.dsp.ducking {
+            Some(settings) if self.ducker.as_ref().map(|d| d.settings()) != Some(settings) => {
+                self.ducker = Some(Ducker::new(settings, rate));
+            }
+            Some(_) => {}
+            None => self.ducker = None,
+        }
+        match self.dsp.compressor {
+            Some(settings) if self.compressor.as_ref().map(|c| c.settings()) != Some(settings) => {
+                self.compressor = Some(Compressor::new(settings, rate));
+            }
+            Some(_) => {}
+            None => self.compressor = None,
+        }
+        let limiter = self.dsp.limiter || self.dsp.bluetooth_safe;
+        match (limiter, self.limiter.is_some()) {
+            (true, false) => {
+                self.limiter = Some(Limiter::new(LIMITER_CEILING_DB, LIMITER_RELEASE, rate))
+            }
+            (false, true) => self.limiter = None,
+            _ => {}
+        }
+    }
+
+    /// Follow the inner source to a new channel count or sample rate. Called at frame
+    /// boundaries, where sources are allowed to change format.
+    fn follow_format(&mut self) {
+        let (channels, rate) = (self.inner.channels().max(1), self.inner.sample_rate());
+        if (channels, rate) == (self.channels, self.sample_rate) {
+            return;
+        }
+        self.start_secs = self.position_secs();
+        self.frames = 0;
+        self.channels = channels;
+        self.sample_rate = rate;
+        // Filter state is tied to the old format; start the stages over
+        self.dc_blocker = None;
+        self.eq = None;
+        self.leveler = None;
+        self.intro_assist = None;
+        self.ducker = None;
+        self.compressor = None;
+        self.limiter = None;
+        self.sync_stages();
+    }
+
+    /// Track position of the current frame in seconds
+    fn position_secs(&self) -> f64 {
+        self.start_secs + self.frames as f64 / self.sample_rate.max(1) as f64
+    }
+
+    /// Pull the next full frame from the inner source and process it
+    fn next_frame(&mut self) -> bool {
+        self.frame.clear();
+        for _ in 0..self.channels {
+            match self.inner.next() {
+                Some(sample) => self.frame.push(sample),
+                None => break,
+            }
+        }
+        if self.frame.is_empty() {
+            return false;
+        }
+        // Pad a truncated final frame so channels stay aligned downstream
+        self.frame.resize(self.channels as usize, 0.0);
+
+        self.refresh_dsp();
+        if let Some(blocker) = &mut self.dc_blocker {
+            blocker.process(&mut self.frame);
+        }
+        if let Some(eq) = &mut self.eq {
+            eq.process(&mut self.frame);
+        }
+        if let Some(assist) = &mut self.intro_assist {
+            assist.process(&mut self.frame);
+        }
+        if let Some(leveler) = &mut self.leveler {
+            leveler.process(&mut self.frame);
+        }
+        if let Some(ducker) = &mut self.ducker {
+            ducker.process(&mut self.frame, self.controls.ducking_input.level());
+        }
+        if let Some(envelope) = &self.dsp.gain_envelope {
+            let gain = envelope.gain_at(self.position_secs());
+            self.frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        if let Some(fade) = &self.dsp.end_fade {
+            if self.controls.stop_request() == self.stop_request {
+                let gain = fade.gain_at(self.position_secs());
+                self.frame.iter_mut().for_each(|s| *s *= gain);
+            }
+        }
+        if let Some(compressor) = &mut self.compressor {
+            compressor.process(&mut self.frame);
+        }
+        if self.dsp.bluetooth_safe {
+            limiter::narrow(&mut self.frame, BLUETOOTH_WIDTH);
+        }
+        if let Some(limiter) = &mut self.limiter {
+            limiter.process(&mut self.frame);
+        }
+
+        // Once stopping, this is a tail fading out, possibly under the next track; leave the
+        // analysis to whatever plays next
+        if self.controls.stop_request() == self.stop_request {
+            // Analysis is best-effort; never make the audio thread wait for a reader
+            if let Some(mut tap) = self.controls.analysis.try_lock() {
+                tap.push(&self.frame, self.sample_rate);
+            }
+        }
+
+        self.frames += 1;
+        self.cursor = 0;
+        true
+    }
+}
+
+impl<S> Iterator for Pipeline<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.cursor >= self.frame.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.cursor];
+        self.cursor += 1;
+        if self.cursor == self.frame.len() {
+            self.follow_format();
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Pipeline<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner
+            .current_frame_len()
+            .map(|len| len + (self.frame.len() - self.cursor))
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Corrections for the speakers rather than the track, run after the `Pipeline`.
+///
+/// Frames are mapped to the output's channel count first, the same way rodio's mixer would
+/// (extra output channels repeat the last track channel, surplus track channels are dropped),
+/// so per-speaker settings line up with the actual speakers.
+///
+/// The finished frames, scaled by the sink's volume, go to the tee and the PCM sink, so they
+/// get what the speakers get. A source that is ramping out on stop isn't passed on; during a
+/// crossfade that leaves only the incoming track.
+pub(crate) struct OutputStage<S> {
+    inner: S,
+    controls: Arc<Controls>,
+    in_channels: u16,
+    out_channels: u16,
+    sample_rate: u32,
+    frame: Vec<f32>,
+    /// `frame` at the sink's volume, for the tee and PCM sink
+    recorded: Vec<f32>,
+    cursor: usize,
+    dsp_version: Option<u64>,
+    delay: Option<ChannelDelay>,
+    /// Output channels to negate; empty when none are
+    invert: Vec<bool>,
+    /// Value of `Controls::stop_requests` when this source was created
+    stop_request: u64,
+    /// Frames left and total length of the stop ramp, once it has started, and the sleep
+    /// timer's gain it ramps down from
+    ramp: Option<(u64, u64, f32)>,
+}
+
+impl<S> OutputStage<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(inner: S, controls: Arc<Controls>, out_channels: u16) -> Self {
+        let in_channels = inner.channels().max(1);
+        let sample_rate = inner.sample_rate();
+        let stop_request = controls.stop_request();
+        Self {
+            inner,
+            controls,
+            in_channels,
+            out_channels: out_channels.max(1),
+            sample_rate,
+            frame: Vec::with_capacity(out_channels as usize),
+            recorded: Vec::with_capacity(out_channels as usize),
+            cursor: 0,
+            dsp_version: None,
+            delay: None,
+            invert: Vec::new(),
+            stop_request,
+            ramp: None,
+        }
+    }
+
+    fn refresh(&mut self) {
+        let version = self.controls.dsp_version.load(Ordering::Acquire);
+        if self.dsp_version == Some(version) {
+            return;
+        }
+        self.dsp_version = Some(version);
+        let (delays, invert) = {
+            let dsp = self.controls.dsp.lock();
+            (dsp.channel_delays.clone(), dsp.invert_polarity.clone())
+        };
+        self.delay = (delays.len() == self.out_channels as usize
+            && delays.iter().any(|&d| d > 0.0))
+        .then(|| ChannelDelay::new(&delays, self.sample_rate));
+        self.invert = if invert.len() == self.out_channels as usize && invert.contains(&true) {
+            invert
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Like `Pipeline::follow_format`; the delay lines are rebuilt at the new rate
+    fn follow_format(&mut self) {
+        let (channels, rate) = (self.inner.channels().max(1), self.inner.sample_rate());
+        if (channels, rate) != (self.in_channels, self.sample_rate) {
+            self.in_channels = channels;
+            self.sample_rate = rate;
+            self.dsp_version = None;
+        }
+    }
+
+    /// Gain for the next frame, following the sleep timer's fade and then the ramp while
+    /// stopping, or `None` once the ramp is done
+    fn stop_gain(&mut self) -> Option<f32> {
+        if self.ramp.is_none() {
+            let stopping = self.controls.stop_request() != self.stop_request;
+            if !stopping && !self.controls.sleep.fired() {
+                return Some(self.controls.sleep.gain());
+            }
+            // Created after the sleep timer stopped playback, e.g. lined up from the queue;
+            // never starts
+            let ramp = match stopping {
+                true => *self.controls.stop_ramp.lock(),
+                false => Duration::ZERO,
+            };
+            let frames = (ramp.as_secs_f64() * self.sample_rate as f64) as u64;
+            self.ramp = Some((frames, frames, self.controls.sleep.gain()));
+        }
+        let (left, total, from) = self.ramp.as_mut()?;
+        if *left == 0 {
+            return None;
+        }
+        *left -= 1;
+        Some(*left as f32 / *total as f32 * *from)
+    }
+
+    fn next_frame(&mut self) -> bool {
+        self.refresh();
+        let Some(gain) = self.stop_gain() else {
+            return false;
+        };
+        self.frame.clear();
+        let mut last = 0.0;
+        for ch in 0..self.in_channels {
+            match self.inner.next() {
+                Some(sample) => {
+                    last = sample;
+                    if ch < self.out_channels {
+                        self.frame.push(sample);
+                    }
+                }
+                None if ch == 0 => return false,
+                None => break,
+            }
+        }
+        self.frame.resize(self.out_channels as usize, last);
+
+        if let Some(delay) = &mut self.delay {
+            delay.process(&mut self.frame);
+        }
+        for (sample, &invert) in self.frame.iter_mut().zip(&self.invert) {
+            if invert {
+                *sample = -*sample;
+            }
+        }
+        if gain < 1.0 {
+            self.frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        if self.ramp.is_none() {
+            self.record();
+        }
+        self.cursor = 0;
+        true
+    }
+
+    /// Pass the frame on to the tee and PCM sink. Either is skipped for this frame rather than
+    /// waited for while the `Player` swaps it out.
+    fn record(&mut self) {
+        let gain = self.controls.sink_gain();
+        self.recorded.clear();
+        self.recorded.extend(self.frame.iter().map(|s| s * gain));
+        if let Some(mut tee) = self.controls.tee.try_lock() {
+            if let Some(tee) = tee.as_mut() {
+                tee.write(&self.recorded, self.out_channels, self.sample_rate);
+            }
+        }
+        if let Some(mut pcm_sink) = self.controls.pcm_sink.try_lock() {
+            pcm_sink.write(&self.recorded, self.out_channels, self.sample_rate);
+        }
+    }
+}
+
+impl<S> Iterator for OutputStage<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.cursor >= self.frame.len() && !self.next_frame() {
+            if let Some(mut pcm_sink) = self.controls.pcm_sink.try_lock() {
+                pcm_sink.flush();
+            }
+            return None;
+        }
+        let sample = self.frame[self.cursor];
+        self.cursor += 1;
+        if self.cursor == self.frame.len() {
+            self.follow_format();
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for OutputStage<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        // Frame boundaries of the inner source carry over, scaled to the output channels
+        self.inner.current_frame_len().map(|len| {
+            len / self.in_channels as usize * self.out_channels as usize
+                + (self.frame.len() - self.cursor)
+        })
+    }
+
+    fn channels(&self) -> u16 {
+        self.out_channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+    use rodio::buffer::SamplesBuffer;
+
+    fn output_stage(
+        samples: Vec<f32>,
+        channels: u16,
+        out_channels: u16,
+        dsp: impl FnOnce(&mut DspSettings),
+    ) -> Vec<f32> {
+        let controls = Arc::new(Controls::default());
+        controls.update_dsp(dsp);
+        let source = SamplesBuffer::new(channels, 44_100, samples);
+        OutputStage::new(source, controls, out_channels).collect()
+    }
+
+    #[test]
+    fn inverts_the_polarity_of_selected_channels() {
+        let samples = vec![0.5, 0.25, -0.5, -0.25];
+        let out = output_stage(samples, 2, 2, |dsp| dsp.invert_polarity = vec![false, true]);
+        assert_eq!(out, [0.5, -0.25, -0.5, 0.25]);
+    }
+
+    #[test]
+    fn ignores_polarity_flags_for_another_channel_count() {
+        let samples = vec![0.5, 0.25];
+        let out = output_stage(samples, 2, 2, |dsp| dsp.invert_polarity = vec![true]);
+        assert_eq!(out, [0.5, 0.25]);
+    }
+
+    #[test]
+    fn inverts_after_mapping_to_the_output_channels() {
+        // A mono track on a stereo device, inverting only the right speaker
+        let out = output_stage(vec![0.5, 0.25], 1, 2, |dsp| {
+            dsp.invert_polarity = vec![false, true]
+        });
+        assert_eq!(out, [0.5, -0.5, 0.25, -0.25]);
+    }
+
+    /// Play `source` through an `OutputStage` with the tee recording into `out`, returning
+    /// what the stage sent to the sink
+    fn tee_output_stage(
+        controls: &Arc<Controls>,
+        source: SamplesBuffer<f32>,
+        out: &TempFile,
+    ) -> Vec<f32> {
+        *controls.tee.lock() = Some(Tee::start(out.path().to_path_buf()).unwrap());
+        let played = OutputStage::new(source, controls.clone(), 2).collect();
+        controls.tee.lock().take().unwrap().finish().unwrap();
+        played
+    }
+
+    fn recorded(out: &TempFile) -> Vec<f32> {
+        let mut reader = hound::WavReader::open(out.path()).unwrap();
+        reader.samples::<f32>().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn tees_what_the_speakers_get() {
+        let controls = Arc::new(Controls::default());
+        controls.update_dsp(|dsp| {
+            dsp.channel_delays = vec![0.0, 1.0];
+            dsp.invert_polarity = vec![true, false];
+        });
+        controls.set_sink_gain(0.5);
+        let out = TempFile::new("wav");
+        // Mono, so the output stage also maps it to the stereo output
+        let source = SamplesBuffer::new(1, 8_000, sine(440.0, 4_000, 1, 8_000));
+        let played = tee_output_stage(&controls, source, &out);
+
+        let at_volume: Vec<f32> = played.iter().map(|s| s * 0.5).collect();
+        assert_eq!(played.len(), 8_000);
+        assert_eq!(recorded(&out), at_volume);
+    }
+
+    #[test]
+    fn leaves_a_stopping_source_out_of_the_tee() {
+        let controls = Arc::new(Controls::default());
+        let out = TempFile::new("wav");
+        *controls.tee.lock() = Some(Tee::start(out.path().to_path_buf()).unwrap());
+        let samples = sine(440.0, 800, 2, 8_000);
+        let mut stage = OutputStage::new(
+            SamplesBuffer::new(2, 8_000, samples.clone()),
+            controls.clone(),
+            2,
+        );
+        let before: Vec<f32> = stage.by_ref().take(400).collect();
+        controls.request_stop(Duration::from_millis(10));
+        let tail: Vec<f32> = stage.collect();
+        assert_eq!(tail.len(), 160);
+        controls.tee.lock().take().unwrap().finish().unwrap();
+
+        assert_eq!(recorded(&out), before);
+        // The ramp only ever turns the source down, all the way to silence
+        let gains: Vec<f32> = tail
+            .iter()
+            .zip(&samples[400..])
+            .filter(|(_, original)| original.abs() > 1e-3)
+            .map(|(played, original)| played / original)
+            .collect();
+        assert!(gains[0] <= 1.0, "{}", gains[0]);
+        assert!(gains.windows(2).all(|w| w[1] <= w[0]), "{:?}", gains);
+        assert!(*gains.last().unwrap() < 0.05, "{:?}", gains);
+    }
+}
@@ -0,0 +1,22 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+
+/// Put the machine to sleep with the platform's own tool, returning once it has been asked
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+pub(crate) fn suspend() -> Result<()> {
+    #[cfg(target_os = "linux")]
+    let (program, args) = ("systemctl", ["suspend"].as_slice());
+    #[cfg(target_os = "macos")]
+    let (program, args) = ("pmset", ["sleepnow"].as_slice());
+    #[cfg(target_os = "windows")]
+    let (program, args) = (
+        "rundll32.exe",
+        ["powrprof.dll,SetSuspendState", "0,1,0"].as_slice(),
+    );
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?;
+    anyhow::ensure!(status.success(), "{} failed: {}", program, status);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use crate::retry::{IoRetry, RetryReader};
+use crate::Artwork;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::formats::{FormatOptions, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{
+    MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey, Visual,
+};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+/// What Symphonia can tell about a file without decoding any audio
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Probe {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Position on the album, from tags like "3" or "3/12"
+    pub track_number: Option<u32>,
+    /// Release year, from date tags like "1997" or "1997-05-21"
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    /// ReplayGain adjustments in dB, from tags like "-6.54 dB"
+    pub track_gain_db: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub duration_ms: Option<u64>,
+    /// Short codec name, e.g. "flac" or "mp3"
+    pub codec: Option<String>,
+    pub channels: Option<u16>,
+    /// The container carries cue points (chapters, CUE sheet tracks)
+    pub has_chapters: bool,
+    /// Embedded artwork is present
+    pub has_cover: bool,
+}
+
+/// Open `path` and probe its container format, using the extension as a hint. Reads that
+/// fail with a transient error are retried as `retry` says, while probing and decoding.
+pub(crate) fn open(path: &Path, retry: IoRetry) -> Result<ProbeResult> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    open_source(Box::new(RetryReader::new(file, retry)), &hint)
+        .with_context(|| format!("Unsupported/invalid audio: {:?}", path))
+}
+
+/// Probe the container format of an already opened `source`
+pub(crate) fn open_source(source: Box<dyn MediaSource>, hint: &Hint) -> Result<ProbeResult> {
+    let mss = MediaSourceStream::new(source, Default::default());
+    Ok(symphonia::default::get_probe().format(
+        hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?)
+}
+
+/// Whether `a` and `b` are tagged with the same album; untagged files never are
+pub(crate) fn same_album(a: &Path, b: &Path) -> bool {
+    let album = |path| probe_file(path).ok().and_then(|probe| probe.album);
+    album(a).is_some_and(|album_a| album(b) == Some(album_a))
+}
+
+/// Read the tags and stream parameters of `path`
+pub(crate) fn probe_file(path: &Path) -> Result<Probe> {
+    let mut probed = open(path, IoRetry::default())?;
+    let mut probe = Probe::default();
+
+    if let Some(track) = default_track(probed.format.tracks()) {
+        probe.duration_ms = track_duration_ms(track);
+        probe.codec = symphonia::default::get_codecs()
+            .get_codec(track.codec_params.codec)
+            .map(|desc| desc.short_name.to_string());
+        probe.channels = track.codec_params.channels.map(|c| c.count() as u16);
+    }
+    probe.has_chapters = !probed.format.cues().is_empty();
+
+    // Tags in the container win over ones found ahead of it (e.g. an ID3v2 block)
+    if let Some(rev) = probed.format.metadata().current() {
+        apply_tags(&mut probe, rev);
+    }
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        apply_tags(&mut probe, rev);
+    }
+
+    Ok(probe)
+}
+
+/// Embedded cover art of `path`: the front cover if marked, otherwise the first image
+pub(crate) fn read_artwork(path: &Path) -> Result<Option<Artwork>> {
+    let mut probed = open(path, IoRetry::default())?;
+    // Container images first, as with tags
+    let mut visuals: Vec<Visual> = Vec::new();
+    if let Some(rev) = probed.format.metadata().current() {
+        visuals.extend_from_slice(rev.visuals());
+    }
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        visuals.extend_from_slice(rev.visuals());
+    }
+
+    let cover = visuals
+        .iter()
+        .position(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .unwrap_or(0);
+    Ok((cover < visuals.len()).then(|| {
+        let visual = visuals.swap_remove(cover);
+        Artwork {
+            mime: visual.media_type,
+            data: visual.data.into_vec(),
+        }
+    }))
+}
+
+/// The first track with a known codec
+pub(crate) fn default_track(tracks: &[Track]) -> Option<&Track> {
+    tracks
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+}
+
+/// Duration of a track from its codec parameters, if the container reports it
+pub(crate) fn track_duration_ms(track: &Track) -> Option<u64> {
+    let params = &track.codec_params;
+    let frames = params.n_frames?;
+    if let Some(tb) = params.time_base {
+        let time = tb.calc_time(frames);
+        return Some(time.seconds * 1000 + (time.frac * 1000.0) as u64);
+    }
+    let rate = params.sample_rate? as u64;
+    Some(frames * 1000 / rate)
+}
+
+/// Fill fields that are still empty from a metadata revision
+fn apply_tags(probe: &mut Probe, rev: &MetadataRevision) {
+    probe.has_cover |= !rev.visuals().is_empty();
+    for tag in rev.tags() {
+        // RIFF INFO strings keep their NUL terminators
+        let value = tag.value.to_string();
+        let value = value.trim_end_matches('\0');
+        if tag.std_key == Some(StandardTagKey::TrackNumber) && probe.track_number.is_none() {
+            probe.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok());
+            continue;
+        }
+        if tag.std_key == Some(StandardTagKey::Date) && probe.year.is_none() {
+            let digits: String = value.trim().chars().take(4).collect();
+            probe.year = (digits.len() == 4).then(|| digits.parse().ok()).flatten();
+            continue;
+        }
+        let gain = match tag.std_key {
+            Some(StandardTagKey::ReplayGainTrackGain) => Some(&mut probe.track_gain_db),
+            Some(StandardTagKey::ReplayGainAlbumGain) => Some(&mut probe.album_gain_db),
+            _ => None,
+        };
+        if let Some(gain) = gain {
+            if gain.is_none() {
+                let db = value.trim().trim_end_matches("dB").trim_end_matches("DB");
+                *gain = db.trim().parse().ok().filter(|db: &f32| db.is_finite());
+            }
+            continue;
+        }
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut probe.title,
+            Some(StandardTagKey::Artist) => &mut probe.artist,
+            Some(StandardTagKey::Album) => &mut probe.album,
+            Some(StandardTagKey::Genre) => &mut probe.genre,
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = Some(value.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{sine, TempFile};
+
+    fn tagged() -> TempFile {
+        let frames = [
+            ("TIT2", "Blue in Green"),
+            ("TPE1", "Miles Davis"),
+            ("TALB", "Kind of Blue"),
+            ("TRCK", "3/5"),
+            ("TDRC", "1959-08-17"),
+            ("TCON", "Jazz"),
+        ];
+        TempFile::tagged_wav(&sine(440.0, 44_100, 2, 44_100), 2, 44_100, &frames, None)
+    }
+
+    #[test]
+    fn reads_tags_ahead_of_the_audio() {
+        let probe = probe_file(tagged().path()).unwrap();
+        assert_eq!(probe.title.as_deref(), Some("Blue in Green"));
+        assert_eq!(probe.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(probe.album.as_deref(), Some("Kind of Blue"));
+        assert_eq!(probe.track_number, Some(3));
+        assert_eq!(probe.year, Some(1959));
+        assert_eq!(probe.genre.as_deref(), Some("Jazz"));
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.duration_ms, Some(1_000));
+        assert!(!probe.has_cover);
+    }
+
+    #[test]
+    fn reads_the_embedded_cover() {
+        let cover = b"\x89PNG\r\n\x1a\nnot really an image";
+        let samples = sine(440.0, 4_410, 2, 44_100);
+        let file = TempFile::tagged_wav(&samples, 2, 44_100, &[], Some(cover));
+        assert!(probe_file(file.path()).unwrap().has_cover);
+        let artwork = read_artwork(file.path()).unwrap().unwrap();
+        assert_eq!(artwork.mime, "image/png");
+        assert_eq!(artwork.data, cover);
+
+        assert_eq!(read_artwork(tagged().path()).unwrap(), None);
+    }
+
+    #[test]
+    fn leaves_missing_tags_empty() {
+        let file = TempFile::wav(&sine(440.0, 4_410, 1, 44_100), 1, 44_100);
+        let probe = probe_file(file.path()).unwrap();
+        assert_eq!(probe.title, None);
+        assert_eq!(probe.track_number, None);
+        assert_eq!(probe.channels, Some(1));
+    }
+}
@@ -0,0 +1,690 @@
+use crate::dsp::eq::TrackEq;
+use crate::RepeatMode;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Lowest weight a track counts with in a weighted shuffle, so none is never picked
+const MIN_WEIGHT: f32 = 0.1;
+
+/// Files lined up to play one after another, and which of them is playing
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Queue {
+    /// In the order they were added
+    entries: Vec<PathBuf>,
+    /// Equalizer of each entry set with `Player::set_track_eq`, alongside `entries`
+    track_eqs: Vec<TrackEq>,
+    /// How often each entry comes up in a weighted shuffle, alongside `entries`
+    weights: Vec<f32>,
+    /// Crossfades set with `Player::set_crossfade_for`, by the indices in `entries` of the
+    /// entry faded out and the entry faded in
+    crossfades: BTreeMap<(usize, usize), Duration>,
+    /// Indices into `entries` in play order; shuffled or in insertion order
+    order: Vec<usize>,
+    /// Position in `order` of the entry loaded from the queue; None before `start` and after
+    /// clearing
+    pos: Option<usize>,
+    /// Generator for the shuffled order; None when not shuffling
+    shuffle: Option<Rng>,
+    /// Whole albums are shuffled rather than single entries
+    by_album: bool,
+    /// Entries by the same artist were spaced out after shuffling
+    spread: bool,
+    /// Each next entry is drawn at random by weight instead of following `order`
+    weighted: bool,
+    /// Shuffle single entries afresh each time the order wraps around to its start
+    reshuffle_on_wrap: bool,
+}
+
+impl Queue {
+    pub(crate) fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+
+    pub(crate) fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Equalizer of the entry at `index` in `entries`
+    pub(crate) fn track_eq(&self, index: usize) -> Option<&TrackEq> {
+        self.track_eqs.get(index)
+    }
+
+    /// Equalizer of the current entry
+    pub(crate) fn current_track_eq(&self) -> Option<TrackEq> {
+        self.track_eqs.get(self.current()?).cloned()
+    }
+
+    /// Index in `entries` of the current entry
+    pub(crate) fn current(&self) -> Option<usize> {
+        self.order.get(self.pos?).copied()
+    }
+
+    pub(crate) fn is_shuffled(&self) -> bool {
+        self.shuffle.is_some()
+    }
+
+    pub(crate) fn is_album_shuffled(&self) -> bool {
+        self.shuffle.is_some() && self.by_album
+    }
+
+    pub(crate) fn is_spread(&self) -> bool {
+        self.shuffle.is_some() && self.spread
+    }
+
+    pub(crate) fn is_weighted(&self) -> bool {
+        self.shuffle.is_some() && self.weighted
+    }
+
+    /// Weight of the entry at `index` in `entries`
+    pub(crate) fn weight(&self, index: usize) -> Option<f32> {
+        self.weights.get(index).copied()
+    }
+
+    /// Give the entry at `index` a `weight`, if there is one
+    pub(crate) fn set_weight(&mut self, index: usize, weight: f32) {
+        if let Some(w) = self.weights.get_mut(index) {
+            *w = weight;
+        }
+    }
+
+    /// Crossfade from the entry at `from` into the one at `to`, when one was set for them
+    pub(crate) fn crossfade(&self, from: usize, to: usize) -> Option<Duration> {
+        self.crossfades.get(&(from, to)).copied()
+    }
+
+    /// Longest crossfade set from the entry at `from` into any other
+    pub(crate) fn longest_crossfade_from(&self, from: usize) -> Option<Duration> {
+        self.crossfades
+            .range((from, 0)..=(from, usize::MAX))
+            .map(|(_, &fade)| fade)
+            .max()
+    }
+
+    pub(crate) fn set_crossfade(&mut self, from: usize, to: usize, fade: Duration) {
+        self.crossfades.insert((from, to), fade);
+    }
+
+    pub(crate) fn set_reshuffle_on_wrap(&mut self, enabled: bool) {
+        self.reshuffle_on_wrap = enabled;
+    }
+
+    pub(crate) fn reshuffles_on_wrap(&self) -> bool {
+        self.reshuffle_on_wrap
+    }
+
+    /// Add `path` at the end, or when shuffling single entries, somewhere among the entries
+    /// yet to play
+    pub(crate) fn push(&mut self, path: PathBuf) {
+        let index = self.entries.len();
+        self.entries.push(path);
+        self.track_eqs.push(TrackEq::default());
+        self.weights.push(1.0);
+        let at = match &mut self.shuffle {
+            Some(rng) if !self.by_album => {
+                let first = self.pos.map_or(0, |pos| pos + 1);
+                first + rng.below(self.order.len() - first + 1)
+            }
+            _ => self.order.len(),
+        };
+        self.order.insert(at, index);
+    }
+
+    /// Take `path` out of the queue wherever it was added, except as the current entry,
+    /// which plays on. Returns whether there was any to take out.
+    pub(crate) fn remove(&mut self, path: &Path) -> bool {
+        let current = self.current();
+        let gone: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| self.entries[i] == path && Some(i) != current)
+            .collect();
+        if gone.is_empty() {
+            return false;
+        }
+        self.remove_entries(&gone);
+        true
+    }
+
+    /// How many entries have played before the current one, in play order
+    pub(crate) fn played(&self) -> usize {
+        self.pos.unwrap_or(0)
+    }
+
+    /// Take out the `count` entries that played longest ago, at most `played` of them
+    pub(crate) fn drop_played(&mut self, count: usize) {
+        let mut gone = self.order[..count.min(self.played())].to_vec();
+        gone.sort_unstable();
+        self.remove_entries(&gone);
+    }
+
+    /// Take out the entries at `gone` in `entries`, in ascending order and none of them
+    /// current
+    fn remove_entries(&mut self, gone: &[usize]) {
+        // Entries before the current one in play order move it up
+        if let Some(pos) = self.pos {
+            self.pos = Some(
+                pos - self.order[..pos]
+                    .iter()
+                    .filter(|i| gone.contains(i))
+                    .count(),
+            );
+        }
+        self.order.retain(|i| !gone.contains(i));
+        let moved = |index: usize| index - gone.iter().filter(|&&g| g < index).count();
+        for index in &mut self.order {
+            *index = moved(*index);
+        }
+        self.crossfades = std::mem::take(&mut self.crossfades)
+            .into_iter()
+            .filter(|((from, to), _)| !gone.contains(from) && !gone.contains(to))
+            .map(|((from, to), fade)| ((moved(from), moved(to)), fade))
+            .collect();
+        for &index in gone.iter().rev() {
+            self.entries.remove(index);
+            self.track_eqs.remove(index);
+            self.weights.remove(index);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.track_eqs.clear();
+        self.weights.clear();
+        self.crossfades.clear();
+        self.order.clear();
+        self.pos = None;
+    }
+
+    /// Shuffle the play order with a Fisher-Yates shuffle seeded by `seed`. The current entry,
+    /// if any, moves to the front so playback carries on from it.
+    pub(crate) fn shuffle(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+        let current = self.current();
+        let mut order: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| Some(i) != current)
+            .collect();
+        for i in (1..order.len()).rev() {
+            order.swap(i, rng.below(i + 1));
+        }
+        if let Some(current) = current {
+            order.insert(0, current);
+            self.pos = Some(0);
+        }
+        self.order = order;
+        self.shuffle = Some(rng);
+        self.by_album = false;
+        self.spread = false;
+        self.weighted = false;
+    }
+
+    /// Shuffle as `shuffle` does, and from then on draw each next entry at random with a
+    /// chance in proportion to its weight, no lower than `MIN_WEIGHT`, rather than playing
+    /// through the shuffled order. Entries can come up again before others have played, and
+    /// the draws never run out.
+    pub(crate) fn shuffle_weighted(&mut self, seed: u64) {
+        self.shuffle(seed);
+        self.weighted = true;
+    }
+
+    /// Draw an index into `entries` by weight; the queue mustn't be empty
+    fn draw_weighted(&mut self) -> usize {
+        let rng = self
+            .shuffle
+            .as_mut()
+            .expect("drawing from an unshuffled queue");
+        let weights = self.weights.iter().map(|&w| w.max(MIN_WEIGHT) as f64);
+        let mut left = rng.fraction() * weights.clone().sum::<f64>();
+        for (index, weight) in weights.enumerate() {
+            if left < weight {
+                return index;
+            }
+            left -= weight;
+        }
+        // Rounding left a sliver past the end
+        self.entries.len() - 1
+    }
+
+    /// Shuffle as `shuffle` does, then space out the entries yet to play that share a key in
+    /// `keys`, such as an artist, so none follows another with the same key where that can be
+    /// avoided. Entries without a key go anywhere.
+    pub(crate) fn shuffle_apart(&mut self, seed: u64, keys: &[Option<String>]) {
+        self.shuffle(seed);
+        let key = |index: usize| keys.get(index).and_then(Option::as_deref);
+        let first = self.pos.map_or(0, |pos| pos + 1);
+        let mut left = self.order.split_off(first);
+        while !left.is_empty() {
+            let prev = self.order.last().and_then(|&i| key(i));
+            let mut counts: Vec<(&str, usize)> = Vec::new();
+            for k in left.iter().filter_map(|&i| key(i)) {
+                match counts.iter_mut().find(|(seen, _)| *seen == k) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((k, 1)),
+                }
+            }
+            // A key on half of what's left has to come now, or its entries can't all be
+            // kept apart
+            let crowded = counts
+                .iter()
+                .filter(|&&(k, count)| Some(k) != prev && 2 * count >= left.len())
+                .max_by_key(|(_, count)| *count)
+                .map(|&(k, _)| k);
+            let at = match crowded {
+                Some(crowded) => left.iter().position(|&i| key(i) == Some(crowded)),
+                None => left
+                    .iter()
+                    .position(|&i| key(i).is_none() || key(i) != prev),
+            };
+            self.order.push(left.remove(at.unwrap_or(0)));
+        }
+        self.spread = true;
+    }
+
+    /// Shuffle the order of whole albums, keeping each album's entries together and in track
+    /// number order. `tags` holds the album and track number of every entry; entries without
+    /// an album count as albums of their own. The current entry's album goes first.
+    pub(crate) fn shuffle_albums(&mut self, seed: u64, tags: &[(Option<String>, Option<u32>)]) {
+        let mut rng = Rng::new(seed);
+        let current = self.current();
+
+        // Albums in order of first appearance
+        let mut groups: Vec<(Option<&str>, Vec<usize>)> = Vec::new();
+        for (index, (album, _)) in tags.iter().enumerate().take(self.entries.len()) {
+            let album = album.as_deref();
+            match groups
+                .iter_mut()
+                .find(|(name, _)| album.is_some() && *name == album)
+            {
+                Some((_, entries)) => entries.push(index),
+                None => groups.push((album, vec![index])),
+            }
+        }
+        let mut albums: Vec<Vec<usize>> = groups.into_iter().map(|(_, entries)| entries).collect();
+        for album in &mut albums {
+            album.sort_by_key(|&i| (tags[i].1.unwrap_or(u32::MAX), i));
+        }
+
+        let first = current.and_then(|c| albums.iter().position(|a| a.contains(&c)));
+        let first = first.map(|at| albums.remove(at));
+        for i in (1..albums.len()).rev() {
+            albums.swap(i, rng.below(i + 1));
+        }
+        self.order = first.into_iter().chain(albums).flatten().collect();
+        self.pos = current.and_then(|c| self.order.iter().position(|&i| i == c));
+        self.shuffle = Some(rng);
+        self.by_album = true;
+        self.spread = false;
+        self.weighted = false;
+    }
+
+    /// Shuffle every entry again for another pass, if asked to with `set_reshuffle_on_wrap`,
+    /// carrying on from the generator's state so each pass differs. The entry that played
+    /// last doesn't come first, so it isn't heard twice in a row.
+    fn reshuffle(&mut self) {
+        let Some(rng) = self
+            .shuffle
+            .as_mut()
+            .filter(|_| self.reshuffle_on_wrap && !self.by_album)
+        else {
+            return;
+        };
+        let last = self.pos.and_then(|pos| self.order.get(pos)).copied();
+        let order = &mut self.order;
+        for i in (1..order.len()).rev() {
+            order.swap(i, rng.below(i + 1));
+        }
+        let len = order.len();
+        if len > 1 && order.first().copied() == last {
+            order.swap(0, 1 + rng.below(len - 1));
+        }
+    }
+
+    /// Go back to insertion order, staying on the current entry
+    pub(crate) fn unshuffle(&mut self) {
+        self.pos = self.current();
+        self.order = (0..self.entries.len()).collect();
+        self.shuffle = None;
+        self.by_album = false;
+        self.spread = false;
+        self.weighted = false;
+    }
+
+    /// Point at the first entry in play order
+    pub(crate) fn start(&mut self) -> Option<PathBuf> {
+        self.pos = (!self.order.is_empty()).then_some(0);
+        self.current_path().map(Path::to_path_buf)
+    }
+
+    /// Step to the following entry. At the end of the queue, either wraps to the first entry
+    /// or stays on the last one and returns None. A weighted shuffle draws the entry instead.
+    pub(crate) fn advance(&mut self, wrap: bool) -> Option<PathBuf> {
+        if self.is_weighted() && self.pos.is_some() {
+            let next = self.draw_weighted();
+            self.pos = self.order.iter().position(|&i| i == next);
+            return self.current_path().map(Path::to_path_buf);
+        }
+        let mut next = self.pos? + 1;
+        if next >= self.order.len() {
+            if !wrap || self.order.is_empty() {
+                return None;
+            }
+            next = 0;
+            self.reshuffle();
+        }
+        self.pos = Some(next);
+        self.current_path().map(Path::to_path_buf)
+    }
+
+    /// Step to the previous entry. At the start of the queue, either wraps to the last entry
+    /// or stays on the first one and returns None.
+    pub(crate) fn back(&mut self, wrap: bool) -> Option<PathBuf> {
+        let prev = match self.pos?.checked_sub(1) {
+            Some(prev) => prev,
+            None if wrap => self.order.len().checked_sub(1)?,
+            None => return None,
+        };
+        self.pos = Some(prev);
+        self.current_path().map(Path::to_path_buf)
+    }
+
+    /// Move on from an entry that has played to its end, as `repeat` says
+    pub(crate) fn follow(&mut self, repeat: RepeatMode) -> Option<PathBuf> {
+        match repeat {
+            RepeatMode::One => self.current_path().map(Path::to_path_buf),
+            mode => self.advance(mode == RepeatMode::All),
+        }
+    }
+
+    pub(crate) fn current_path(&self) -> Option<&Path> {
+        self.entries.get(self.current()?).map(PathBuf::as_path)
+    }
+}
+
+/// Small xorshift generator; a shuffle only needs to look random and be repeatable
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at zero
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Roughly uniform value in `0..n`; `n` must be non-zero
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Roughly uniform value in `0.0..1.0`
+    fn fraction(&mut self) -> f64 {
+        // The top 53 bits fill an f64's mantissa
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(len: usize) -> Queue {
+        let mut queue = Queue::default();
+        for i in 0..len {
+            queue.push(PathBuf::from(format!("{}.flac", i)));
+        }
+        queue
+    }
+
+    fn is_permutation(order: &[usize], len: usize) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort();
+        sorted == (0..len).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn shuffles_reproducibly_from_a_seed() {
+        let (mut a, mut b, mut c) = (queue(20), queue(20), queue(20));
+        a.shuffle(42);
+        b.shuffle(42);
+        c.shuffle(7);
+        assert_eq!(a.order(), b.order());
+        assert_ne!(a.order(), c.order());
+        assert!(is_permutation(a.order(), 20));
+        assert_ne!(a.order(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keeps_the_current_entry_playing_through_shuffle_and_back() {
+        let mut queue = queue(10);
+        queue.start();
+        queue.advance(false);
+        queue.advance(false);
+        assert_eq!(queue.current(), Some(2));
+
+        queue.shuffle(1);
+        assert_eq!(queue.order()[0], 2);
+        assert_eq!(queue.current(), Some(2));
+
+        // Next and previous follow the shuffled order
+        let next = queue.order()[1];
+        assert_eq!(
+            queue.advance(false),
+            Some(PathBuf::from(format!("{}.flac", next)))
+        );
+        assert_eq!(queue.back(false), Some(PathBuf::from("2.flac")));
+
+        queue.advance(false);
+        queue.unshuffle();
+        assert_eq!(queue.order(), (0..10).collect::<Vec<_>>());
+        assert_eq!(queue.current(), Some(next));
+        // Carries on in insertion order from there
+        assert_eq!(
+            queue.clone().advance(false).as_ref(),
+            queue.entries().get(next + 1)
+        );
+    }
+
+    #[test]
+    fn adds_to_the_unplayed_entries_while_shuffled() {
+        let mut queue = queue(10);
+        queue.start();
+        queue.shuffle(3);
+        for _ in 0..4 {
+            queue.advance(false);
+        }
+        for i in 10..30 {
+            queue.push(PathBuf::from(format!("{}.flac", i)));
+        }
+        let pos = queue.pos.unwrap();
+        assert!(is_permutation(queue.order(), 30));
+        // Nothing was slipped in among what has already played
+        assert!(queue.order()[..=pos].iter().all(|&i| i < 10));
+        // And the new entries aren't all just appended
+        assert_ne!(&queue.order()[10..], (10..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wraps_only_when_asked() {
+        let mut queue = queue(2);
+        queue.start();
+        assert_eq!(queue.advance(false), Some(PathBuf::from("1.flac")));
+        assert_eq!(queue.advance(false), None);
+        assert_eq!(queue.current_path(), Some(Path::new("1.flac")));
+        assert_eq!(queue.advance(true), Some(PathBuf::from("0.flac")));
+        assert_eq!(queue.back(false), None);
+        assert_eq!(queue.back(true), Some(PathBuf::from("1.flac")));
+    }
+
+    #[test]
+    fn follows_the_repeat_mode() {
+        let mut queue = queue(2);
+        queue.start();
+        assert_eq!(queue.follow(RepeatMode::One), Some(PathBuf::from("0.flac")));
+        assert_eq!(queue.follow(RepeatMode::Off), Some(PathBuf::from("1.flac")));
+        assert_eq!(queue.follow(RepeatMode::Off), None);
+        assert_eq!(queue.follow(RepeatMode::All), Some(PathBuf::from("0.flac")));
+    }
+
+    #[test]
+    fn removes_entries_but_keeps_the_current_one_playing() {
+        let mut queue = queue(5);
+        queue.push(PathBuf::from("1.flac"));
+        queue.start();
+        queue.advance(false);
+        queue.advance(false);
+        assert!(queue.remove(Path::new("1.flac")));
+        assert_eq!(
+            queue.entries(),
+            ["0.flac", "2.flac", "3.flac", "4.flac"].map(PathBuf::from)
+        );
+        assert_eq!(queue.order(), [0, 1, 2, 3]);
+        assert_eq!(queue.current_path(), Some(Path::new("2.flac")));
+        assert_eq!(queue.advance(false), Some(PathBuf::from("3.flac")));
+
+        assert!(!queue.remove(Path::new("3.flac")));
+        assert!(!queue.remove(Path::new("9.flac")));
+    }
+
+    #[test]
+    fn shuffles_whole_albums_in_track_order() {
+        // Three albums added with their tracks interleaved and out of order, and a single
+        let tags: Vec<(Option<String>, Option<u32>)> = [
+            (Some("A"), Some(2)),
+            (Some("B"), Some(1)),
+            (Some("C"), Some(3)),
+            (Some("A"), Some(1)),
+            (None, None),
+            (Some("C"), Some(1)),
+            (Some("B"), Some(2)),
+            (Some("C"), Some(2)),
+            (Some("A"), Some(3)),
+        ]
+        .into_iter()
+        .map(|(album, track)| (album.map(String::from), track))
+        .collect();
+
+        for seed in 0..20 {
+            let mut queue = queue(tags.len());
+            queue.shuffle_albums(seed, &tags);
+            assert!(queue.is_album_shuffled());
+            let order = queue.order();
+            assert!(is_permutation(order, tags.len()));
+            // Entry indices of each album's tracks, in track number order
+            let albums: [&[usize]; 3] = [&[3, 0, 8], &[1, 6], &[5, 7, 2]];
+            for tracks in albums {
+                let start = order.iter().position(|&i| i == tracks[0]).unwrap();
+                assert_eq!(&order[start..start + tracks.len()], tracks, "{:?}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn starts_album_shuffle_from_the_current_album() {
+        let tags = vec![
+            (Some("A".to_string()), Some(1)),
+            (Some("B".to_string()), Some(1)),
+            (Some("B".to_string()), Some(2)),
+        ];
+        let mut queue = queue(3);
+        queue.start();
+        queue.advance(false);
+        queue.shuffle_albums(9, &tags);
+        assert_eq!(&queue.order()[..2], [1, 2]);
+        assert_eq!(queue.current(), Some(1));
+    }
+
+    #[test]
+    fn reshuffles_each_pass_only_when_asked() {
+        let passes = |reshuffle| {
+            let mut queue = queue(8);
+            queue.shuffle(42);
+            queue.set_reshuffle_on_wrap(reshuffle);
+            let mut played = vec![queue.start().unwrap()];
+            for _ in 1..16 {
+                played.push(queue.follow(RepeatMode::All).unwrap());
+            }
+            let second = played.split_off(8);
+            (played, second)
+        };
+
+        let (first, second) = passes(true);
+        assert_ne!(first, second);
+        // Each pass still plays everything once, without the wrap repeating a track
+        let mut sorted = second.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 8);
+        assert_ne!(first.last(), second.first());
+
+        let (first, second) = passes(false);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn keeps_entries_by_the_same_artist_apart() {
+        // Half by one artist, so they only fit alternating, and a few untagged
+        let mut keys: Vec<Option<String>> = vec![Some("Nina".to_string()); 6];
+        keys.extend(["Ella", "Billie", "Sarah", "Ella"].map(|a| Some(a.to_string())));
+        keys.extend([None, None]);
+        let key = |i: usize| keys[i].as_deref();
+        for seed in 1..50 {
+            let mut queue = queue(12);
+            queue.shuffle_apart(seed, &keys);
+            assert!(queue.is_spread());
+            assert!(is_permutation(queue.order(), 12));
+            for pair in queue.order().windows(2) {
+                let (a, b) = (key(pair[0]), key(pair[1]));
+                assert!(a.is_none() || a != b, "seed {}: {:?}", seed, queue.order());
+            }
+        }
+
+        // Without tags it's a plain shuffle
+        let (mut plain, mut untagged) = (queue(12), queue(12));
+        plain.shuffle(9);
+        untagged.shuffle_apart(9, &[]);
+        assert_eq!(plain.order(), untagged.order());
+    }
+
+    #[test]
+    fn draws_entries_in_proportion_to_their_weight() {
+        let mut queue = queue(5);
+        queue.set_weight(0, 3.0);
+        queue.set_weight(4, 0.0);
+        queue.shuffle_weighted(11);
+        assert!(queue.is_weighted());
+        queue.start();
+
+        let mut picks = [0usize; 5];
+        for _ in 0..30_000 {
+            // Even without repeat the draws go on
+            queue.follow(RepeatMode::Off).unwrap();
+            picks[queue.current().unwrap()] += 1;
+        }
+        for baseline in &picks[1..4] {
+            let ratio = picks[0] as f32 / *baseline as f32;
+            assert!((2.7..3.3).contains(&ratio), "{:?}", picks);
+        }
+        // Weighted nothing, it still comes up now and then
+        assert!(picks[4] > 0, "{:?}", picks);
+        assert!(picks[4] < picks[1] / 5, "{:?}", picks);
+    }
+
+    #[test]
+    fn keeps_crossfades_with_their_entries() {
+        let mut queue = queue(4);
+        let fade = Duration::from_secs(2);
+        queue.set_crossfade(2, 3, fade);
+        queue.set_crossfade(1, 2, fade);
+        assert_eq!(queue.longest_crossfade_from(1), Some(fade));
+        queue.remove(Path::new("0.flac"));
+        assert_eq!(queue.crossfade(1, 2), Some(fade));
+        assert_eq!(queue.crossfade(0, 1), Some(fade));
+        queue.remove(Path::new("2.flac"));
+        assert_eq!(queue.crossfade(0, 1), None);
+        assert_eq!(queue.longest_crossfade_from(1), None);
+    }
+}
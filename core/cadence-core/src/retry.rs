@@ -0,0 +1,154 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+
+/// How often a failed read of a track's file is retried before giving up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct IoRetry {
+    /// Retries per read; zero fails on the first error
+    pub attempts: u32,
+    /// Wait between retries
+    pub delay: Duration,
+}
+
+/// Wraps a reader and retries reads and seeks that fail with a transient error.
+///
+/// End of file is never retried; decoders rely on `UnexpectedEof` to find the end of the
+/// stream.
+pub(crate) struct RetryReader<R> {
+    inner: R,
+    retry: IoRetry,
+}
+
+impl<R> RetryReader<R> {
+    pub(crate) fn new(inner: R, retry: IoRetry) -> Self {
+        Self { inner, retry }
+    }
+
+    fn with_retry<T>(&mut self, mut op: impl FnMut(&mut R) -> io::Result<T>) -> io::Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op(&mut self.inner) {
+                Err(e) if attempt < self.retry.attempts && is_transient(&e) => {
+                    attempt += 1;
+                    thread::sleep(self.retry.delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Errors that a flaky disk or network share can recover from
+fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        e.kind(),
+        Interrupted | WouldBlock | TimedOut | ConnectionReset | ConnectionAborted | NotConnected
+    )
+}
+
+impl<R: Read> Read for RetryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.with_retry(|inner| inner.read(buf))
+    }
+}
+
+impl<R: Seek> Seek for RetryReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.with_retry(|inner| inner.seek(pos))
+    }
+}
+
+impl<R: MediaSource> MediaSource for RetryReader<R> {
+    fn is_seekable(&self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.inner.byte_len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::{SymphoniaDecoder, SymphoniaSource};
+    use crate::testutil::{sine, TempFile};
+    use parking_lot::Mutex;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use symphonia::core::probe::Hint;
+
+    /// Reads `data`, failing twice with a timeout once `fail_at` bytes have been read
+    struct Flaky {
+        data: Cursor<Vec<u8>>,
+        fail_at: u64,
+        failures: u32,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.position() >= self.fail_at && self.failures < 2 {
+                self.failures += 1;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "share went away"));
+            }
+            // Stop short of the failure point so reads reach it exactly
+            let left = self.fail_at.saturating_sub(self.data.position()) as usize;
+            let len = match left {
+                0 => buf.len(),
+                left => buf.len().min(left),
+            };
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    impl Seek for Flaky {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    impl MediaSource for Flaky {
+        fn is_seekable(&self) -> bool {
+            false
+        }
+
+        fn byte_len(&self) -> Option<u64> {
+            None
+        }
+    }
+
+    /// Samples decoded from a one second WAV whose reads fail twice half way through
+    fn decode(attempts: u32) -> usize {
+        let wav = TempFile::wav(&sine(440.0, 8_000, 1, 8_000), 1, 8_000);
+        let data = std::fs::read(wav.path()).unwrap();
+        let flaky = Flaky {
+            fail_at: data.len() as u64 / 2,
+            data: Cursor::new(data),
+            failures: 0,
+        };
+        let retry = IoRetry {
+            attempts,
+            delay: Duration::ZERO,
+        };
+        let mut hint = Hint::new();
+        hint.with_extension("wav");
+        let source = Box::new(RetryReader::new(flaky, retry));
+        let decoder = SymphoniaDecoder::open_stream(source, &hint, "flaky").unwrap();
+        SymphoniaSource::new(Arc::new(Mutex::new(decoder)), Arc::default()).count()
+    }
+
+    #[test]
+    fn continues_past_transient_errors_with_retries() {
+        assert_eq!(decode(2), 8_000);
+    }
+
+    #[test]
+    fn stops_at_the_first_error_without_retries() {
+        assert!(decode(0) < 8_000);
+        // One retry isn't enough for two failures in a row
+        assert!(decode(1) < 8_000);
+    }
+}
@@ -0,0 +1,212 @@
+use rodio::buffer::SamplesBuffer;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// Length of the noise buffer that is looped while a noise signal plays
+const NOISE_LOOP: Duration = Duration::from_secs(10);
+/// Peak level of the sweep
+const SWEEP_AMPLITUDE: f32 = 0.5;
+/// RMS level of both noise signals, about -18 dBFS
+const NOISE_RMS: f32 = 0.125;
+
+/// Built-in calibration signals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestSignal {
+    /// Equal energy per Hz, looped until stopped
+    WhiteNoise,
+    /// Equal energy per octave (-3 dB/octave), looped until stopped
+    PinkNoise,
+    /// Exponential sine sweep, played once
+    Sweep {
+        start_hz: f32,
+        end_hz: f32,
+        duration: Duration,
+    },
+}
+
+/// Render `signal` at the given output format. With `channel` set, only that channel carries
+/// the signal and the others are silent, for identifying speakers.
+pub(crate) fn render(
+    signal: TestSignal,
+    channels: u16,
+    sample_rate: u32,
+    channel: Option<u16>,
+) -> SamplesBuffer<f32> {
+    let mono = match signal {
+        TestSignal::WhiteNoise => white_noise(frames(NOISE_LOOP, sample_rate)),
+        TestSignal::PinkNoise => pink_noise(frames(NOISE_LOOP, sample_rate)),
+        TestSignal::Sweep {
+            start_hz,
+            end_hz,
+            duration,
+        } => sweep(start_hz, end_hz, frames(duration, sample_rate), sample_rate),
+    };
+
+    let mut data = Vec::with_capacity(mono.len() * channels as usize);
+    for sample in mono {
+        for ch in 0..channels {
+            let on = channel.is_none_or(|only| only == ch);
+            data.push(if on { sample } else { 0.0 });
+        }
+    }
+    SamplesBuffer::new(channels, sample_rate, data)
+}
+
+fn frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64) as usize
+}
+
+/// Small xorshift generator; calibration noise doesn't need a cryptographic source
+struct Noise(u64);
+
+impl Noise {
+    /// Uniform sample in [-1, 1)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+fn white_noise(frames: usize) -> Vec<f32> {
+    let mut noise = Noise(0x9E37_79B9_7F4A_7C15);
+    // Uniform noise in [-1, 1) has an RMS of 1/sqrt(3)
+    let scale = NOISE_RMS * 3f32.sqrt();
+    (0..frames).map(|_| noise.next() * scale).collect()
+}
+
+/// White noise through Paul Kellet's refined pinking filter
+fn pink_noise(frames: usize) -> Vec<f32> {
+    let mut noise = Noise(0x2545_F491_4F6C_DD1D);
+    let mut b = [0.0f32; 7];
+    let mut out: Vec<f32> = (0..frames)
+        .map(|_| {
+            let white = noise.next();
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.96900 * b[2] + white * 0.153852;
+            b[3] = 0.86650 * b[3] + white * 0.3104856;
+            b[4] = 0.55000 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+            let pink = b[..6].iter().sum::<f32>() + b[6] + white * 0.5362;
+            b[6] = white * 0.115926;
+            pink
+        })
+        .collect();
+
+    let rms = (out.iter().map(|s| s * s).sum::<f32>() / out.len().max(1) as f32).sqrt();
+    if rms > 0.0 {
+        out.iter_mut().for_each(|s| *s *= NOISE_RMS / rms);
+    }
+    out
+}
+
+/// Exponential sweep, spending equal time per octave
+fn sweep(start_hz: f32, end_hz: f32, frames: usize, sample_rate: u32) -> Vec<f32> {
+    let (f0, f1) = (start_hz.max(1.0) as f64, end_hz.max(1.0) as f64);
+    let length = frames as f64 / sample_rate as f64;
+    let ratio = (f1 / f0).ln();
+    (0..frames)
+        .map(|i| {
+            let t = i as f64 / sample_rate as f64;
+            let phase = if ratio.abs() < 1e-9 {
+                2.0 * PI * f0 * t
+            } else {
+                2.0 * PI * f0 * length / ratio * ((t / length * ratio).exp() - 1.0)
+            };
+            phase.sin() as f32 * SWEEP_AMPLITUDE
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustfft::num_complex::Complex;
+    use rustfft::FftPlanner;
+
+    const RATE: u32 = 44_100;
+    const FFT_LEN: usize = 4_096;
+
+    /// Slope of the power spectral density in dB per octave, fitted over 100 Hz to 10 kHz
+    fn slope_db_per_octave(samples: &[f32]) -> f64 {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_LEN);
+        // Welch average over Hann-windowed blocks
+        let mut psd = vec![0.0f64; FFT_LEN / 2];
+        for block in samples.chunks_exact(FFT_LEN) {
+            let mut buf: Vec<Complex<f32>> = block
+                .iter()
+                .enumerate()
+                .map(|(i, &s)| {
+                    let w = 0.5 - 0.5 * (2.0 * PI * i as f64 / FFT_LEN as f64).cos();
+                    Complex::new(s * w as f32, 0.0)
+                })
+                .collect();
+            fft.process(&mut buf);
+            for (p, c) in psd.iter_mut().zip(&buf) {
+                *p += c.norm_sqr() as f64;
+            }
+        }
+        let bin_hz = RATE as f64 / FFT_LEN as f64;
+        // Mean density per octave band, against the band's octave number
+        let points: Vec<(f64, f64)> = (0..7)
+            .map(|octave| {
+                let low = 100.0 * 2f64.powi(octave);
+                let bins = (low / bin_hz) as usize..(2.0 * low / bin_hz) as usize;
+                let mean = psd[bins.clone()].iter().sum::<f64>() / bins.len() as f64;
+                (octave as f64, 10.0 * mean.log10())
+            })
+            .collect();
+        let n = points.len() as f64;
+        let (mx, my) = points
+            .iter()
+            .fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+        let covariance: f64 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+        let variance: f64 = points.iter().map(|(x, _)| (x - mx) * (x - mx)).sum();
+        covariance / variance
+    }
+
+    fn mono(signal: TestSignal) -> Vec<f32> {
+        render(signal, 1, RATE, None).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn white_noise_is_flat() {
+        let noise = mono(TestSignal::WhiteNoise);
+        let slope = slope_db_per_octave(&noise);
+        assert!(slope.abs() < 0.5, "{}", slope);
+        assert!((rms(&noise) - NOISE_RMS).abs() < 0.01);
+    }
+
+    #[test]
+    fn pink_noise_falls_3_db_per_octave() {
+        let noise = mono(TestSignal::PinkNoise);
+        let slope = slope_db_per_octave(&noise);
+        assert!((slope + 3.0).abs() < 0.5, "{}", slope);
+        assert!((rms(&noise) - NOISE_RMS).abs() < 0.01);
+    }
+
+    #[test]
+    fn plays_on_one_channel_only() {
+        let stereo: Vec<f32> = render(TestSignal::PinkNoise, 2, RATE, Some(1)).collect();
+        assert!(stereo.iter().step_by(2).all(|&s| s == 0.0));
+        assert!(stereo.iter().skip(1).step_by(2).any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn sweeps_for_the_requested_duration() {
+        let sweep = mono(TestSignal::Sweep {
+            start_hz: 20.0,
+            end_hz: 20_000.0,
+            duration: Duration::from_secs(2),
+        });
+        assert_eq!(sweep.len(), 2 * RATE as usize);
+        let peak = sweep.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - SWEEP_AMPLITUDE).abs() < 0.01);
+    }
+}
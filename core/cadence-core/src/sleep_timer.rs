@@ -0,0 +1,166 @@
+use crate::pipeline::Controls;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the fade-out steps the volume down
+const FADE_STEP: Duration = Duration::from_millis(50);
+
+/// What the sleep timer has done to the output, read by the audio thread
+pub(crate) struct SleepState {
+    /// Linear gain of the fade-out, as `f32` bits; 1.0 when not fading
+    gain: AtomicU32,
+    /// Set once the timer has stopped playback; sources starting after that end at once
+    fired: AtomicBool,
+}
+
+impl Default for SleepState {
+    fn default() -> Self {
+        Self {
+            gain: AtomicU32::new(1f32.to_bits()),
+            fired: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SleepState {
+    pub(crate) fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn fired(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+
+    fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Undo the fade and let sources play again
+    pub(crate) fn reset(&self) {
+        self.set_gain(1.0);
+        self.fired.store(false, Ordering::Release);
+    }
+}
+
+/// Counts down a `Player::set_sleep_timer` on its own thread, fading the output and stopping
+/// it on time whether or not the player is being polled. Dropping it cancels the timer, but
+/// leaves the output as it was until `SleepState::reset`, so a stop ramp started during the
+/// fade carries on from the faded volume.
+pub(crate) struct SleepTimer {
+    deadline: Instant,
+    controls: Arc<Controls>,
+    cancel: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SleepTimer {
+    /// Fire `after` from now, fading out over the last `fade` before then when given
+    pub(crate) fn start(controls: Arc<Controls>, after: Duration, fade: Option<Duration>) -> Self {
+        let deadline = Instant::now() + after;
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let shared = controls.clone();
+        let thread = thread::spawn(move || loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                // Stays silent through the stop ramp after a fade-out
+                if fade.is_some() {
+                    shared.sleep.set_gain(0.0);
+                }
+                // The sources fade out and end on the audio thread, as for `stop`
+                shared.request_stop(shared.dsp().stop_ramp);
+                shared.sleep.fired.store(true, Ordering::Release);
+                return;
+            }
+            let wait = match fade {
+                None => left,
+                Some(fade) if left > fade => left - fade,
+                Some(fade) => {
+                    shared
+                        .sleep
+                        .set_gain(left.as_secs_f32() / fade.as_secs_f32());
+                    left.min(FADE_STEP)
+                }
+            };
+            // A message or a hang-up both mean the timer was cancelled
+            if cancelled.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+        });
+        Self {
+            deadline,
+            controls,
+            cancel: Some(cancel),
+            thread: Some(thread),
+        }
+    }
+
+    /// Time left until the timer fires
+    pub(crate) fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the timer has stopped playback
+    pub(crate) fn fired(&self) -> bool {
+        self.controls.sleep.fired()
+    }
+}
+
+impl Drop for SleepTimer {
+    fn drop(&mut self) {
+        // Wakes the thread at once; waiting for it means it can't touch the output after a
+        // reset that follows
+        drop(self.cancel.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_out_then_fires() {
+        let controls = Arc::new(Controls::default());
+        let timer = SleepTimer::start(
+            controls.clone(),
+            Duration::from_millis(400),
+            Some(Duration::from_millis(200)),
+        );
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(controls.sleep.gain(), 1.0);
+        thread::sleep(Duration::from_millis(200));
+        let gain = controls.sleep.gain();
+        assert!(gain > 0.1 && gain < 0.9, "{}", gain);
+        assert!(!timer.fired());
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(timer.fired());
+        assert_eq!(controls.sleep.gain(), 0.0);
+        assert!(controls.stop_request() > 0);
+
+        // Dropping it leaves the output silent until reset
+        drop(timer);
+        assert!(controls.sleep.fired());
+        controls.sleep.reset();
+        assert!(!controls.sleep.fired());
+        assert_eq!(controls.sleep.gain(), 1.0);
+    }
+
+    #[test]
+    fn does_nothing_once_cancelled() {
+        let controls = Arc::new(Controls::default());
+        drop(SleepTimer::start(
+            controls.clone(),
+            Duration::from_millis(50),
+            None,
+        ));
+        thread::sleep(Duration::from_millis(150));
+        assert!(!controls.sleep.fired());
+        assert_eq!(controls.stop_request(), 0);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::pipeline::Controls;
+use anyhow::Result;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Number of recent samples kept for analysis; also the largest analyzer FFT
+const TAP_LEN: usize = 4096;
+/// Lowest frequency covered by the analyzer's bands
+const LOWEST_BAND_HZ: f32 = 20.0;
+/// Below this RMS (dBFS) there is nothing worth analysing
+const QUIET_DB: f32 = -50.0;
+/// A clear peak must stand this far above the average bin magnitude
+const MIN_PEAK_RATIO: f32 = 8.0;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// The most recent output, downmixed to mono, for analysis off the audio thread
+#[derive(Default)]
+pub(crate) struct AnalysisTap {
+    samples: VecDeque<f32>,
+    sample_rate: u32,
+    /// Frames pushed so far, so readers can tell whether anything new has played
+    pushed: u64,
+}
+
+impl AnalysisTap {
+    /// Append one processed frame; called from the audio thread
+    pub(crate) fn push(&mut self, frame: &[f32], sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.samples.clear();
+            self.sample_rate = sample_rate;
+        }
+        if self.samples.len() == TAP_LEN {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back(frame.iter().sum::<f32>() / frame.len().max(1) as f32);
+        self.pushed += 1;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Copy of the buffered samples and their sample rate, once the buffer is full
+    pub(crate) fn snapshot(&self) -> Option<(Vec<f32>, u32)> {
+        (self.samples.len() == TAP_LEN)
+            .then(|| (self.samples.iter().copied().collect(), self.sample_rate))
+    }
+
+    /// The latest `len` samples and their sample rate, if the buffer is full and something
+    /// was pushed since `seen`, which is updated
+    fn latest(&self, len: usize, seen: &mut u64) -> Option<(Vec<f32>, u32)> {
+        if self.samples.len() < TAP_LEN || self.pushed == *seen {
+            return None;
+        }
+        *seen = self.pushed;
+        let skip = self.samples.len() - len.min(TAP_LEN);
+        Some((
+            self.samples.iter().skip(skip).copied().collect(),
+            self.sample_rate,
+        ))
+    }
+}
+
+/// Callback for `Player::set_analyzer`, given one magnitude per band
+pub type SpectrumCallback = Box<dyn FnMut(&[f32]) + Send>;
+
+/// How `Player::set_analyzer` computes its spectrum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalyzerSettings {
+    /// Samples per FFT, rounded to a power of two between 64 and 4096. Larger sizes resolve
+    /// low frequencies better but react more slowly.
+    pub fft_size: usize,
+    /// Number of bands handed to the callback, spaced logarithmically from 20 Hz to half the
+    /// sample rate
+    pub bands: usize,
+    /// How many times a second to call back while audio is playing
+    pub rate_hz: f32,
+}
+
+impl Default for AnalyzerSettings {
+    fn default() -> Self {
+        Self {
+            fft_size: 2048,
+            bands: 32,
+            rate_hz: 30.0,
+        }
+    }
+}
+
+/// Runs the analyzer callback on its own thread, so a slow callback can only delay the
+/// next update and never the audio. Stops when dropped.
+pub(crate) struct Analyzer {
+    stop: Arc<AtomicBool>,
+}
+
+impl Analyzer {
+    pub(crate) fn start(
+        controls: Arc<Controls>,
+        settings: AnalyzerSettings,
+        mut callback: SpectrumCallback,
+    ) -> Result<Self> {
+        // NaN would get through the clamp below
+        if !settings.rate_hz.is_finite() {
+            anyhow::bail!("Invalid analyzer rate: {}", settings.rate_hz);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let fft_size = settings.fft_size.clamp(64, TAP_LEN).next_power_of_two();
+        let interval = Duration::from_secs_f32(1.0 / settings.rate_hz.clamp(1.0, 120.0));
+        thread::spawn(move || {
+            let mut seen = 0;
+            while !stopped.load(Ordering::Acquire) {
+                thread::sleep(interval);
+                // Whatever played while the callback was busy is skipped, not queued
+                let latest = controls.analysis.lock().latest(fft_size, &mut seen);
+                if let Some((samples, sample_rate)) = latest {
+                    callback(&bands(&samples, sample_rate, settings.bands));
+                }
+            }
+        });
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for Analyzer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+/// Log-spaced band levels of `samples`, each the strongest bin in its range, scaled so a
+/// full-scale sine reads about 1.0
+fn bands(samples: &[f32], sample_rate: u32, count: usize) -> Vec<f32> {
+    let bins = magnitudes(samples);
+    let hz_per_bin = sample_rate.max(1) as f32 / samples.len().max(1) as f32;
+    let nyquist = sample_rate as f32 / 2.0;
+    // The Hann window halves a sine's peak bin, which is then `len / 2` times its amplitude
+    let scale = 4.0 / samples.len().max(1) as f32;
+    let edge = |band: usize| {
+        let hz = LOWEST_BAND_HZ * (nyquist / LOWEST_BAND_HZ).powf(band as f32 / count as f32);
+        ((hz / hz_per_bin) as usize).min(bins.len())
+    };
+    (0..count)
+        .map(|band| {
+            let start = edge(band).min(bins.len().saturating_sub(1));
+            // Low bands can be narrower than a bin; they read the bin they fall in
+            let end = edge(band + 1).max(start + 1);
+            bins[start..end.min(bins.len())]
+                .iter()
+                .fold(0.0f32, |peak, &mag| peak.max(mag))
+                * scale
+        })
+        .collect()
+}
+
+/// Hann-windowed magnitude spectrum of `samples`, one bin per `sample_rate / len` Hz
+pub(crate) fn magnitudes(samples: &[f32]) -> Vec<f32> {
+    let len = samples.len();
+    let mut buffer: Vec<Complex<f32>> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| {
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / (len - 1).max(1) as f32).cos();
+            Complex::new(s * window, 0.0)
+        })
+        .collect();
+    FftPlanner::new().plan_fft_forward(len).process(&mut buffer);
+    buffer[..len / 2].iter().map(|c| c.norm()).collect()
+}
+
+/// The frequency of the strongest clear peak in `samples`, if there is one
+pub(crate) fn dominant_frequency(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+    if 20.0 * rms.max(1e-10).log10() < QUIET_DB {
+        return None;
+    }
+
+    let bins = magnitudes(samples);
+    // Skip DC
+    let (peak, &peak_mag) = bins
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let mean = bins.iter().sum::<f32>() / bins.len() as f32;
+    if peak_mag < mean * MIN_PEAK_RATIO || peak + 1 >= bins.len() {
+        return None;
+    }
+
+    // Parabolic interpolation between neighbouring bins for sub-bin accuracy
+    let (left, right) = (bins[peak - 1], bins[peak + 1]);
+    let denom = left - 2.0 * peak_mag + right;
+    let offset = if denom.abs() > f32::EPSILON {
+        0.5 * (left - right) / denom
+    } else {
+        0.0
+    };
+    Some((peak as f32 + offset) * sample_rate as f32 / samples.len() as f32)
+}
+
+/// Nearest equal-tempered note name for a frequency, e.g. "A4" for 440 Hz
+pub(crate) fn note_name(freq_hz: f32) -> Option<String> {
+    if freq_hz <= 0.0 {
+        return None;
+    }
+    let midi = (69.0 + 12.0 * (freq_hz / 440.0).log2()).round() as i32;
+    if midi < 0 {
+        return None;
+    }
+    Some(format!(
+        "{}{}",
+        NOTE_NAMES[(midi % 12) as usize],
+        midi / 12 - 1
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::sine;
+
+    fn start(rate_hz: f32) -> Result<Analyzer> {
+        let settings = AnalyzerSettings {
+            rate_hz,
+            ..Default::default()
+        };
+        Analyzer::start(Arc::new(Controls::default()), settings, Box::new(|_| {}))
+    }
+
+    #[test]
+    fn rejects_rates_that_are_not_numbers() {
+        assert!(start(f32::NAN).is_err());
+        assert!(start(f32::INFINITY).is_err());
+        assert!(start(f32::NEG_INFINITY).is_err());
+    }
+
+    #[test]
+    fn clamps_out_of_range_rates() {
+        assert!(start(0.0).is_ok());
+        assert!(start(-5.0).is_ok());
+        assert!(start(10_000.0).is_ok());
+    }
+
+    #[test]
+    fn finds_the_frequency_and_note_of_a_sine() {
+        let samples = sine(440.0, TAP_LEN, 1, 44_100);
+        let freq = dominant_frequency(&samples, 44_100).unwrap();
+        assert!((freq - 440.0).abs() < 2.0, "{freq} Hz");
+        assert_eq!(note_name(freq).as_deref(), Some("A4"));
+    }
+
+    #[test]
+    fn finds_nothing_in_silence() {
+        assert_eq!(dominant_frequency(&[0.0; TAP_LEN], 44_100), None);
+    }
+
+    #[test]
+    fn a_sine_dominates_its_own_band() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let controls = Arc::new(Controls::default());
+        let _analyzer = Analyzer::start(
+            controls.clone(),
+            AnalyzerSettings::default(),
+            Box::new(move |bands| {
+                let _ = tx.send(bands.to_vec());
+            }),
+        )
+        .unwrap();
+        for sample in sine(1_000.0, TAP_LEN, 1, 44_100) {
+            controls.analysis.lock().push(&[sample], 44_100);
+        }
+
+        let bands = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(bands.len(), 32);
+        let (loudest, &level) = bands
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        // 1 kHz is log(1000 / 20) / log(22050 / 20) of the way up 32 bands
+        assert_eq!(loudest, 17);
+        // A half-scale sine reads about 0.5
+        assert!((level - 0.5).abs() < 0.1, "{level}");
+    }
+
+    #[test]
+    fn names_notes() {
+        assert_eq!(note_name(440.0).as_deref(), Some("A4"));
+        assert_eq!(note_name(261.63).as_deref(), Some("C4"));
+        assert_eq!(note_name(0.0), None);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::EqBand;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies a bookmark across sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BookmarkId(pub u64);
+
+/// A labelled spot in a track, saved by `Player::add_bookmark`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: BookmarkId,
+    pub path: PathBuf,
+    pub position_ms: u64,
+    pub label: String,
+}
+
+/// Saved playback positions, bookmarks and equalizer presets, kept as one JSON file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Store {
+    /// Where to resume each track, in ms, by path
+    #[serde(default)]
+    pub positions: BTreeMap<PathBuf, u64>,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    /// Id for the next bookmark, so an id is never reused after its bookmark is removed
+    #[serde(default)]
+    next_bookmark_id: u64,
+    /// Equalizer presets saved with `Player::save_eq_preset`, by name
+    #[serde(default)]
+    pub eq_presets: BTreeMap<String, Vec<EqBand>>,
+}
+
+impl Store {
+    /// Read the store at `path`; a store that doesn't exist yet is empty
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", path)),
+        };
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid position store {:?}", path))
+    }
+
+    /// Replace the store at `path`, writing a temp file first so a crash can't leave it half
+    /// written
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        let file = File::create(&tmp).with_context(|| format!("Failed to create {:?}", tmp))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
+        writer
+            .flush()
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace {:?}", path))
+    }
+
+    /// Add a bookmark with an id no bookmark in the store has ever had
+    pub(crate) fn add_bookmark(
+        &mut self,
+        path: PathBuf,
+        position_ms: u64,
+        label: String,
+    ) -> Bookmark {
+        // Stores saved before `next_bookmark_id` existed start after their highest id
+        let id = self
+            .bookmarks
+            .iter()
+            .map(|b| b.id.0 + 1)
+            .fold(self.next_bookmark_id, u64::max);
+        self.next_bookmark_id = id + 1;
+        let bookmark = Bookmark {
+            id: BookmarkId(id),
+            path,
+            position_ms,
+            label,
+        };
+        self.bookmarks.push(bookmark.clone());
+        bookmark
+    }
+}
+
+/// `cadence/positions.json` in the platform's config directory, falling back to the
+/// working directory if that can't be found
+pub(crate) fn default_path() -> PathBuf {
+    config_dir()
+        .map(|dir| dir.join("cadence"))
+        .unwrap_or_default()
+        .join("positions.json")
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempFile;
+
+    #[test]
+    fn bookmark_ids_are_never_reused() {
+        let file = TempFile::new("json");
+        let mut store = Store::default();
+        let first = store.add_bookmark("a.flac".into(), 1_000, "intro".into());
+        let second = store.add_bookmark("a.flac".into(), 2_000, "verse".into());
+        assert_ne!(first.id, second.id);
+
+        // Removing the newest bookmark mustn't free its id, even across a save and load
+        store.bookmarks.retain(|b| b.id != second.id);
+        store.save(file.path()).unwrap();
+        let mut store = Store::load(file.path()).unwrap();
+        let third = store.add_bookmark("b.flac".into(), 0, "start".into());
+        assert!(third.id > second.id);
+    }
+
+    #[test]
+    fn stores_without_a_next_id_continue_after_their_bookmarks() {
+        let file = TempFile::new("json");
+        let old = r#"{"bookmarks": [{"id": 7, "path": "a.flac", "position_ms": 0, "label": ""}]}"#;
+        fs::write(file.path(), old).unwrap();
+        let mut store = Store::load(file.path()).unwrap();
+        let bookmark = store.add_bookmark("a.flac".into(), 5, "later".into());
+        assert_eq!(bookmark.id, BookmarkId(8));
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let file = TempFile::new("json");
+        let mut store = Store::default();
+        store.positions.insert("book.m4b".into(), 3_600_000);
+        store.positions.insert("talk.mp3".into(), 42);
+        store.save(file.path()).unwrap();
+
+        let loaded = Store::load(file.path()).unwrap();
+        assert_eq!(loaded.positions, store.positions);
+        // The temp file was renamed into place
+        assert!(!file.path().with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn a_missing_store_is_empty() {
+        let file = TempFile::new("json");
+        let store = Store::load(file.path()).unwrap();
+        assert!(store.positions.is_empty());
+        assert!(store.bookmarks.is_empty());
+    }
+}
@@ -0,0 +1,608 @@
+use crate::pipeline::Controls;
+use crate::probe;
+use crate::retry::IoRetry;
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use rodio::Source;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{
+    CodecType, Decoder, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_ADPCM_IMA_WAV,
+    CODEC_TYPE_ADPCM_MS, CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3, CODEC_TYPE_OPUS,
+    CODEC_TYPE_VORBIS,
+};
+use symphonia::core::errors::{Error, SeekErrorKind};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSource;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+/// A track being decoded packet by packet with Symphonia, shared between the `Player` (which
+/// seeks it) and the source currently playing from it
+pub(crate) struct SymphoniaDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    channels: u16,
+    sample_rate: u32,
+    duration: Option<Duration>,
+    /// Frames still to drop to land exactly on the last seek target
+    skip_frames: u64,
+    /// Bumped on every seek; sources from an older generation stop playing
+    generation: u64,
+    sample_buf: Option<(Samples, SignalSpec)>,
+    /// Whether the codec throws information away, so its bitrate is worth showing
+    lossy: bool,
+    bitrate: BitrateMeter,
+}
+
+/// Damaged packets skipped in a row before giving up on the stream
+const MAX_CONSECUTIVE_ERRORS: u32 = 100;
+
+/// Sample format Symphonia decodes each packet into before it's played.
+///
+/// Playback and processing run in 32-bit float either way; an integer format makes the decoder
+/// hand over a 16- or 32-bit source's samples as they are stored, rather than converting each
+/// packet to float itself. `I16` keeps only the top 16 bits of deeper sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InternalFormat {
+    #[default]
+    F32,
+    I32,
+    I16,
+}
+
+/// A packet's samples, interleaved, in one of the `InternalFormat`s
+enum Samples {
+    F32(SampleBuffer<f32>),
+    I32(SampleBuffer<i32>),
+    I16(SampleBuffer<i16>),
+}
+
+impl Samples {
+    fn new(format: InternalFormat, frames: usize, spec: SignalSpec) -> Self {
+        let frames = frames as u64;
+        match format {
+            InternalFormat::F32 => Self::F32(SampleBuffer::new(frames, spec)),
+            InternalFormat::I32 => Self::I32(SampleBuffer::new(frames, spec)),
+            InternalFormat::I16 => Self::I16(SampleBuffer::new(frames, spec)),
+        }
+    }
+
+    fn format(&self) -> InternalFormat {
+        match self {
+            Self::F32(_) => InternalFormat::F32,
+            Self::I32(_) => InternalFormat::I32,
+            Self::I16(_) => InternalFormat::I16,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Self::F32(buf) => buf.capacity(),
+            Self::I32(buf) => buf.capacity(),
+            Self::I16(buf) => buf.capacity(),
+        }
+    }
+
+    fn copy(&mut self, decoded: AudioBufferRef) {
+        match self {
+            Self::F32(buf) => buf.copy_interleaved_ref(decoded),
+            Self::I32(buf) => buf.copy_interleaved_ref(decoded),
+            Self::I16(buf) => buf.copy_interleaved_ref(decoded),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::F32(buf) => buf.len(),
+            Self::I32(buf) => buf.len(),
+            Self::I16(buf) => buf.len(),
+        }
+    }
+
+    /// Append the samples from `from` on to `out` as floats in -1.0..1.0
+    fn extend_f32(&self, out: &mut Vec<f32>, from: usize) {
+        match self {
+            Self::F32(buf) => out.extend_from_slice(&buf.samples()[from..]),
+            Self::I32(buf) => out.extend(
+                buf.samples()[from..]
+                    .iter()
+                    .map(|&s| s as f32 / 2_147_483_648.0),
+            ),
+            Self::I16(buf) => {
+                out.extend(buf.samples()[from..].iter().map(|&s| s as f32 / 32_768.0))
+            }
+        }
+    }
+}
+
+/// Stretch of recently decoded audio the live bitrate is averaged over
+const BITRATE_WINDOW: Duration = Duration::from_secs(2);
+
+pub(crate) type SharedDecoder = Arc<Mutex<SymphoniaDecoder>>;
+
+/// The lossy codecs Symphonia can decode, as opposed to PCM and the lossless family
+fn is_lossy(codec: CodecType) -> bool {
+    [
+        CODEC_TYPE_MP1,
+        CODEC_TYPE_MP2,
+        CODEC_TYPE_MP3,
+        CODEC_TYPE_AAC,
+        CODEC_TYPE_VORBIS,
+        CODEC_TYPE_OPUS,
+        CODEC_TYPE_ADPCM_MS,
+        CODEC_TYPE_ADPCM_IMA_WAV,
+    ]
+    .contains(&codec)
+}
+
+impl SymphoniaDecoder {
+    pub(crate) fn open(path: &Path, retry: IoRetry) -> Result<Self> {
+        let probed = probe::open(path, retry)?;
+        Self::from_format(probed.format, &format!("{:?}", path))
+    }
+
+    /// Decode an already opened stream, e.g. an HTTP download. `name` identifies it in
+    /// errors.
+    pub(crate) fn open_stream(
+        source: Box<dyn MediaSource>,
+        hint: &Hint,
+        name: &str,
+    ) -> Result<Self> {
+        let probed = probe::open_source(source, hint)
+            .with_context(|| format!("Unsupported/invalid audio: {}", name))?;
+        Self::from_format(probed.format, name)
+    }
+
+    fn from_format(format: Box<dyn FormatReader>, name: &str) -> Result<Self> {
+        let track = probe::default_track(format.tracks())
+            .with_context(|| format!("No playable track in {}", name))?;
+        let params = &track.codec_params;
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .with_context(|| format!("Unsupported codec in {}", name))?;
+        let sample_rate = params
+            .sample_rate
+            .with_context(|| format!("Unknown sample rate in {}", name))?;
+        let lossy = is_lossy(params.codec);
+
+        Ok(Self {
+            track_id: track.id,
+            time_base: params.time_base,
+            channels: params.channels.map_or(2, |c| c.count() as u16),
+            sample_rate,
+            duration: probe::track_duration_ms(track).map(Duration::from_millis),
+            format,
+            decoder,
+            skip_frames: 0,
+            generation: 0,
+            sample_buf: None,
+            lossy,
+            bitrate: BitrateMeter::default(),
+        })
+    }
+
+    pub(crate) fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Length reported by the container, without decoding anything
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Bits per second of the packets decoded over the last couple of seconds, following a
+    /// VBR track as it goes; `None` for PCM and lossless codecs, or before the first packet
+    pub(crate) fn bitrate(&self) -> Option<u32> {
+        self.lossy.then(|| self.bitrate.average()).flatten()
+    }
+
+    /// Move to exactly `to`, invalidating sources handed out before. Returns false, leaving
+    /// the position alone, if `to` is past the end of the track.
+    pub(crate) fn seek(&mut self, to: Duration) -> Result<bool> {
+        if self.duration.is_some_and(|d| to >= d) {
+            return Ok(false);
+        }
+        let seeked = match self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(to.as_secs_f64()),
+                track_id: Some(self.track_id),
+            },
+        ) {
+            Ok(seeked) => seeked,
+            Err(Error::SeekError(SeekErrorKind::OutOfRange)) => return Ok(false),
+            Err(e) => return Err(e).context("Seek failed"),
+        };
+        self.decoder.reset();
+        // Demuxers land on a packet boundary at or before the target; decode up to it
+        let ts_gap = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        self.skip_frames = match self.time_base {
+            Some(tb) => {
+                let time = tb.calc_time(ts_gap);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+            }
+            None => ts_gap,
+        };
+        self.generation += 1;
+        self.bitrate.clear();
+        Ok(true)
+    }
+
+    /// Switch to the stream that replaced the current one midway through the file, e.g. the
+    /// next link of a chained Ogg file. Returns false if there is nothing playable to switch to.
+    fn follow_reset(&mut self) -> bool {
+        let Some(track) = probe::default_track(self.format.tracks()) else {
+            return false;
+        };
+        let params = &track.codec_params;
+        let Ok(decoder) = symphonia::default::get_codecs().make(params, &DecoderOptions::default())
+        else {
+            return false;
+        };
+        self.track_id = track.id;
+        self.time_base = params.time_base;
+        self.decoder = decoder;
+        self.lossy = is_lossy(params.codec);
+        self.bitrate.clear();
+        // The new stream's format is announced by its first decoded packet
+        self.sample_buf = None;
+        true
+    }
+
+    /// Decode the next packet of the track into `out` by way of `format`, replacing its
+    /// contents. Returns false at the end of the stream or on an unrecoverable error.
+    ///
+    /// A packet that decodes to a different channel count or sample rate than the last
+    /// updates `channels` and `sample_rate` to match it, including the first packet after
+    /// the stream is replaced by a new one.
+    fn decode_next(&mut self, out: &mut Vec<f32>, format: InternalFormat) -> bool {
+        let mut errors = 0;
+        let mut reset = false;
+        loop {
+            if std::mem::take(&mut reset) && !self.follow_reset() {
+                return false;
+            }
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::ResetRequired) if self.follow_reset() => continue,
+                Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return false,
+                // Damaged container data; the demuxer resyncs on the next call
+                Err(Error::DecodeError(_)) if errors < MAX_CONSECUTIVE_ERRORS => {
+                    errors += 1;
+                    continue;
+                }
+                Err(_) => return false,
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped rather than ending playback
+                Err(Error::DecodeError(_)) if errors < MAX_CONSECUTIVE_ERRORS => {
+                    errors += 1;
+                    continue;
+                }
+                // The packet is lost, but the stream carries on with a fresh decoder
+                Err(Error::ResetRequired) => {
+                    reset = true;
+                    continue;
+                }
+                Err(_) => return false,
+            };
+            let spec = *decoded.spec();
+            self.bitrate.push(
+                packet.data.len(),
+                decoded.frames() as f64 / spec.rate.max(1) as f64,
+            );
+            self.channels = spec.channels.count().max(1) as u16;
+            self.sample_rate = spec.rate;
+
+            if self.sample_buf.as_ref().is_some_and(|(buf, buf_spec)| {
+                *buf_spec != spec
+                    || buf.format() != format
+                    || buf.capacity() < decoded.capacity() * spec.channels.count()
+            }) {
+                self.sample_buf = None;
+            }
+            let (buf, _) = self
+                .sample_buf
+                .get_or_insert_with(|| (Samples::new(format, decoded.capacity(), spec), spec));
+            buf.copy(decoded);
+
+            let channels = self.channels as usize;
+            let frames = buf.len() / channels;
+            let skip = (self.skip_frames as usize).min(frames);
+            self.skip_frames -= skip as u64;
+            out.clear();
+            buf.extend_f32(out, skip * channels);
+            if !out.is_empty() {
+                return true;
+            }
+        }
+    }
+}
+
+/// Running average of packet sizes over the last `BITRATE_WINDOW` of audio
+#[derive(Default)]
+struct BitrateMeter {
+    /// Size in bytes and length in seconds of each packet in the window, oldest first
+    packets: VecDeque<(usize, f64)>,
+    bytes: usize,
+    secs: f64,
+}
+
+impl BitrateMeter {
+    fn push(&mut self, bytes: usize, secs: f64) {
+        self.packets.push_back((bytes, secs));
+        self.bytes += bytes;
+        self.secs += secs;
+        while let Some(&(bytes, secs)) = self.packets.front() {
+            if self.secs - secs < BITRATE_WINDOW.as_secs_f64() {
+                break;
+            }
+            self.packets.pop_front();
+            self.bytes -= bytes;
+            self.secs -= secs;
+        }
+    }
+
+    fn average(&self) -> Option<u32> {
+        (self.secs > 0.0).then(|| (self.bytes as f64 * 8.0 / self.secs).round() as u32)
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Plays a `SymphoniaDecoder` from wherever it was last seeked to, decoding one packet at a
+/// time so memory use doesn't grow with the length of the track.
+///
+/// The next packet is decoded as soon as the current one runs out, so `channels` and
+/// `sample_rate` already describe the upcoming samples at every frame boundary that
+/// `current_frame_len` reports. That lets rodio and the `Pipeline` follow format changes.
+pub(crate) struct SymphoniaSource {
+    shared: SharedDecoder,
+    controls: Arc<Controls>,
+    /// Value of `Controls::dsp_version` that `format` was chosen at
+    dsp_version: u64,
+    format: InternalFormat,
+    generation: u64,
+    buffer: Vec<f32>,
+    pos: usize,
+    channels: u16,
+    sample_rate: u32,
+    duration: Option<Duration>,
+}
+
+impl SymphoniaSource {
+    pub(crate) fn new(shared: SharedDecoder, controls: Arc<Controls>) -> Self {
+        let (generation, channels, sample_rate, duration) = {
+            let decoder = shared.lock();
+            (
+                decoder.generation,
+                decoder.channels,
+                decoder.sample_rate,
+                decoder.duration,
+            )
+        };
+        let mut source = Self {
+            shared,
+            dsp_version: controls.dsp_version(),
+            format: controls.dsp().decode_format(),
+            controls,
+            generation,
+            buffer: Vec::new(),
+            pos: 0,
+            channels,
+            sample_rate,
+            duration,
+        };
+        source.fetch();
+        source
+    }
+
+    /// Decode the next packet; leaves the buffer empty once the stream is over
+    fn fetch(&mut self) {
+        // Processing switched on or off since the last packet
+        let version = self.controls.dsp_version();
+        if version != self.dsp_version {
+            self.format = self.controls.dsp().decode_format();
+            self.dsp_version = version;
+        }
+        let mut decoder = self.shared.lock();
+        self.pos = 0;
+        // Replaced by a seek
+        if decoder.generation != self.generation
+            || !decoder.decode_next(&mut self.buffer, self.format)
+        {
+            self.buffer.clear();
+            return;
+        }
+        self.channels = decoder.channels;
+        self.sample_rate = decoder.sample_rate;
+    }
+}
+
+impl Iterator for SymphoniaSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = *self.buffer.get(self.pos)?;
+        self.pos += 1;
+        if self.pos == self.buffer.len() {
+            self.fetch();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.buffer.len() - self.pos)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use symphonia::core::audio::Channels;
+    use symphonia::core::codecs::{CodecParameters, CODEC_TYPE_PCM_F32LE};
+    use symphonia::core::formats::{Cue, FormatOptions, Packet, SeekedTo, Track};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::{Metadata, MetadataLog};
+
+    enum Step {
+        Packet(Packet),
+        /// A new logical stream starts, replacing the tracks
+        Chain(Vec<Track>),
+    }
+
+    /// Stands in for a chained Ogg file: plays one stream, then reports `ResetRequired` and
+    /// carries on with another
+    struct Chained {
+        tracks: Vec<Track>,
+        steps: VecDeque<Step>,
+        metadata: MetadataLog,
+    }
+
+    impl FormatReader for Chained {
+        fn try_new(
+            _: MediaSourceStream,
+            _: &FormatOptions,
+        ) -> symphonia::core::errors::Result<Self> {
+            unreachable!("not used by the tests")
+        }
+
+        fn cues(&self) -> &[Cue] {
+            &[]
+        }
+
+        fn metadata(&mut self) -> Metadata<'_> {
+            self.metadata.metadata()
+        }
+
+        fn seek(&mut self, _: SeekMode, _: SeekTo) -> symphonia::core::errors::Result<SeekedTo> {
+            Err(Error::SeekError(SeekErrorKind::Unseekable))
+        }
+
+        fn tracks(&self) -> &[Track] {
+            &self.tracks
+        }
+
+        fn next_packet(&mut self) -> symphonia::core::errors::Result<Packet> {
+            match self.steps.pop_front() {
+                Some(Step::Packet(packet)) => Ok(packet),
+                Some(Step::Chain(tracks)) => {
+                    self.tracks = tracks;
+                    Err(Error::ResetRequired)
+                }
+                None => Err(Error::IoError(ErrorKind::UnexpectedEof.into())),
+            }
+        }
+
+        fn into_inner(self: Box<Self>) -> MediaSourceStream {
+            unreachable!("not used by the tests")
+        }
+    }
+
+    fn track(id: u32, rate: u32, channels: Channels) -> Track {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_PCM_F32LE)
+            .with_sample_rate(rate)
+            .with_channels(channels)
+            .with_max_frames_per_packet(64);
+        Track::new(id, params)
+    }
+
+    fn packet(track_id: u32, samples: &[f32]) -> Step {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        Step::Packet(Packet::new_from_slice(track_id, 0, 0, &data))
+    }
+
+    #[test]
+    fn follows_a_chained_stream_with_a_new_format() {
+        let stereo = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+        let format = Chained {
+            tracks: vec![track(1, 44_100, Channels::FRONT_LEFT)],
+            steps: VecDeque::from([
+                packet(1, &[0.1; 4]),
+                packet(1, &[0.2; 4]),
+                Step::Chain(vec![track(7, 22_050, stereo)]),
+                packet(7, &[0.3; 6]),
+            ]),
+            metadata: MetadataLog::default(),
+        };
+        let decoder = SymphoniaDecoder::from_format(Box::new(format), "chained").unwrap();
+        let mut source = SymphoniaSource::new(Arc::new(Mutex::new(decoder)), Arc::default());
+
+        let mut first = Vec::new();
+        for _ in 0..8 {
+            assert_eq!((source.channels(), source.sample_rate()), (1, 44_100));
+            first.push(source.next().unwrap());
+        }
+        assert_eq!(first, [0.1, 0.1, 0.1, 0.1, 0.2, 0.2, 0.2, 0.2]);
+
+        // The second link is announced at the frame boundary and played to its end
+        assert_eq!((source.channels(), source.sample_rate()), (2, 22_050));
+        let rest: Vec<f32> = source.by_ref().collect();
+        assert_eq!(rest, [0.3; 6]);
+    }
+
+    #[test]
+    fn ends_at_the_end_of_the_stream() {
+        let format = Chained {
+            tracks: vec![track(1, 8_000, Channels::FRONT_LEFT)],
+            steps: VecDeque::from([packet(1, &[0.5; 3])]),
+            metadata: MetadataLog::default(),
+        };
+        let decoder = SymphoniaDecoder::from_format(Box::new(format), "plain").unwrap();
+        let source = SymphoniaSource::new(Arc::new(Mutex::new(decoder)), Arc::default());
+        assert_eq!(source.count(), 3);
+    }
+
+    #[test]
+    fn averages_the_bitrate_over_the_last_two_seconds() {
+        let mut meter = BitrateMeter::default();
+        assert_eq!(meter.average(), None);
+        // A second of 64 kbps in 0.1 s packets, then two of 256 kbps
+        for _ in 0..10 {
+            meter.push(800, 0.1);
+        }
+        assert_eq!(meter.average(), Some(64_000));
+        for _ in 0..10 {
+            meter.push(3_200, 0.1);
+        }
+        assert_eq!(meter.average(), Some(160_000));
+        for _ in 0..10 {
+            meter.push(3_200, 0.1);
+        }
+        assert_eq!(meter.average(), Some(256_000));
+    }
+}
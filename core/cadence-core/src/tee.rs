@@ -0,0 +1,245 @@
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Frames gathered before a block is handed to the writer thread; about 20 ms at 48 kHz
+const BLOCK_FRAMES: usize = 1024;
+/// Blocks that can wait for the writer before new ones are dropped; about 1.4 s at 48 kHz
+const QUEUE_BLOCKS: usize = 64;
+
+/// Records what's sent to the output into a 32-bit float WAV file.
+///
+/// The audio thread only gathers frames into blocks and hands them over a bounded channel;
+/// a writer thread owns the file, so a slow disk can't stall playback. If the writer falls
+/// that far behind, blocks are dropped and `finish` reports how many frames went missing.
+/// Dropped without `finish`, the writer still finalizes the file, but nobody waits for it.
+pub(crate) struct Tee {
+    blocks: Option<SyncSender<Block>>,
+    block: Block,
+    dropped: Arc<AtomicU64>,
+    writer: Option<JoinHandle<Result<()>>>,
+}
+
+/// Interleaved frames in a single format
+struct Block {
+    channels: u16,
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl Tee {
+    /// Start recording to `path`. The file is created now, so a bad path fails here, and is
+    /// written once the first frames arrive.
+    pub(crate) fn start(path: PathBuf) -> Result<Self> {
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create tee output {:?}", path))?;
+        let (blocks, blocks_rx) = mpsc::sync_channel(QUEUE_BLOCKS);
+        let writer = thread::Builder::new()
+            .name("cadence-tee".to_string())
+            .spawn(move || write_blocks(file, &path, blocks_rx))
+            .context("Failed to start the tee writer")?;
+        Ok(Self {
+            blocks: Some(blocks),
+            block: Block {
+                channels: 0,
+                sample_rate: 0,
+                samples: Vec::new(),
+            },
+            dropped: Arc::new(AtomicU64::new(0)),
+            writer: Some(writer),
+        })
+    }
+
+    /// Stop recording: wait for the writer to finalize the file and report any error it hit
+    /// or frames that had to be dropped
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.flush();
+        // Ends the writer's loop
+        self.blocks = None;
+        let written = match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(anyhow::anyhow!("The tee writer panicked")),
+            None => Ok(()),
+        };
+        written.context("Failed to write tee output")?;
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            anyhow::bail!(
+                "The tee output couldn't keep up and is missing {} frames",
+                dropped
+            );
+        }
+        Ok(())
+    }
+
+    /// Append one frame; called from the audio thread and never blocks
+    pub(crate) fn write(&mut self, frame: &[f32], channels: u16, sample_rate: u32) {
+        if (channels, sample_rate) != (self.block.channels, self.block.sample_rate) {
+            self.flush();
+            self.block.channels = channels;
+            self.block.sample_rate = sample_rate;
+        }
+        self.block.samples.extend_from_slice(frame);
+        if self.block.samples.len() >= BLOCK_FRAMES * channels as usize {
+            self.flush();
+        }
+    }
+
+    /// Hand the frames gathered so far to the writer, e.g. at the end of a track
+    pub(crate) fn flush(&mut self) {
+        if self.block.samples.is_empty() {
+            return;
+        }
+        let block = Block {
+            channels: self.block.channels,
+            sample_rate: self.block.sample_rate,
+            samples: std::mem::replace(
+                &mut self.block.samples,
+                Vec::with_capacity(BLOCK_FRAMES * self.block.channels as usize),
+            ),
+        };
+        let frames = (block.samples.len() / block.channels.max(1) as usize) as u64;
+        let Some(blocks) = &self.blocks else {
+            return;
+        };
+        match blocks.try_send(block) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(frames, Ordering::Relaxed);
+            }
+            // The writer stopped on an error, which `finish` reports
+            Err(TrySendError::Disconnected(_)) => self.blocks = None,
+        }
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// The writer thread. A WAV file can't change format midway, so a block in a different
+/// channel count or sample rate finalizes the current file and continues in
+/// `<stem>-<n>.wav`.
+fn write_blocks(file: File, path: &Path, blocks: Receiver<Block>) -> Result<()> {
+    let mut file = Some(file);
+    let mut writer: Option<WavWriter<BufWriter<File>>> = None;
+    let mut segments = 0;
+    for block in blocks {
+        let spec = WavSpec {
+            channels: block.channels,
+            sample_rate: block.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let writer = match &mut writer {
+            Some(current) if current.spec() == spec => current,
+            _ => {
+                if let Some(done) = writer.take() {
+                    done.finalize()?;
+                }
+                let target = segment_path(path, segments);
+                let out = match file.take() {
+                    Some(file) => file,
+                    None => File::create(&target)
+                        .with_context(|| format!("Failed to create {:?}", target))?,
+                };
+                segments += 1;
+                writer.insert(WavWriter::new(BufWriter::new(out), spec)?)
+            }
+        };
+        for sample in block.samples {
+            writer.write_sample(sample)?;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.finalize()?;
+    }
+    // Nothing played while recording; don't leave an empty file that isn't a WAV
+    if file.is_some() {
+        drop(file);
+        fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// The first segment uses the requested path, later ones get a numeric suffix
+fn segment_path(path: &Path, segment: u32) -> PathBuf {
+    if segment == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}-{}.wav", stem, segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempFile;
+
+    fn read(path: &Path) -> (WavSpec, Vec<f32>) {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        let samples = reader.samples::<f32>().map(Result::unwrap).collect();
+        (reader.spec(), samples)
+    }
+
+    #[test]
+    fn records_frames_in_order() {
+        let out = TempFile::new("wav");
+        let mut tee = Tee::start(out.path().to_path_buf()).unwrap();
+        // More than a block, so some frames cross to the writer early and some at `finish`
+        let samples: Vec<f32> = (0..3_000).map(|i| i as f32 / 3_000.0).collect();
+        for frame in samples.chunks(2) {
+            tee.write(frame, 2, 48_000);
+        }
+        tee.finish().unwrap();
+
+        let (spec, recorded) = read(out.path());
+        assert_eq!((spec.channels, spec.sample_rate), (2, 48_000));
+        assert_eq!(recorded, samples);
+    }
+
+    #[test]
+    fn continues_in_a_new_file_when_the_format_changes() {
+        let out = TempFile::new("wav");
+        let mut tee = Tee::start(out.path().to_path_buf()).unwrap();
+        tee.write(&[0.1, 0.2], 2, 44_100);
+        tee.write(&[0.3], 1, 22_050);
+        tee.finish().unwrap();
+
+        let second = segment_path(out.path(), 1);
+        let (first_spec, first) = read(out.path());
+        let (second_spec, rest) = read(&second);
+        std::fs::remove_file(&second).unwrap();
+        assert_eq!((first_spec.channels, first_spec.sample_rate), (2, 44_100));
+        assert_eq!(first, [0.1, 0.2]);
+        assert_eq!((second_spec.channels, second_spec.sample_rate), (1, 22_050));
+        assert_eq!(rest, [0.3]);
+    }
+
+    #[test]
+    fn leaves_no_file_when_nothing_played() {
+        let out = TempFile::new("wav");
+        Tee::start(out.path().to_path_buf())
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert!(!out.path().exists());
+    }
+
+    #[test]
+    fn fails_to_start_on_a_bad_path() {
+        let dir = TempFile::new("d");
+        assert!(Tee::start(dir.path().join("out.wav")).is_err());
+    }
+}
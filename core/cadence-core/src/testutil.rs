@@ -0,0 +1,210 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A file in the temp directory, removed when dropped
+pub(crate) struct TempFile(PathBuf);
+
+impl TempFile {
+    /// A path no other test uses, with the given extension; nothing is created yet
+    pub(crate) fn new(extension: &str) -> Self {
+        Self(unique_path(extension))
+    }
+
+    /// Interleaved `samples` written as a 32-bit float WAV
+    pub(crate) fn wav(samples: &[f32], channels: u16, sample_rate: u32) -> Self {
+        let file = Self::new("wav");
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&file.0, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        file
+    }
+
+    /// Interleaved `samples` written as a 16-bit integer WAV
+    pub(crate) fn wav_i16(samples: &[i16], channels: u16, sample_rate: u32) -> Self {
+        let file = Self::new("wav");
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&file.0, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        file
+    }
+
+    /// A WAV of `samples` behind an ID3v2.4 tag holding `frames` of text, as (frame id,
+    /// text) pairs such as ("TIT2", "Title"), and `cover` as a PNG front cover if given
+    pub(crate) fn tagged_wav(
+        samples: &[f32],
+        channels: u16,
+        sample_rate: u32,
+        frames: &[(&str, &str)],
+        cover: Option<&[u8]>,
+    ) -> Self {
+        // Sizes in ID3v2.4 are "syncsafe": seven bits per byte
+        let syncsafe = |len: usize| (0..4).rev().map(move |i| ((len >> (7 * i)) & 0x7f) as u8);
+        let frame = |id: &str, body: Vec<u8>| {
+            let mut frame = id.as_bytes().to_vec();
+            frame.extend(syncsafe(body.len()));
+            frame.extend([0, 0]);
+            frame.extend(body);
+            frame
+        };
+
+        let mut body = Vec::new();
+        for (id, text) in frames {
+            // Latin-1 text
+            body.extend(frame(id, [&[0], text.as_bytes()].concat()));
+        }
+        if let Some(cover) = cover {
+            // Latin-1, MIME type, front cover, empty description, then the image
+            body.extend(frame("APIC", [b"\0image/png\0\x03\0", cover].concat()));
+        }
+        let mut tagged = b"ID3\x04\0\0".to_vec();
+        tagged.extend(syncsafe(body.len()));
+        tagged.extend(body);
+
+        let file = Self::wav(samples, channels, sample_rate);
+        tagged.extend(std::fs::read(file.path()).unwrap());
+        std::fs::write(file.path(), tagged).unwrap();
+        file
+    }
+
+    /// A mono 44.1 kHz MP3 of silence, one frame of 1152 samples per entry of `kbps`, each
+    /// frame at that bitrate; varying them makes a VBR file
+    pub(crate) fn silent_mp3(kbps: &[u32]) -> Self {
+        const BITRATES: [u32; 14] = [
+            32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ];
+        let mut data = Vec::new();
+        for &rate in kbps {
+            let index = BITRATES.iter().position(|&b| b == rate).unwrap() as u8 + 1;
+            // MPEG-1 Layer III without CRC, 44.1 kHz, no padding, mono
+            let mut frame = vec![0xff, 0xfb, index << 4, 0xc0];
+            // Zeroed side info and main data decode to silence
+            frame.resize((144_000 * rate / 44_100) as usize, 0);
+            data.extend(frame);
+        }
+        let file = Self::new("mp3");
+        std::fs::write(&file.0, data).unwrap();
+        file
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// An empty directory in the temp directory, removed with its contents when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        let path = unique_path("d");
+        std::fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A path in the temp directory that no other test uses, with the given extension
+fn unique_path(extension: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "cadence-test-{}-{}.{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+        extension
+    );
+    std::env::temp_dir().join(name)
+}
+
+/// A sine of `freq` Hz at half scale, `frames` long and the same in every channel
+pub(crate) fn sine(freq: f32, frames: usize, channels: u16, sample_rate: u32) -> Vec<f32> {
+    (0..frames)
+        .flat_map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let sample = 0.5 * (std::f32::consts::TAU * freq * t).sin();
+            std::iter::repeat_n(sample, channels as usize)
+        })
+        .collect()
+}
+
+/// Serve `body` from `/track.wav` on a local port, with `/moved` redirecting to it and
+/// `/chunked` sending it in chunks; everything else is a 404. Returns the base URL.
+pub(crate) fn serve(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let head = |status: &str, headers: &str| {
+                format!(
+                    "HTTP/1.1 {}\r\nConnection: close\r\n{}\r\n",
+                    status, headers
+                )
+            };
+            let response = match path {
+                "/track.wav" => {
+                    let headers = format!(
+                        "Content-Type: audio/wav\r\nContent-Length: {}\r\n",
+                        body.len()
+                    );
+                    [head("200 OK", &headers).into_bytes(), body.clone()].concat()
+                }
+                "/moved" => {
+                    head("302 Found", "Location: /track.wav\r\nContent-Length: 0\r\n").into_bytes()
+                }
+                "/chunked" => {
+                    let mut response = head(
+                        "200 OK",
+                        "Content-Type: audio/x-wav\r\nTransfer-Encoding: chunked\r\n",
+                    )
+                    .into_bytes();
+                    for chunk in body.chunks(1000) {
+                        response.extend(format!("{:x}\r\n", chunk.len()).bytes());
+                        response.extend(chunk);
+                        response.extend(b"\r\n");
+                    }
+                    response.extend(b"0\r\n\r\n");
+                    response
+                }
+                _ => head("404 Not Found", "Content-Length: 0\r\n").into_bytes(),
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+    base
+}
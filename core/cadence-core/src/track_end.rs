@@ -0,0 +1,248 @@
+use crate::lineup::NearEnd;
+use crate::pipeline::Controls;
+use crate::TrackInfo;
+use parking_lot::Mutex;
+use rodio::Source;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Delivers `TrackInfo` to subscribers when a track plays out to its end
+#[derive(Default)]
+pub(crate) struct TrackEnd {
+    subscribers: Mutex<Vec<Sender<TrackInfo>>>,
+    /// Bumped whenever the playing source is replaced or stopped, so a source that was
+    /// already on its way out can't report an end
+    generation: AtomicU64,
+}
+
+impl TrackEnd {
+    pub(crate) fn subscribe(&self) -> Receiver<TrackInfo> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().push(tx);
+        rx
+    }
+
+    /// Invalidate every source handed out so far
+    pub(crate) fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn notify(&self, generation: u64, info: TrackInfo) {
+        if self.generation.load(Ordering::Acquire) != generation {
+            return;
+        }
+        // Drop subscribers whose receiver has gone away
+        self.subscribers
+            .lock()
+            .retain(|tx| tx.send(info.clone()).is_ok());
+    }
+}
+
+/// Links a source queued behind the playing one to whoever queued it; see
+/// `NotifyOnEnd::queued`
+#[derive(Clone)]
+pub(crate) struct Handover {
+    /// Set to withdraw the source before it starts; it then ends without playing anything.
+    /// Shared by everything queued for the same track.
+    withdrawn: Arc<AtomicBool>,
+    /// When the source took over from the one ahead of it
+    started: Arc<OnceLock<Instant>>,
+}
+
+impl Handover {
+    pub(crate) fn new(withdrawn: Arc<AtomicBool>) -> Self {
+        Self {
+            withdrawn,
+            started: Arc::default(),
+        }
+    }
+
+    pub(crate) fn started(&self) -> Option<Instant> {
+        self.started.get().copied()
+    }
+
+    /// Start the source unless it was withdrawn first
+    fn take_over(&self) -> bool {
+        if self.withdrawn.load(Ordering::Acquire) {
+            return false;
+        }
+        let _ = self.started.set(Instant::now());
+        true
+    }
+}
+
+/// Passes `inner` through and reports `info` as ended once it runs dry. A source dropped
+/// before then (by `stop`, a seek or the next track) reports nothing.
+pub(crate) struct NotifyOnEnd<S> {
+    inner: S,
+    controls: Arc<Controls>,
+    generation: u64,
+    info: Option<TrackInfo>,
+    /// Set until a queued source starts playing
+    handover: Option<Handover>,
+    /// Samples left before `near_end` is signalled, or `None` to signal it at the end
+    until_near_end: Option<u64>,
+    near_end: Option<NearEnd>,
+}
+
+impl<S> NotifyOnEnd<S> {
+    /// Wrap the new source for the current track, superseding any earlier one
+    pub(crate) fn new(inner: S, controls: Arc<Controls>, info: TrackInfo) -> Self {
+        let generation = controls.track_end.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        Self {
+            inner,
+            controls,
+            generation,
+            info: Some(info),
+            handover: None,
+            until_near_end: None,
+            near_end: None,
+        }
+    }
+
+    /// Wrap a source appended behind the one playing now. It leaves the current source's end
+    /// to be reported, takes over once it starts playing, and plays nothing if `handover`
+    /// is withdrawn before then.
+    pub(crate) fn queued(
+        inner: S,
+        controls: Arc<Controls>,
+        info: TrackInfo,
+        handover: Handover,
+    ) -> Self {
+        Self {
+            inner,
+            controls,
+            generation: 0,
+            info: Some(info),
+            handover: Some(handover),
+            until_near_end: None,
+            near_end: None,
+        }
+    }
+}
+
+impl<S> NotifyOnEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Signal `near_end` once `lead` of the source's `remaining` play time is left, or when
+    /// it ends if `remaining` isn't known
+    pub(crate) fn near_end(
+        mut self,
+        remaining: Option<Duration>,
+        lead: Duration,
+        near_end: NearEnd,
+    ) -> Self {
+        let samples_per_sec = self.inner.sample_rate() as f64 * self.inner.channels() as f64;
+        self.until_near_end =
+            remaining.map(|r| (r.saturating_sub(lead).as_secs_f64() * samples_per_sec) as u64);
+        self.near_end = Some(near_end);
+        self
+    }
+}
+
+impl<S> Iterator for NotifyOnEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(handover) = self.handover.take() {
+            if !handover.take_over() {
+                self.info = None;
+                self.near_end = None;
+                self.handover = Some(handover);
+                return None;
+            }
+            self.generation = self
+                .controls
+                .track_end
+                .generation
+                .fetch_add(1, Ordering::AcqRel)
+                + 1;
+        }
+        match &mut self.until_near_end {
+            Some(0) | None => {}
+            Some(left) => *left -= 1,
+        }
+        let sample = self.inner.next();
+        if sample.is_none() || self.until_near_end == Some(0) {
+            if let Some(near_end) = self.near_end.take() {
+                near_end.signal();
+            }
+        }
+        if sample.is_none() {
+            if let Some(info) = self.info.take() {
+                self.controls.track_end.notify(self.generation, info);
+            }
+        }
+        sample
+    }
+}
+
+impl<S> Source for NotifyOnEnd<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use std::path::PathBuf;
+
+    fn source(controls: &Arc<Controls>, name: &str) -> NotifyOnEnd<SamplesBuffer<f32>> {
+        let info = TrackInfo::untagged(PathBuf::from(name), Some(1));
+        NotifyOnEnd::new(
+            SamplesBuffer::new(1, 8_000, vec![0.0; 8]),
+            controls.clone(),
+            info,
+        )
+    }
+
+    #[test]
+    fn reports_the_end_once() {
+        let controls = Arc::new(Controls::default());
+        let ended = controls.track_end.subscribe();
+        let mut playing = source(&controls, "a.wav");
+        assert_eq!(playing.by_ref().count(), 8);
+        // Asked again after running dry, as a mixer may do
+        assert_eq!(playing.next(), None);
+
+        assert_eq!(ended.try_recv().unwrap().path, PathBuf::from("a.wav"));
+        assert!(ended.try_recv().is_err());
+    }
+
+    #[test]
+    fn says_nothing_for_a_replaced_source() {
+        let controls = Arc::new(Controls::default());
+        let ended = controls.track_end.subscribe();
+        let replaced = source(&controls, "a.wav");
+        let playing = source(&controls, "b.wav");
+        assert_eq!(replaced.count(), 8);
+        assert!(ended.try_recv().is_err());
+
+        controls.track_end.cancel();
+        assert_eq!(playing.count(), 8);
+        assert!(ended.try_recv().is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use rodio::Source;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// Input frames either side of an output frame that the interpolation filter reaches
+const HALF_TAPS: usize = 16;
+
+/// Raises a source's sample rate by a whole factor with a windowed-sinc interpolation
+/// filter, which passes everything below the source's Nyquist frequency and leaves its
+/// samples where they were. Each output sample costs `2 * HALF_TAPS` multiply-adds.
+///
+/// Assumes the format doesn't change partway through; factor 1 passes the source through.
+pub(crate) struct Upsample<S> {
+    inner: S,
+    channels: usize,
+    sample_rate: u32,
+    factor: usize,
+    /// Filter taps for each output phase, over the input frames in `window`
+    kernels: Vec<Vec<f32>>,
+    /// `2 * HALF_TAPS` input frames, interleaved, centred on the one being interpolated
+    /// from; zeros before the start and after the end
+    window: VecDeque<f32>,
+    /// Input frames from `inner` at or after the centre, still to interpolate from
+    left: usize,
+    /// Output phase of the next sample, and its channel
+    phase: usize,
+    channel: usize,
+}
+
+impl<S> Upsample<S>
+where
+    S: Source<Item = f32>,
+{
+    pub(crate) fn new(inner: S, factor: u8) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let sample_rate = inner.sample_rate();
+        let factor = factor.max(1) as usize;
+        let kernels = (0..factor)
+            .map(|phase| {
+                // Input frame `j` of the window sits `j - (HALF_TAPS - 1)` frames from the
+                // centre; the output sample sits `phase / factor` frames past it
+                (0..2 * HALF_TAPS)
+                    .map(|j| {
+                        let t = phase as f64 / factor as f64 - (j as f64 - (HALF_TAPS - 1) as f64);
+                        (sinc(t) * blackman(t / HALF_TAPS as f64)) as f32
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut upsample = Self {
+            inner,
+            channels,
+            sample_rate,
+            factor,
+            kernels,
+            window: VecDeque::with_capacity(2 * HALF_TAPS * channels),
+            left: 0,
+            phase: 0,
+            channel: 0,
+        };
+        if factor > 1 {
+            upsample.window.resize((HALF_TAPS - 1) * channels, 0.0);
+            for _ in 0..=HALF_TAPS {
+                upsample.push_frame();
+            }
+        }
+        upsample
+    }
+
+    /// Move the next input frame, or silence once the source has ended, into the window
+    fn push_frame(&mut self) {
+        if self.window.len() == 2 * HALF_TAPS * self.channels {
+            self.window.drain(..self.channels);
+        }
+        let mut real = false;
+        for _ in 0..self.channels {
+            let sample = self.inner.next();
+            real |= sample.is_some();
+            self.window.push_back(sample.unwrap_or(0.0));
+        }
+        if real {
+            self.left += 1;
+        }
+    }
+}
+
+fn sinc(t: f64) -> f64 {
+    if t == 0.0 {
+        1.0
+    } else {
+        (PI * t).sin() / (PI * t)
+    }
+}
+
+/// Blackman window over -1..=1
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let x = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos()
+}
+
+impl<S> Iterator for Upsample<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.factor == 1 {
+            return self.inner.next();
+        }
+        if self.left == 0 {
+            return None;
+        }
+        let kernel = &self.kernels[self.phase];
+        let sample = kernel
+            .iter()
+            .enumerate()
+            .map(|(j, tap)| tap * self.window[j * self.channels + self.channel])
+            .sum();
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.phase += 1;
+            if self.phase == self.factor {
+                self.phase = 0;
+                self.left -= 1;
+                self.push_frame();
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for Upsample<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate * self.factor as u32
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::sine;
+    use rodio::buffer::SamplesBuffer;
+
+    #[test]
+    fn doubles_the_rate_and_keeps_the_samples() {
+        let input = sine(1_000.0, 4_410, 2, 44_100);
+        let source = Upsample::new(SamplesBuffer::new(2, 44_100, input.clone()), 2);
+        assert_eq!((source.channels(), source.sample_rate()), (2, 88_200));
+        let output: Vec<f32> = source.collect();
+        assert_eq!(output.len(), input.len() * 2);
+        for (frame, original) in output.chunks(4).zip(input.chunks(2)) {
+            assert!((frame[0] - original[0]).abs() < 1e-6);
+            assert!((frame[1] - original[1]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn fills_in_between_along_the_wave() {
+        // Away from the ends, the new samples land on the 1 kHz sine at four times the rate
+        let input = sine(1_000.0, 4_410, 1, 44_100);
+        let expected = sine(1_000.0, 4 * 4_410, 1, 4 * 44_100);
+        let output: Vec<f32> = Upsample::new(SamplesBuffer::new(1, 44_100, input), 4).collect();
+        assert_eq!(output.len(), expected.len());
+        let middle = 400..expected.len() - 400;
+        let worst = output[middle.clone()]
+            .iter()
+            .zip(&expected[middle])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 1e-3, "{}", worst);
+    }
+
+    #[test]
+    fn passes_through_at_factor_one() {
+        let input = sine(440.0, 100, 1, 8_000);
+        let source = Upsample::new(SamplesBuffer::new(1, 8_000, input.clone()), 1);
+        assert_eq!(source.sample_rate(), 8_000);
+        assert_eq!(source.collect::<Vec<_>>(), input);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+/// Extensions of the files a watched folder adds to the queue, in the formats Symphonia is
+/// built with here
+const AUDIO_EXTENSIONS: [&str; 8] = ["wav", "mp3", "flac", "ogg", "oga", "m4a", "mp4", "aac"];
+
+/// A file appearing in or leaving a folder passed to `Player::watch_folder`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FolderChange {
+    Added(PathBuf),
+    Removed(PathBuf),
+}
+
+/// Keeps a `Player::watch_folder` watch running; dropping it stops watching
+pub struct WatchHandle {
+    _watcher: RecommendedWatcher,
+}
+
+impl std::fmt::Debug for WatchHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchHandle").finish_non_exhaustive()
+    }
+}
+
+/// Send the audio files added to and removed from `dir` to `changes`, from the watcher's
+/// own thread, until the handle is dropped
+pub(crate) fn watch(dir: &Path, changes: Sender<FolderChange>) -> Result<WatchHandle> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            for change in folder_changes(&event) {
+                let _ = changes.send(change);
+            }
+        }
+    })
+    .context("Failed to start watching for files")?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {:?}", dir))?;
+    Ok(WatchHandle { _watcher: watcher })
+}
+
+/// The audio files `event` adds or removes
+fn folder_changes(event: &notify::Event) -> Vec<FolderChange> {
+    let audio = event.paths.iter().filter(|p| is_audio(p)).cloned();
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            audio.map(FolderChange::Added).collect()
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            audio.map(FolderChange::Removed).collect()
+        }
+        // Renamed within the folder, from the first path to the second
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match &event.paths[..] {
+            [from, to] => [
+                is_audio(from).then(|| FolderChange::Removed(from.clone())),
+                is_audio(to).then(|| FolderChange::Added(to.clone())),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, RemoveKind};
+    use notify::Event;
+
+    #[test]
+    fn picks_out_audio_files_coming_and_going() {
+        let created = Event::new(EventKind::Create(CreateKind::File))
+            .add_path(PathBuf::from("/in/a.FLAC"))
+            .add_path(PathBuf::from("/in/notes.txt"));
+        assert_eq!(
+            folder_changes(&created),
+            [FolderChange::Added(PathBuf::from("/in/a.FLAC"))]
+        );
+
+        let removed =
+            Event::new(EventKind::Remove(RemoveKind::File)).add_path(PathBuf::from("/in/b.mp3"));
+        assert_eq!(
+            folder_changes(&removed),
+            [FolderChange::Removed(PathBuf::from("/in/b.mp3"))]
+        );
+
+        let renamed = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(PathBuf::from("/in/c.part"))
+            .add_path(PathBuf::from("/in/c.wav"));
+        assert_eq!(
+            folder_changes(&renamed),
+            [FolderChange::Added(PathBuf::from("/in/c.wav"))]
+        );
+    }
+}
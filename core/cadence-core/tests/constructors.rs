@@ -0,0 +1,12 @@
+//! The constructors `tools/cadence-cli` and other frontends call; renaming one breaks this
+//! build instead of theirs.
+
+use anyhow::Result;
+use cadence_core::Player;
+
+#[test]
+fn constructors_keep_their_names() {
+    let _: fn() -> Result<Player> = Player::new;
+    let _: fn(&str) -> Result<Player> = Player::new_with_device;
+    let _: fn() -> Result<Player> = Player::new_headless;
+}
@@ -18,11 +18,51 @@ enum Command {
     Pause,
     Resume,
     Stop,
+    Status,
+    Report,
     Advance { seconds: i64 },
+    Seek(SeekTarget),
     Quit,
     Help,
 }
 
+/// Where `seek` should go
+#[derive(Debug, PartialEq)]
+enum SeekTarget {
+    Millis(u64),
+    /// Fraction of the track, 0.0 to 1.0
+    Fraction(f64),
+}
+
+impl FromStr for SeekTarget {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = input.strip_suffix('%') {
+            return match percent.parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(SeekTarget::Fraction(p / 100.0)),
+                Ok(_) => Err(format!("Percentage must be 0-100: {}", input)),
+                Err(_) => Err(format!("Invalid percentage: {}", input)),
+            };
+        }
+        let seconds = match input.split_once(':') {
+            Some((minutes, seconds)) => match (minutes.parse::<u64>(), seconds.parse::<u64>()) {
+                (Ok(m), Ok(s)) if s < 60 => m.checked_mul(60).and_then(|m| m.checked_add(s)),
+                _ => return Err(format!("Invalid time: {}", input)),
+            },
+            None => Some(
+                input
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid time: {}", input))?,
+            ),
+        };
+        seconds
+            .and_then(|s| s.checked_mul(1000))
+            .map(SeekTarget::Millis)
+            .ok_or_else(|| format!("Time out of range: {}", input))
+    }
+}
+
 impl FromStr for Command {
     type Err = String;
 
@@ -37,6 +77,8 @@ impl FromStr for Command {
             "pause" => Ok(Command::Pause),
             "resume" => Ok(Command::Resume),
             "stop" => Ok(Command::Stop),
+            "status" | "s" => Ok(Command::Status),
+            "report" => Ok(Command::Report),
             "+" | "-" => {
                 if parts.len() < 2 {
                     Err("Usage: +/- <seconds>. Enter a number after +/-".to_string())
@@ -48,6 +90,10 @@ impl FromStr for Command {
                     }
                 }
             }
+            "seek" => match parts.get(1) {
+                Some(target) => target.parse().map(Command::Seek),
+                None => Err("Usage: seek <seconds | mm:ss | percent%>".to_string()),
+            },
             "quit" | "q" | "exit" => Ok(Command::Quit),
             "help" | "h" => Ok(Command::Help),
             cmd => Err(format!(
@@ -70,8 +116,11 @@ fn main() -> Result<()> {
         info.duration_ms.unwrap_or(0)
     );
 
-    let commands_description =
-        "Commands: pause, resume, stop, +/- <seconds> (advance or rewind by <seconds>), quit";
+    let commands_description = concat!(
+        "Commands: pause, resume, stop, status, report, ",
+        "+/- <seconds> (advance or rewind by <seconds>), ",
+        "seek <seconds | mm:ss | percent%>, quit"
+    );
 
     println!("{}", commands_description);
 
@@ -103,8 +152,32 @@ fn main() -> Result<()> {
                 player.stop();
                 println!("Stopped");
             }
+            Ok(Command::Status) => {
+                let times = player.times();
+                match (times.duration_ms, times.remaining_ms) {
+                    (Some(duration), Some(remaining)) => println!(
+                        "{} / {} (-{})",
+                        format_ms(times.position_ms),
+                        format_ms(duration),
+                        format_ms(remaining)
+                    ),
+                    _ => println!("{}", format_ms(times.position_ms)),
+                }
+            }
+            Ok(Command::Report) => {
+                println!("{}", player.format_report());
+            }
             Ok(Command::Advance { seconds }) => {
-                if let Err(e) = player.advance_or_rewind(seconds * 1000) {
+                if let Err(e) = player.advance_or_rewind(seconds.saturating_mul(1000)) {
+                    println!("Error: {}", e);
+                }
+            }
+            Ok(Command::Seek(target)) => {
+                let result = match target {
+                    SeekTarget::Millis(ms) => player.seek(ms),
+                    SeekTarget::Fraction(fraction) => player.seek_fraction(fraction),
+                };
+                if let Err(e) = result {
                     println!("Error: {}", e);
                 }
             }
@@ -126,3 +199,43 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Format milliseconds as m:ss
+fn format_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seek(input: &str) -> Result<Command, String> {
+        format!("seek {}", input).parse()
+    }
+
+    #[test]
+    fn seek_percentages() {
+        assert_eq!(seek("50%"), Ok(Command::Seek(SeekTarget::Fraction(0.5))));
+        assert_eq!(seek("0%"), Ok(Command::Seek(SeekTarget::Fraction(0.0))));
+        assert_eq!(seek("100%"), Ok(Command::Seek(SeekTarget::Fraction(1.0))));
+        assert!(seek("150%").is_err());
+        assert!(seek("-5%").is_err());
+        assert!(seek("%").is_err());
+        assert!(seek("nan%").is_err());
+    }
+
+    #[test]
+    fn seek_times() {
+        assert_eq!(seek("90"), Ok(Command::Seek(SeekTarget::Millis(90_000))));
+        assert_eq!(seek("1:30"), Ok(Command::Seek(SeekTarget::Millis(90_000))));
+        assert!(seek("1:60").is_err());
+        assert!(seek("abc").is_err());
+    }
+
+    #[test]
+    fn seek_out_of_range_is_an_error() {
+        assert!(seek("99999999999999999:00").is_err());
+        assert!(seek("18446744073709551615").is_err());
+    }
+}